@@ -0,0 +1,80 @@
+use std::io::{IsTerminal, Write};
+
+use crate::errors::TogetherResult;
+
+/// Copies text to the system clipboard with the first clipboard tool available, or through the
+/// terminal with an OSC 52 escape sequence, which also works over ssh in terminals supporting it
+pub fn copy(text: &str) -> TogetherResult<()> {
+    for argv in tools() {
+        if pipe_to(argv, text).is_ok() {
+            return Ok(());
+        }
+    }
+    let mut stdout = std::io::stdout();
+    if !stdout.is_terminal() {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            "No clipboard tool found, install e.g. xclip or wl-clipboard",
+        )
+        .into());
+    }
+    write!(stdout, "\x1b]52;c;{}\x07", base64(text.as_bytes()))?;
+    stdout.flush()?;
+    Ok(())
+}
+
+/// Clipboard tools to try, in order, that read the text to copy from stdin
+fn tools() -> Vec<&'static [&'static str]> {
+    if cfg!(target_os = "macos") {
+        return vec![&["pbcopy"]];
+    }
+    if cfg!(windows) {
+        return vec![&["clip.exe"]];
+    }
+    let mut tools: Vec<&[&str]> = vec![];
+    if std::env::var_os("WAYLAND_DISPLAY").is_some() {
+        tools.push(&["wl-copy"]);
+    }
+    if std::env::var_os("DISPLAY").is_some() {
+        tools.push(&["xclip", "-selection", "clipboard"]);
+        tools.push(&["xsel", "--clipboard", "--input"]);
+    }
+    tools
+}
+
+fn pipe_to(argv: &[&str], text: &str) -> std::io::Result<()> {
+    let mut child = std::process::Command::new(argv[0])
+        .args(&argv[1..])
+        .stdin(std::process::Stdio::piped())
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .spawn()?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(text.as_bytes())?;
+    }
+    match child.wait()? {
+        status if status.success() => Ok(()),
+        status => Err(std::io::Error::other(format!(
+            "{} exited with {}",
+            argv[0], status
+        ))),
+    }
+}
+
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let n = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |n, (i, b)| n | (*b as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            match i <= chunk.len() {
+                true => encoded.push(ALPHABET[(n >> (18 - 6 * i) & 0x3f) as usize] as char),
+                false => encoded.push('='),
+            }
+        }
+    }
+    encoded
+}
@@ -4,31 +4,86 @@ use std::{
     path::{Path, PathBuf},
 };
 
-use clap::CommandFactory;
-
 use crate::{
-    errors::{TogetherError, TogetherResult},
-    log, log_err, t_println, terminal,
+    errors::{ConfigError, TogetherError, TogetherResult},
+    log, log_err,
+    output::OutputFilter,
+    recurring::{self, Recurrence},
+    supervisor::SupervisorOptions,
+    t_println, terminal,
+    timestamp::TimestampFormat,
 };
 
 #[derive(Debug, Clone)]
 pub struct StartTogetherOptions {
     pub config: TogetherConfigFile,
+    /// The configuration as loaded, before commands with a `matrix` or `dirs` were expanded
+    pub source_config: TogetherConfigFile,
+    /// Index in `source_config` of the command each command in `config` was expanded from
+    pub command_sources: Vec<usize>,
     pub working_directory: Option<String>,
     pub active_recipes: Option<Vec<String>>,
+    /// Recipes excluded on the command line
+    pub exclude_recipes: Option<Vec<String>>,
+    /// Commands picked with `--only`, run instead of prompting
+    pub only: Option<Vec<String>>,
     pub config_path: Option<std::path::PathBuf>,
+    pub output_filter: Option<OutputFilter>,
+    pub supervise: Option<SupervisorOptions>,
+    pub timestamp_format: TimestampFormat,
 }
 
-pub fn to_start_options(command_args: terminal::TogetherArgs) -> StartTogetherOptions {
+impl StartTogetherOptions {
+    /// Recipes excluded on the command line or in the configuration file
+    pub fn excluded_recipes(&self) -> Vec<&str> {
+        let config = self.config.start_options.exclude_recipes.iter().flatten();
+        let args = self.exclude_recipes.iter().flatten();
+        config.chain(args).map(|r| r.as_str()).collect()
+    }
+
+    /// The configuration as loaded, with `running` set to the given commands. A command
+    /// expanded from a `matrix` or `dirs` is saved as the command it came from, and commands
+    /// started outside the configuration, such as exit hooks, are left out.
+    pub fn session(&self, running: &[impl AsRef<str>]) -> TogetherConfigFile {
+        let commands = &self.config.start_options.commands;
+        let mut sources = vec![];
+        for command in running {
+            let source = commands
+                .iter()
+                .position(|c| c.matches(command.as_ref()))
+                .and_then(|position| self.command_sources.get(position));
+            if let Some(&source) = source.filter(|source| !sources.contains(*source)) {
+                sources.push(source);
+            }
+        }
+        TogetherConfigFile {
+            running: Some(sources.into_iter().map(Into::into).collect()),
+            ..self.source_config.clone()
+        }
+    }
+}
+
+pub fn to_start_options(
+    command_args: terminal::TogetherArgs,
+) -> TogetherResult<StartTogetherOptions> {
+    let output_filter = output_filter(&command_args)
+        .map_err(|e| ConfigError::new(format!("Invalid output filter: {}", e)))?;
+    let supervise = supervise_options(&command_args);
+
     #[derive(Default)]
     struct StartMeta {
         config_path: Option<std::path::PathBuf>,
         recipes: Option<Vec<String>>,
+        exclude_recipes: Option<Vec<String>>,
+        only: Option<Vec<String>>,
     }
     let (config, meta) = match command_args.command {
         Some(terminal::ArgsCommands::Run(run_opts)) => {
             let mut config_start_opts: commands::ConfigFileStartOptions = run_opts.into();
-            let meta = StartMeta::default();
+            let meta = StartMeta {
+                only: command_args.only,
+                ..StartMeta::default()
+            };
             config_start_opts.init_only = command_args.init_only;
             config_start_opts.no_init = command_args.no_init;
             config_start_opts.quiet_startup = command_args.quiet_startup;
@@ -37,36 +92,46 @@ pub fn to_start_options(command_args: terminal::TogetherArgs) -> StartTogetherOp
 
         Some(terminal::ArgsCommands::Rerun(_)) => {
             if command_args.no_config {
-                log_err!("To use rerun, you must have a configuration file");
-                std::process::exit(1);
+                return Err(
+                    ConfigError::new("To use rerun, you must have a configuration file").into(),
+                );
             }
-            let config = load();
-            let config = config
-                .map_err(|e| {
-                    log_err!("Failed to load configuration: {}", e);
-                    std::process::exit(1);
-                })
-                .unwrap();
-            let config_path: PathBuf = path_or_default();
+            let config_path = match session_path().filter(|path| path.exists()) {
+                Some(path) => path,
+                None => path_or_default()?,
+            };
+            log!("Loading configuration from: {:?}", config_path);
+            let config = load_from(&config_path)?;
             let meta = StartMeta {
                 config_path: Some(config_path),
+                only: command_args.only,
                 ..StartMeta::default()
             };
             (config, meta)
         }
 
+        Some(
+            terminal::ArgsCommands::Add(_)
+            | terminal::ArgsCommands::Remove(_)
+            | terminal::ArgsCommands::Order(_)
+            | terminal::ArgsCommands::Status(_)
+            | terminal::ArgsCommands::Report(_)
+            | terminal::ArgsCommands::Ctl(_)
+            | terminal::ArgsCommands::Cleanup(_),
+        ) => {
+            return Err(ConfigError::new(
+                "add, remove, order, status, report, ctl and cleanup don't start any commands",
+            )
+            .into());
+        }
+
         Some(terminal::ArgsCommands::Load(load)) => {
             if command_args.no_config {
-                log_err!("To use rerun, you must have a configuration file");
-                std::process::exit(1);
+                return Err(
+                    ConfigError::new("To use rerun, you must have a configuration file").into(),
+                );
             }
-            let config = load_from(&load.path);
-            let mut config = config
-                .map_err(|e| {
-                    log_err!("Failed to load configuration from '{}': {}", load.path, e);
-                    std::process::exit(1);
-                })
-                .unwrap();
+            let mut config = load_from(&load.path)?;
             let config_path: PathBuf = load.path.into();
             config.start_options.init_only = load.init_only;
             config.start_options.no_init = load.no_init;
@@ -74,76 +139,618 @@ pub fn to_start_options(command_args: terminal::TogetherArgs) -> StartTogetherOp
             let meta = StartMeta {
                 config_path: Some(config_path),
                 recipes: load.recipes,
+                exclude_recipes: load.exclude_recipes,
+                only: load.only,
             };
             (config, meta)
         }
 
-        None => (!command_args.no_config)
-            .then_some(())
-            .and_then(|()| path(None))
-            .and_then(|path| load_from(&path).ok().map(|config| (config, path)))
-            .map_or_else(
-                || {
-                    _ = terminal::TogetherArgs::command().print_long_help();
-                    std::process::exit(1);
-                },
-                |(mut config, config_path)| {
-                    let config_start_opts = &mut config.start_options;
-                    config_start_opts.init_only = command_args.init_only;
-                    config_start_opts.no_init = command_args.no_init;
-                    config_start_opts.quiet_startup = command_args.quiet_startup;
-                    let meta = StartMeta {
-                        config_path: Some(config_path.into()),
-                        recipes: command_args.recipes,
-                    };
-                    (config, meta)
-                },
-            ),
+        None => {
+            let config_path = (!command_args.no_config)
+                .then(|| path(None))
+                .flatten()
+                .ok_or(TogetherError::NoConfigFile)?;
+            // a configuration file that exists but fails to load is reported, not skipped
+            let mut config = load_from(&config_path)?;
+            let config_start_opts = &mut config.start_options;
+            config_start_opts.init_only = command_args.init_only;
+            config_start_opts.no_init = command_args.no_init;
+            config_start_opts.quiet_startup = command_args.quiet_startup;
+            let meta = StartMeta {
+                config_path: Some(config_path),
+                recipes: command_args.recipes,
+                exclude_recipes: command_args.exclude_recipes,
+                only: command_args.only,
+            };
+            (config, meta)
+        }
     };
 
-    StartTogetherOptions {
+    let source_config = config.clone();
+    let mut config = config;
+    if command_args.report.is_some() {
+        config.start_options.report = command_args.report;
+    }
+    let command_sources = prepare_config(command_args.working_directory.as_deref(), &mut config)
+        .map_err(|e| e.in_file(meta.config_path.as_deref()))?;
+    let only = only_commands(&config.start_options, meta.only)?;
+
+    let timestamp_format = timestamp_format(
+        &mut config.start_options,
+        command_args.timestamps,
+        command_args.timestamp_format,
+        command_args.utc,
+    )?;
+
+    Ok(StartTogetherOptions {
         config,
+        source_config,
+        command_sources,
         working_directory: command_args.working_directory,
         active_recipes: meta.recipes,
+        exclude_recipes: meta.exclude_recipes,
+        only,
         config_path: meta.config_path,
+        output_filter,
+        supervise,
+        timestamp_format,
+    })
+}
+
+/// Expands the commands of a loaded configuration and checks their settings, returning the
+/// index of the command each expanded command came from
+fn prepare_config(
+    working_directory: Option<&str>,
+    config: &mut TogetherConfigFile,
+) -> TogetherResult<Vec<usize>> {
+    let sources = expand_commands(working_directory, config)?;
+    validate_working_directories(working_directory, &config.start_options)?;
+    validate_recurrences(&config.start_options)?;
+    let options = &config.start_options;
+    validate_command_setting(
+        options,
+        "timeout",
+        |d| d.timeout.as_deref(),
+        recurring::parse_duration,
+    )?;
+    validate_command_setting(
+        options,
+        "idle_after",
+        |d| d.idle_after.as_deref(),
+        recurring::parse_duration,
+    )?;
+    validate_command_setting(
+        options,
+        "max_memory",
+        |d| d.max_memory.as_deref(),
+        terminal::parse_bytes,
+    )?;
+    validate_crash_loop_limit(&config.start_options)?;
+    validate_output_rules(&config.start_options)?;
+    validate_targets(&config.start_options)?;
+    crate::kb::KeyBindings::new(&config.start_options.keybindings).map_err(|e| {
+        ConfigError::new(format!("Invalid keybindings: {}", e)).with_key("keybindings")
+    })?;
+    Ok(sources)
+}
+
+/// Applies the timestamp command line flags over the configuration file, failing if the
+/// resulting format is invalid
+pub fn timestamp_format(
+    start_options: &mut commands::ConfigFileStartOptions,
+    timestamps: bool,
+    format: Option<String>,
+    utc: bool,
+) -> TogetherResult<TimestampFormat> {
+    start_options.timestamps |= timestamps;
+    start_options.timestamp_utc |= utc;
+    if format.is_some() {
+        start_options.timestamp_format = format;
+    }
+    TimestampFormat::new(
+        start_options.timestamp_format.as_deref(),
+        start_options.timestamp_utc,
+    )
+    .map_err(|e| {
+        ConfigError::new(format!("Invalid timestamp format: {}", e))
+            .with_key("timestamp_format")
+            .into()
+    })
+}
+
+/// Resolves the directory a command runs in: relative command directories are joined onto
+/// the `--cwd` base directory, absolute command directories are used as-is
+pub fn resolve_cwd(base: Option<&str>, cwd: Option<&str>) -> Option<String> {
+    match (base, cwd) {
+        (Some(base), Some(cwd)) => Some(Path::new(base).join(cwd).to_string_lossy().into_owned()),
+        (None, Some(cwd)) => Some(cwd.to_string()),
+        (base, None) => base.map(|base| base.to_string()),
+    }
+}
+
+/// Resolves the `--only` commands, given by alias, command or index, failing on any that
+/// don't match a command
+pub fn only_commands(
+    start_options: &commands::ConfigFileStartOptions,
+    only: Option<Vec<String>>,
+) -> TogetherResult<Option<Vec<String>>> {
+    let Some(only) = only else {
+        return Ok(None);
+    };
+    let mut commands = vec![];
+    for name in only {
+        let index = match name.parse::<usize>() {
+            Ok(index) => commands::CommandIndex::Simple(index),
+            Err(_) => commands::CommandIndex::Alias(name.clone()),
+        };
+        let Some(command) = index.retrieve(&start_options.commands) else {
+            return Err(
+                ConfigError::new(format!("No command matches '{}' given to --only", name)).into(),
+            );
+        };
+        commands.push(command.as_str().to_string());
+    }
+    Ok(Some(commands))
+}
+
+/// Expands each command with a `matrix` into a command per combination of its values, and
+/// each command with `dirs` into a command per matching directory. Indices in `running`,
+/// `startup` and `shutdown` are shifted to match, with an index of an expanded command
+/// covering all of them. Returns the index of the command each expanded command came from.
+pub fn expand_commands(
+    working_directory: Option<&str>,
+    config: &mut TogetherConfigFile,
+) -> TogetherResult<Vec<usize>> {
+    let commands = &config.start_options.commands;
+    if !commands
+        .iter()
+        .any(|c| c.dirs().is_some() || c.matrix().is_some())
+    {
+        return Ok((0..commands.len()).collect());
+    }
+    let mut expanded = vec![];
+    let mut sources = vec![];
+    // the new indices of each command
+    let mut positions = vec![];
+    for (source, command) in commands.iter().enumerate() {
+        let Some(details) = command
+            .details()
+            .filter(|d| d.dirs.is_some() || d.matrix.is_some())
+        else {
+            positions.push(vec![expanded.len()]);
+            expanded.push(command.clone());
+            sources.push(source);
+            continue;
+        };
+        let mut indices = vec![];
+        for variant in expand_matrix(details)? {
+            for details in expand_dirs(working_directory, variant)? {
+                indices.push(expanded.len());
+                expanded.push(commands::CommandConfig::Detailed(Box::new(details)));
+                sources.push(source);
+            }
+        }
+        positions.push(indices);
+    }
+
+    let reindex = |index: &commands::CommandIndex| -> Vec<commands::CommandIndex> {
+        let position = index
+            .retrieve(commands)
+            .and_then(|command| commands.iter().position(|c| std::ptr::eq(c, command)));
+        match (index, position) {
+            (commands::CommandIndex::Alias(_), Some(i)) if positions[i].len() == 1 => {
+                vec![index.clone()]
+            }
+            (_, Some(i)) => positions[i].iter().map(|&i| i.into()).collect(),
+            (_, None) => vec![index.clone()],
+        }
+    };
+    let reindex_entries = |entries: Option<Vec<commands::StartupEntry>>| {
+        let entries = entries?;
+        let entries = entries.iter().map(|entry| match entry {
+            commands::StartupEntry::Command(index) => match reindex(index).as_slice() {
+                [index] => commands::StartupEntry::Command(index.clone()),
+                group => commands::StartupEntry::Group(group.to_vec()),
+            },
+            commands::StartupEntry::Group(group) => {
+                commands::StartupEntry::Group(group.iter().flat_map(reindex).collect())
+            }
+        });
+        Some(entries.collect())
+    };
+    let running = config
+        .running
+        .take()
+        .map(|running| running.iter().flat_map(reindex).collect());
+    let startup = reindex_entries(config.startup.take());
+    let shutdown = reindex_entries(config.shutdown.take());
+    let launch_order = config
+        .launch_order
+        .take()
+        .map(|order| order.iter().flat_map(reindex).collect());
+    config.running = running;
+    config.launch_order = launch_order;
+    config.startup = startup;
+    config.shutdown = shutdown;
+    config.start_options.commands = expanded;
+    Ok(sources)
+}
+
+/// A command for each combination of the matrix values, with `{key}` replaced by the value
+/// of each key. Commands get an alias and recipes from their values, so `--recipes api` runs
+/// every combination with `api`.
+fn expand_matrix(
+    details: &commands::CommandDetails,
+) -> TogetherResult<Vec<commands::CommandDetails>> {
+    let Some(matrix) = &details.matrix else {
+        return Ok(vec![details.clone()]);
+    };
+    let name = details.alias.as_deref().unwrap_or(&details.command);
+    let mut keys = vec![];
+    for (key, values) in matrix {
+        let Some(position) = details.command.find(&format!("{{{}}}", key)) else {
+            return Err(ConfigError::new(format!(
+                "'{}' has a matrix, so its command needs {{{}}} to tell them apart",
+                name, key
+            ))
+            .with_key("matrix")
+            .into());
+        };
+        if values.is_empty() {
+            return Err(ConfigError::new(format!(
+                "The matrix of '{}' has no values for '{}'",
+                name, key
+            ))
+            .with_key("matrix")
+            .into());
+        }
+        keys.push((position, key.as_str(), values));
+    }
+    // values are combined in the order their keys appear in the command, e.g. `api-dev`
+    keys.sort_by_key(|(position, _, _)| *position);
+
+    let mut combinations: Vec<Vec<(&str, &str)>> = vec![vec![]];
+    for (_, key, values) in keys {
+        combinations = combinations
+            .into_iter()
+            .flat_map(|combination| {
+                values.iter().map(move |value| {
+                    let mut combination = combination.clone();
+                    combination.push((key, value.as_str()));
+                    combination
+                })
+            })
+            .collect();
+    }
+
+    let variants = combinations.into_iter().map(|combination| {
+        let fill = |template: &str| {
+            combination
+                .iter()
+                .fold(template.to_string(), |filled, (key, value)| {
+                    filled.replace(&format!("{{{}}}", key), value)
+                })
+        };
+        let values: Vec<_> = combination.iter().map(|(_, value)| *value).collect();
+        let alias = match details.alias.as_deref() {
+            Some(alias) if alias != fill(alias) => fill(alias),
+            Some(alias) => format!("{}-{}", alias, values.join("-")),
+            None => values.join("-"),
+        };
+        let mut recipes: Vec<_> = details.recipes.iter().flatten().map(|r| fill(r)).collect();
+        for value in values {
+            if !recipes.iter().any(|r| r == value) {
+                recipes.push(value.to_string());
+            }
+        }
+        commands::CommandDetails {
+            command: fill(&details.command),
+            alias: Some(alias),
+            recipes: Some(recipes),
+            group: details.group.as_deref().map(fill),
+            cwd: details.cwd.as_deref().map(fill),
+            matrix: None,
+            ..details.clone()
+        }
+    });
+    Ok(variants.collect())
+}
+
+/// A command for each directory matching its `dirs`, run in that directory with `{dir}`
+/// replaced by its name. Aliases without `{dir}` get the name appended so they stay unique,
+/// and commands without `{dir}` are known by their alias, or by the command and directory.
+fn expand_dirs(
+    working_directory: Option<&str>,
+    details: commands::CommandDetails,
+) -> TogetherResult<Vec<commands::CommandDetails>> {
+    let Some(patterns) = &details.dirs else {
+        return Ok(vec![details]);
+    };
+    let name = details.alias.as_deref().unwrap_or(&details.command);
+    let base = resolve_cwd(working_directory, details.cwd.as_deref());
+    let base = Path::new(base.as_deref().unwrap_or("."));
+    let mut dirs = vec![];
+    for pattern in patterns {
+        let matched = match_dirs(base, pattern)
+            .map_err(|e| ConfigError::new(format!("Failed to expand dirs of '{}': {}", name, e)))?;
+        for dir in matched {
+            if !dirs.contains(&dir) {
+                dirs.push(dir);
+            }
+        }
+    }
+    if dirs.is_empty() {
+        return Err(
+            ConfigError::new(format!("No directories match the dirs of '{}'", name))
+                .with_key("dirs")
+                .into(),
+        );
+    }
+    let variants = dirs.into_iter().map(|dir| {
+        let dir_name = dir.file_name().unwrap_or(dir.as_os_str());
+        let dir_name = dir_name.to_string_lossy();
+        let cwd = match details.cwd.as_deref() {
+            Some(cwd) => Path::new(cwd).join(&dir),
+            None => dir.clone(),
+        };
+        let alias = details
+            .alias
+            .as_ref()
+            .map(|alias| match alias.contains("{dir}") {
+                true => alias.replace("{dir}", &dir_name),
+                false => format!("{}-{}", alias, dir_name),
+            });
+        let key = match details.command.contains("{dir}") {
+            true => None,
+            false => Some(
+                alias
+                    .clone()
+                    .unwrap_or_else(|| format!("{} ({})", details.command, dir.to_string_lossy())),
+            ),
+        };
+        commands::CommandDetails {
+            command: details.command.replace("{dir}", &dir_name),
+            alias,
+            cwd: Some(cwd.to_string_lossy().into_owned()),
+            dirs: None,
+            key,
+            ..details.clone()
+        }
+    });
+    Ok(variants.collect())
+}
+
+/// Directories under `base` matching a pattern of `*` and `?` wildcards, such as
+/// `services/*`, as paths relative to `base`. Hidden directories only match a pattern
+/// component starting with `.`.
+fn match_dirs(base: &Path, pattern: &str) -> std::io::Result<Vec<PathBuf>> {
+    let mut matched = vec![PathBuf::new()];
+    for component in pattern
+        .split(['/', '\\'])
+        .filter(|c| !c.is_empty() && *c != ".")
+    {
+        if !component.contains(['*', '?']) {
+            matched = matched
+                .into_iter()
+                .map(|dir| dir.join(component))
+                .filter(|dir| base.join(dir).is_dir())
+                .collect();
+            continue;
+        }
+        let regex = regex::escape(component)
+            .replace("\\*", ".*")
+            .replace("\\?", ".");
+        let regex = regex::Regex::new(&format!("^{}$", regex)).map_err(std::io::Error::other)?;
+        let mut next = vec![];
+        for dir in matched {
+            let mut entries = vec![];
+            for entry in std::fs::read_dir(base.join(&dir))? {
+                let entry = entry?;
+                let name = entry.file_name().to_string_lossy().into_owned();
+                let hidden = name.starts_with('.') && !component.starts_with('.');
+                if !hidden && regex.is_match(&name) && entry.path().is_dir() {
+                    entries.push(dir.join(name));
+                }
+            }
+            entries.sort();
+            next.extend(entries);
+        }
+        matched = next;
+    }
+    Ok(matched)
+}
+
+/// Checks that the `every` and `schedule` settings of every command can be parsed
+pub fn validate_recurrences(
+    start_options: &commands::ConfigFileStartOptions,
+) -> TogetherResult<()> {
+    for command in &start_options.commands {
+        if let Some(Err(e)) = Recurrence::of(command) {
+            let name = command.alias().unwrap_or(command.as_str());
+            return Err(ConfigError::new(format!("Invalid schedule of '{}': {}", name, e)).into());
+        }
+    }
+    Ok(())
+}
+
+/// Checks that a setting of every command, such as `timeout`, can be parsed
+fn validate_command_setting<T, E: std::fmt::Display>(
+    start_options: &commands::ConfigFileStartOptions,
+    key: &str,
+    setting: impl Fn(&commands::CommandDetails) -> Option<&str>,
+    parse: impl Fn(&str) -> Result<T, E>,
+) -> TogetherResult<()> {
+    for command in &start_options.commands {
+        if let Some(Err(e)) = command.details().and_then(&setting).map(&parse) {
+            let name = command.alias().unwrap_or(command.as_str());
+            return Err(
+                ConfigError::new(format!("Invalid {} of '{}': {}", key, name, e))
+                    .with_key(key)
+                    .into(),
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Checks that the `crash_loop` limit allows at least one restart
+pub fn validate_crash_loop_limit(
+    start_options: &commands::ConfigFileStartOptions,
+) -> TogetherResult<()> {
+    match start_options.crash_loop.as_ref().map(|l| l.validate()) {
+        Some(Err(e)) => Err(ConfigError::new(format!("Invalid crash_loop: {}", e))
+            .with_key("crash_loop")
+            .into()),
+        _ => Ok(()),
     }
 }
 
+/// Checks that the `on_output` patterns of every command are valid regular expressions
+pub fn validate_output_rules(
+    start_options: &commands::ConfigFileStartOptions,
+) -> TogetherResult<()> {
+    for command in &start_options.commands {
+        for rule in command.output_rules() {
+            if let Err(e) = regex::Regex::new(&rule.pattern) {
+                let name = command.alias().unwrap_or(command.as_str());
+                return Err(ConfigError::new(format!(
+                    "Invalid on_output pattern of '{}': {}",
+                    name, e
+                ))
+                .with_key("on_output")
+                .into());
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Checks that commands run on at most one of a host over ssh, a container or WSL, and
+/// that those on a host or in a container don't also set a local user to run as
+pub fn validate_targets(start_options: &commands::ConfigFileStartOptions) -> TogetherResult<()> {
+    for command in &start_options.commands {
+        let name = command.alias().unwrap_or(command.as_str());
+        let targets = [
+            command.host().is_some(),
+            command.container().is_some(),
+            command.wsl().is_some(),
+        ];
+        let error = match (targets, command.user()) {
+            ([true, true, _] | [true, _, true] | [_, true, true], _) => {
+                "can only run in one of a host, a container or WSL"
+            }
+            ([true, _, _], Some(_)) => {
+                "runs on a host over ssh, so its user should be part of the host instead"
+            }
+            ([_, true, _], Some(_)) => {
+                "runs in a container, so its user should be set with docker's --user instead"
+            }
+            _ => continue,
+        };
+        return Err(ConfigError::new(format!("'{}' {}", name, error)).into());
+    }
+    Ok(())
+}
+
+/// Checks that the `--cwd` base directory and every command directory exist, failing with
+/// the missing directories listed rather than later when the command is started
+pub fn validate_working_directories(
+    working_directory: Option<&str>,
+    start_options: &commands::ConfigFileStartOptions,
+) -> TogetherResult<()> {
+    let mut missing = vec![];
+    if let Some(dir) = working_directory {
+        if !Path::new(dir).is_dir() {
+            missing.push(format!("--cwd: {}", dir));
+        }
+    }
+    for command in &start_options.commands {
+        // the directory of a remote command is on its host or in its container
+        let (Some(cwd), true) = (command.cwd(), command.is_local()) else {
+            continue;
+        };
+        let name = command.alias().unwrap_or(command.as_str());
+        if working_directory.is_some() && Path::new(cwd).is_absolute() {
+            log!(
+                "{}: using its absolute cwd '{}' instead of --cwd",
+                name,
+                cwd
+            );
+        }
+        let resolved = resolve_cwd(working_directory, Some(cwd)).unwrap_or_default();
+        if !Path::new(&resolved).is_dir() {
+            missing.push(format!("{}: {}", name, resolved));
+        }
+    }
+    if missing.is_empty() {
+        return Ok(());
+    }
+    let missing: Vec<_> = missing.iter().map(|dir| format!("  - {}", dir)).collect();
+    Err(ConfigError::new(format!(
+        "Working directories not found:\n{}",
+        missing.join("\n")
+    ))
+    .into())
+}
+
+pub fn supervise_options(command_args: &terminal::TogetherArgs) -> Option<SupervisorOptions> {
+    command_args
+        .supervise
+        .then(|| SupervisorOptions::new(command_args.heartbeat_log.clone()))
+}
+
+pub fn output_filter(
+    command_args: &terminal::TogetherArgs,
+) -> Result<Option<OutputFilter>, regex::Error> {
+    if let Some(pattern) = &command_args.grep {
+        return Ok(Some(OutputFilter::Grep(regex::Regex::new(pattern)?)));
+    }
+    if let Some(pattern) = &command_args.highlight {
+        return Ok(Some(OutputFilter::Highlight(regex::Regex::new(pattern)?)));
+    }
+    Ok(None)
+}
+
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub struct TogetherConfigFile {
     #[serde(flatten)]
     pub start_options: commands::ConfigFileStartOptions,
     pub running: Option<Vec<commands::CommandIndex>>,
-    pub startup: Option<Vec<commands::CommandIndex>>,
+    pub startup: Option<Vec<commands::StartupEntry>>,
+    /// Commands to run once all other processes have stopped, before exiting
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub shutdown: Option<Vec<commands::StartupEntry>>,
+    /// Order to start the selected commands in, as set with `together order`. Commands not
+    /// listed start after those that are.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub launch_order: Option<Vec<commands::CommandIndex>>,
     pub version: Option<String>,
+    /// Cargo features of the together build that wrote the file
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub features: Option<Vec<String>>,
 }
 
+/// Top level fields of `TogetherConfigFile`, which can't be introspected as it flattens
+/// `ConfigFileStartOptions` into itself
+const CONFIG_FILE_FIELDS: &[&str] = &[
+    "running",
+    "startup",
+    "shutdown",
+    "launch_order",
+    "version",
+    "features",
+];
+
 impl TogetherConfigFile {
-    fn new(start_options: commands::ConfigFileStartOptions) -> Self {
+    pub fn new(start_options: commands::ConfigFileStartOptions) -> Self {
         Self {
             start_options,
             running: None,
             startup: None,
+            shutdown: None,
+            launch_order: None,
             version: Some(env!("CARGO_PKG_VERSION").to_string()),
-        }
-    }
-
-    pub fn with_running(self, running: &[impl AsRef<str>]) -> Self {
-        let running = running
-            .iter()
-            .map(|c| {
-                self.start_options
-                    .commands
-                    .iter()
-                    .position(|x| x.matches(c.as_ref()))
-                    .unwrap()
-                    .into()
-            })
-            .collect();
-
-        Self {
-            running: Some(running),
-            ..self
+            features: Some(enabled_features()),
         }
     }
 
@@ -181,26 +788,64 @@ impl TryFrom<&std::path::Path> for ConfigFileType {
         match value.extension().and_then(|ext| ext.to_str()) {
             Some("toml") => Ok(Self::Toml),
             Some("yaml") | Some("yml") => Ok(Self::Yaml),
-            _ => Err(TogetherError::InternalError(
-                crate::errors::TogetherInternalError::InvalidConfigExtension,
-            )),
+            _ => Err(ConfigError::new(
+                "Unsupported configuration file extension, expected .yml, .yaml or .toml",
+            )
+            .with_path(value)
+            .into()),
         }
     }
 }
 
+/// Loads a configuration file, failing with the file and, for invalid contents, the line at
+/// fault
 pub fn load_from(config_path: impl AsRef<std::path::Path>) -> TogetherResult<TogetherConfigFile> {
     let config_path = config_path.as_ref();
-    let config = std::fs::read_to_string(config_path)?;
-    let config: TogetherConfigFile = match config_path.try_into()? {
-        ConfigFileType::Toml => toml::from_str(&config)?,
-        ConfigFileType::Yaml => serde_yml::from_str(&config)?,
+    let file_type = ConfigFileType::try_from(config_path)?;
+    let contents = std::fs::read_to_string(config_path).map_err(|e| {
+        ConfigError::new(format!("Failed to read the configuration file: {}", e))
+            .with_path(config_path)
+    })?;
+    let parsed = match file_type {
+        ConfigFileType::Toml => parse_toml(&contents),
+        ConfigFileType::Yaml => parse_yaml(&contents),
     };
-    check_version(&config);
+    let (config, raw) = parsed.map_err(|e| e.with_path(config_path))?;
+    check_version(&config, &raw);
     Ok(config)
 }
 
+fn parse_toml(contents: &str) -> Result<(TogetherConfigFile, serde_json::Value), ConfigError> {
+    let error = |e: toml::de::Error| {
+        let error = ConfigError::new(e.message());
+        // settings flattened into the file are only known to be somewhere in the whole of it
+        match e.span().filter(|span| span.start > 0) {
+            Some(span) => error.with_line(contents[..span.start].matches('\n').count() + 1),
+            None => error,
+        }
+    };
+    Ok((
+        toml::from_str(contents).map_err(error)?,
+        toml::from_str(contents).map_err(error)?,
+    ))
+}
+
+fn parse_yaml(contents: &str) -> Result<(TogetherConfigFile, serde_json::Value), ConfigError> {
+    let error = |e: serde_yml::Error| {
+        let error = ConfigError::new(e.to_string());
+        match e.location() {
+            Some(location) => error.with_line(location.line()),
+            None => error,
+        }
+    };
+    Ok((
+        serde_yml::from_str(contents).map_err(error)?,
+        serde_yml::from_str(contents).map_err(error)?,
+    ))
+}
+
 pub fn load() -> TogetherResult<TogetherConfigFile> {
-    let config_path = path_or_default();
+    let config_path = path_or_default()?;
     log!("Loading configuration from: {:?}", config_path);
     load_from(config_path)
 }
@@ -209,10 +854,16 @@ pub fn save(
     config: &TogetherConfigFile,
     config_path: Option<&std::path::Path>,
 ) -> TogetherResult<()> {
-    let config_path = config_path
-        .map(Cow::from)
-        .unwrap_or_else(|| path_or_default().into());
+    let config_path = match config_path {
+        Some(config_path) => Cow::from(config_path),
+        None => path_or_default()?.into(),
+    };
     log!("Saving configuration to: {:?}", config_path);
+    let config = &TogetherConfigFile {
+        version: Some(env!("CARGO_PKG_VERSION").to_string()),
+        features: Some(enabled_features()),
+        ..config.clone()
+    };
     let config = match config_path.as_ref().try_into()? {
         ConfigFileType::Toml => toml::to_string(config)?,
         ConfigFileType::Yaml => serde_yml::to_string(config)?,
@@ -221,6 +872,187 @@ pub fn save(
     Ok(())
 }
 
+/// Appends a command to the configuration file, for `together add`
+pub fn add_command(add: &terminal::AddCommand) -> TogetherResult<()> {
+    let (mut config, config_path) = load_for_edit(add.path.as_deref(), add.yes)?;
+    let start_options = &mut config.start_options;
+    let names = [Some(add.command.as_str()), add.alias.as_deref()];
+    if let Some(name) = names
+        .into_iter()
+        .flatten()
+        .find(|name| start_options.find_command(name).is_some())
+    {
+        return Err(ConfigError::new(format!(
+            "A command matching '{}' is already in {:?}",
+            name, config_path
+        ))
+        .into());
+    }
+    let command = match (&add.alias, &add.recipes) {
+        (None, None) => commands::CommandConfig::Simple(add.command.clone()),
+        _ => commands::CommandConfig::Detailed(Box::new(commands::CommandDetails {
+            command: add.command.clone(),
+            alias: add.alias.clone(),
+            recipes: add.recipes.clone(),
+            ..Default::default()
+        })),
+    };
+    start_options.commands.push(command);
+    save(&config, Some(&config_path))?;
+    log!("Added '{}'", add.command);
+    Ok(())
+}
+
+/// Removes a command from the configuration file, for `together remove`. The command is
+/// also removed from `running`, `startup` and `shutdown`, and indices after it are shifted.
+pub fn remove_command(remove: &terminal::RemoveCommand) -> TogetherResult<()> {
+    let (mut config, config_path) = load_for_edit(remove.path.as_deref(), remove.yes)?;
+    let commands = &config.start_options.commands;
+    let index = match remove.command.parse::<usize>() {
+        Ok(index) => commands::CommandIndex::Simple(index),
+        Err(_) => commands::CommandIndex::Alias(remove.command.clone()),
+    };
+    let Some(position) = index
+        .retrieve(commands)
+        .and_then(|command| commands.iter().position(|c| std::ptr::eq(c, command)))
+    else {
+        return Err(ConfigError::new(format!(
+            "No command matches '{}' in {:?}",
+            remove.command, config_path
+        ))
+        .into());
+    };
+    let removed = config.start_options.commands.remove(position);
+
+    let reindex = |index: &commands::CommandIndex| match index {
+        commands::CommandIndex::Simple(i) if *i == position => None,
+        commands::CommandIndex::Simple(i) if *i > position => Some((i - 1).into()),
+        commands::CommandIndex::Alias(alias) if removed.matches(alias) => None,
+        index => Some(index.clone()),
+    };
+    let reindex_entries = |entries: Option<Vec<commands::StartupEntry>>| {
+        let entries = entries?;
+        let entries = entries.iter().filter_map(|entry| match entry {
+            commands::StartupEntry::Command(index) => {
+                reindex(index).map(commands::StartupEntry::Command)
+            }
+            commands::StartupEntry::Group(group) => {
+                let group: Vec<_> = group.iter().filter_map(reindex).collect();
+                (!group.is_empty()).then_some(commands::StartupEntry::Group(group))
+            }
+        });
+        Some(entries.collect())
+    };
+    config.running = config
+        .running
+        .map(|running| running.iter().filter_map(reindex).collect());
+    config.startup = reindex_entries(config.startup);
+    config.shutdown = reindex_entries(config.shutdown);
+    config.launch_order = config
+        .launch_order
+        .map(|order| order.iter().filter_map(reindex).collect());
+
+    save(&config, Some(&config_path))?;
+    log!("Removed '{}'", removed.as_str());
+    Ok(())
+}
+
+/// Prompts for the order to start commands in, and the wait between them, saving both to the
+/// configuration file
+pub fn order_commands(order: &terminal::OrderCommand) -> TogetherResult<()> {
+    let (mut config, config_path) = load_for_edit(order.path.as_deref(), false)?;
+    let commands = &config.start_options.commands;
+    if commands.is_empty() {
+        return Err(ConfigError::new(format!("No commands to order in {:?}", config_path)).into());
+    }
+    let mut labels: Vec<_> = commands
+        .iter()
+        .map(|c| c.alias().unwrap_or(c.as_str()))
+        .collect();
+    // start from the current order, so running it again only needs the changes
+    if let Some(order) = &config.launch_order {
+        let position = |label: &&str| {
+            order
+                .iter()
+                .position(|index| index.retrieve(commands).is_some_and(|c| c.matches(label)))
+                .unwrap_or(order.len())
+        };
+        labels.sort_by_key(position);
+    }
+    let Some(ordered) = terminal::Terminal::select_ordered("Order to start commands in", &labels)?
+    else {
+        return Ok(());
+    };
+    let launch_order = ordered
+        .into_iter()
+        .filter_map(|label| {
+            let position = commands.iter().position(|c| c.matches(label))?;
+            Some(match commands[position].alias() {
+                Some(alias) => commands::CommandIndex::Alias(alias.to_string()),
+                None => position.into(),
+            })
+        })
+        .collect();
+
+    let current = config.start_options.stagger_ms.unwrap_or_default();
+    let prompt = format!(
+        "Milliseconds to wait between commands (currently {})",
+        current
+    );
+    if let Some(input) = terminal::Terminal::input(&prompt)? {
+        let stagger_ms = input.parse::<u64>().map_err(|_| {
+            ConfigError::new(format!("'{}' is not a number of milliseconds", input))
+        })?;
+        config.start_options.stagger_ms = (stagger_ms > 0).then_some(stagger_ms);
+    }
+    config.launch_order = Some(launch_order);
+
+    save(&config, Some(&config_path))?;
+    log!("Saved launch order to {:?}", config_path);
+    Ok(())
+}
+
+/// Loads the configuration file to edit, the given path or the one in the current directory
+fn load_for_edit(
+    config_path: Option<&str>,
+    yes: bool,
+) -> TogetherResult<(TogetherConfigFile, PathBuf)> {
+    let config_path = match config_path {
+        Some(config_path) => PathBuf::from(config_path),
+        None => path(None).ok_or_else(|| {
+            ConfigError::new(
+                "No configuration file found in the current directory, use --path to pick one",
+            )
+        })?,
+    };
+    let config = load_from(&config_path)?;
+    // the file is written again from the parsed configuration, which has no comments
+    let contents = std::fs::read_to_string(&config_path)?;
+    if has_comments(&contents) && !yes {
+        let prompt = format!(
+            "{:?} has comments, which are lost when it is rewritten. Rewrite it anyway?",
+            config_path
+        );
+        let interactive = std::io::IsTerminal::is_terminal(&std::io::stdin());
+        if !interactive || !terminal::Terminal::confirm(&prompt)? {
+            return Err(ConfigError::new(format!(
+                "Left {:?} unchanged to keep its comments, pass --yes to rewrite it anyway",
+                config_path
+            ))
+            .into());
+        }
+    }
+    Ok((config, config_path))
+}
+
+/// Whether a configuration file has comments. A `#` inside a quoted value also counts, as
+/// telling them apart needs the full syntax of the file.
+fn has_comments(contents: &str) -> bool {
+    contents.lines().any(|line| {
+        line.trim_start().starts_with('#') || line.contains(" #") || line.contains("\t#")
+    })
+}
+
 pub fn dump(config: &TogetherConfigFile) -> TogetherResult<()> {
     let config = serde_yml::to_string(config)?;
     t_println!("Configuration:");
@@ -252,6 +1084,52 @@ pub fn get_unique_recipes(start_options: &commands::ConfigFileStartOptions) -> H
         .collect::<HashSet<_>>()
 }
 
+/// Unique recipes sorted by name, with deprecated recipes last
+pub fn get_ordered_recipes(start_options: &commands::ConfigFileStartOptions) -> Vec<String> {
+    let mut recipes: Vec<String> = get_unique_recipes(start_options)
+        .into_iter()
+        .cloned()
+        .collect();
+    recipes.sort_by(|a, b| {
+        let a_deprecated = start_options.deprecated_recipes.contains_key(a);
+        let b_deprecated = start_options.deprecated_recipes.contains_key(b);
+        a_deprecated.cmp(&b_deprecated).then_with(|| a.cmp(b))
+    });
+    recipes
+}
+
+pub fn warn_deprecated_commands(
+    start_options: &commands::ConfigFileStartOptions,
+    commands: &[impl AsRef<str>],
+) {
+    for command in commands {
+        let command = command.as_ref();
+        let Some(config) = start_options.find_command(command) else {
+            continue;
+        };
+        if let Some(message) = config.deprecated() {
+            let name = config.alias().unwrap_or(command);
+            log_err!("Command '{}' is deprecated: {}", name, message);
+        }
+    }
+}
+
+pub fn warn_deprecated_recipes(
+    start_options: &commands::ConfigFileStartOptions,
+    recipes: &[impl AsRef<str>],
+) {
+    for recipe in recipes {
+        let recipe = recipe.as_ref().trim();
+        let deprecated = start_options
+            .deprecated_recipes
+            .iter()
+            .find(|(r, _)| r.eq_ignore_ascii_case(recipe));
+        if let Some((recipe, message)) = deprecated {
+            log_err!("Recipe '{}' is deprecated: {}", recipe, message);
+        }
+    }
+}
+
 pub fn collect_commands_by_recipes(
     start_options: &commands::ConfigFileStartOptions,
     recipes: &[impl AsRef<str>],
@@ -265,14 +1143,56 @@ pub fn collect_commands_by_recipes(
     selected_commands
 }
 
-fn path_or_default() -> std::path::PathBuf {
-    let dir_path = dirs::config_dir().unwrap();
+/// Leaves out commands tagged under any of the excluded recipes
+pub fn exclude_commands_by_recipes(
+    start_options: &commands::ConfigFileStartOptions,
+    commands: Vec<String>,
+    excluded: &[impl AsRef<str>],
+) -> Vec<String> {
+    commands
+        .into_iter()
+        .filter(|c| {
+            let command = start_options.find_command(c);
+            !excluded
+                .iter()
+                .any(|r| command.is_some_and(|c| c.contains_recipe(r.as_ref())))
+        })
+        .collect()
+}
+
+/// Whether a configuration file exists in the current directory
+pub fn exists() -> bool {
+    path(None).is_some()
+}
+
+fn path_or_default() -> TogetherResult<std::path::PathBuf> {
+    let dir_path = dirs::config_dir()
+        .ok_or_else(|| ConfigError::new("No configuration directory found for this user"))?;
     match path(Some(&dir_path)) {
-        Some(path) => path,
-        None => dir_path.join("together.yml"),
+        Some(path) => Ok(path),
+        None => Ok(dir_path.join("together.yml")),
     }
 }
 
+/// Saves the commands running when together quits, along with the configuration they were
+/// started from, for `together rerun`. This is kept apart from any configuration file, so
+/// quitting never rewrites one.
+pub fn save_session(config: &TogetherConfigFile) -> TogetherResult<()> {
+    let session_path = session_path()
+        .ok_or_else(|| ConfigError::new("No state directory found to save the session in"))?;
+    if let Some(dir) = session_path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    save(config, Some(&session_path))
+}
+
+/// `together/session.yml` in the user's state directory, or local data directory where there
+/// is none
+fn session_path() -> Option<PathBuf> {
+    let dir = dirs::state_dir().or_else(dirs::data_local_dir)?;
+    Some(dir.join("together").join("session.yml"))
+}
+
 fn path(dir: Option<&Path>) -> Option<std::path::PathBuf> {
     let files = ["together.yml", "together.yaml", "together.toml"];
     files.iter().find_map(|f| {
@@ -285,39 +1205,152 @@ fn path(dir: Option<&Path>) -> Option<std::path::PathBuf> {
     })
 }
 
-fn check_version(config: &TogetherConfigFile) {
-    let Some(version) = &config.version else {
-        log_err!(
-            "The configuration file was created with a different version of together. \
-            Please update together to the latest version."
-        );
-        std::process::exit(1);
-    };
+/// Warns about configuration files written by a different version of together, listing any
+/// settings this version doesn't know about and will ignore, rather than refusing to load them
+fn check_version(config: &TogetherConfigFile, raw: &serde_json::Value) {
     let current_version = env!("CARGO_PKG_VERSION");
-    let current_version = semver::Version::parse(current_version).unwrap();
-    let config_version = semver::Version::parse(version).unwrap();
-    if current_version.major < config_version.major {
+    let current = semver::Version::parse(current_version).unwrap();
+    let config_version = config.version.as_deref().unwrap_or("unknown");
+    match semver::Version::parse(config_version) {
+        Ok(version) if (version.major, version.minor) > (current.major, current.minor) => {
+            log_err!(
+                "The configuration file was created with a more recent version of together ({}), \
+                this is version {}. Some features may not be available.",
+                version,
+                current
+            );
+        }
+        Ok(_) => {}
+        Err(_) => {
+            log_err!(
+                "The configuration file was created with an unknown version of together ({}), \
+                this is version {}.",
+                config_version,
+                current
+            );
+        }
+    }
+
+    let ignored = unknown_fields(raw);
+    if !ignored.is_empty() {
         log_err!(
-            "The configuration file was created with a more recent version of together (>={config_version}). \
-            Please update together to the latest version."
+            "Ignoring settings not supported by together {}: {}",
+            current,
+            ignored.join(", ")
         );
-        std::process::exit(1);
     }
 
-    if current_version.minor < config_version.minor {
+    let enabled = enabled_features();
+    let missing: Vec<_> = config
+        .features
+        .iter()
+        .flatten()
+        .filter(|f| !enabled.contains(f))
+        .collect();
+    if !missing.is_empty() {
         log!(
-            "Using configuration file created with a more recent version of together (>={config_version}). \
-            Some features may not be available."
+            "The configuration file was saved by a build of together with features this build lacks: {}",
+            missing.iter().map(|f| f.as_str()).collect::<Vec<_>>().join(", ")
         );
     }
 }
 
+fn enabled_features() -> Vec<String> {
+    let features = [
+        ("termion", cfg!(feature = "termion")),
+        ("cargo", cfg!(feature = "cargo")),
+        ("otlp", cfg!(feature = "otlp")),
+        ("windows", cfg!(feature = "windows")),
+    ];
+    features
+        .into_iter()
+        .filter(|(_, enabled)| *enabled)
+        .map(|(feature, _)| feature.to_string())
+        .collect()
+}
+
+/// Paths of fields in a raw configuration file that don't deserialize into any setting
+fn unknown_fields(raw: &serde_json::Value) -> Vec<String> {
+    let start_fields = struct_fields::<commands::ConfigFileStartOptions>();
+    let command_fields = struct_fields::<commands::CommandDetails>();
+
+    let mut unknown = vec![];
+    let Some(raw) = raw.as_object() else {
+        return unknown;
+    };
+    for (key, value) in raw {
+        if !start_fields.contains(&key.as_str()) && !CONFIG_FILE_FIELDS.contains(&key.as_str()) {
+            unknown.push(key.clone());
+        }
+        if key != "commands" {
+            continue;
+        }
+        for (index, command) in value.as_array().into_iter().flatten().enumerate() {
+            for key in command.as_object().into_iter().flat_map(|c| c.keys()) {
+                if !command_fields.contains(&key.as_str()) {
+                    unknown.push(format!("commands[{}].{}", index, key));
+                }
+            }
+        }
+    }
+    unknown
+}
+
+/// Field names (including aliases) of a struct, taken from the list serde passes to
+/// `Deserializer::deserialize_struct`
+fn struct_fields<'de, T: serde::Deserialize<'de>>() -> &'static [&'static str] {
+    use serde::de::{self, Error};
+
+    struct FieldsDeserializer<'a>(&'a mut &'static [&'static str]);
+
+    impl<'de> de::Deserializer<'de> for FieldsDeserializer<'_> {
+        type Error = de::value::Error;
+
+        fn deserialize_any<V: de::Visitor<'de>>(self, _: V) -> Result<V::Value, Self::Error> {
+            Err(Self::Error::custom("expected a struct"))
+        }
+
+        fn deserialize_struct<V: de::Visitor<'de>>(
+            self,
+            _name: &'static str,
+            fields: &'static [&'static str],
+            _visitor: V,
+        ) -> Result<V::Value, Self::Error> {
+            *self.0 = fields;
+            Err(Self::Error::custom("only collecting fields"))
+        }
+
+        serde::forward_to_deserialize_any! {
+            bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+            bytes byte_buf option unit unit_struct newtype_struct seq tuple
+            tuple_struct map enum identifier ignored_any
+        }
+    }
+
+    let mut fields: &'static [&'static str] = &[];
+    _ = T::deserialize(FieldsDeserializer(&mut fields));
+    fields
+}
+
 pub mod commands {
+    use std::collections::BTreeMap;
+
     use serde::{Deserialize, Serialize};
 
-    use crate::terminal;
+    use crate::{
+        container::Container,
+        log_sink::{ForwardTarget, LogSinkKind},
+        output::OutputMode,
+        ports::PortsMode,
+        report::ReportTarget,
+        restarts::CrashLoopLimit,
+        telemetry::OtlpEndpoint,
+        terminal,
+        theme::Theme,
+        wsl::Wsl,
+    };
 
-    #[derive(Debug, Clone, Serialize, Deserialize)]
+    #[derive(Debug, Clone, Default, Serialize, Deserialize)]
     pub struct ConfigFileStartOptions {
         pub commands: Vec<CommandConfig>,
         #[serde(default)]
@@ -330,6 +1363,63 @@ pub mod commands {
         pub quiet_startup: bool,
         #[serde(default = "defaults::true_value")]
         pub raw: bool,
+        #[serde(default)]
+        pub output_mode: OutputMode,
+        #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+        pub pty: bool,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub spawn_retries: Option<u32>,
+        /// Milliseconds to wait between starting each command
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub stagger_ms: Option<u64>,
+        #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+        pub timestamps: bool,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub timestamp_format: Option<String>,
+        #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+        pub timestamp_utc: bool,
+        #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+        pub deprecated_recipes: BTreeMap<String, String>,
+        /// Commands tagged under these recipes are left out when selecting commands to run
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub exclude_recipes: Option<Vec<String>>,
+        /// Keys to use for actions instead of their defaults, e.g. `kill: x`
+        #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+        pub keybindings: BTreeMap<String, char>,
+        /// Which key actions ask for confirmation, only quitting needs a second press otherwise
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub confirm: Option<ConfirmPolicy>,
+        /// Seconds processes have to exit after being stopped before they are force killed
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub kill_timeout: Option<u64>,
+        /// Forward process output and lifecycle events to syslog or journald
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub log_sink: Option<LogSinkKind>,
+        /// Stream process output as ndjson to a collector at `tcp://host:port` or `udp://host:port`
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub log_forward: Option<ForwardTarget>,
+        /// Export a span for each process to an OTLP collector, e.g. `http://localhost:4318`
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub otlp_endpoint: Option<OtlpEndpoint>,
+        /// Write a report of how each command ran when the session ends, as JUnit XML to a
+        /// `.xml` file or Markdown to a `.md` file
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub report: Option<ReportTarget>,
+        /// `auto` picks a free port for each command, passed to them as `PORT` and
+        /// `TOGETHER_PORT_<ALIAS>`
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub ports: Option<PortsMode>,
+        /// Link file references in output with this instead of `file://` URLs, e.g.
+        /// `vscode://file/{path}:{line}:{column}`
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub hyperlink_format: Option<String>,
+        /// Colors and symbols of together's messages, and the prefix of output lines
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub theme: Option<Theme>,
+        /// Stop restarting a command automatically once it restarts more than `max_restarts`
+        /// times within `window`, 5 times a minute by default
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub crash_loop: Option<CrashLoopLimit>,
         #[serde(skip)]
         pub init_only: bool,
         #[serde(skip)]
@@ -351,6 +1441,26 @@ pub mod commands {
                 quit_on_completion: args.quit_on_completion,
                 quiet_startup: false,
                 raw: args.raw,
+                output_mode: args.output_mode,
+                pty: args.pty,
+                spawn_retries: args.spawn_retries,
+                stagger_ms: args.stagger_ms,
+                timestamps: false,
+                timestamp_format: None,
+                timestamp_utc: false,
+                deprecated_recipes: BTreeMap::new(),
+                exclude_recipes: None,
+                keybindings: BTreeMap::new(),
+                confirm: None,
+                kill_timeout: None,
+                log_sink: None,
+                log_forward: None,
+                otlp_endpoint: None,
+                report: None,
+                ports: None,
+                hyperlink_format: None,
+                theme: None,
+                crash_loop: None,
                 init_only: args.init_only,
                 no_init: args.no_init,
             }
@@ -369,6 +1479,10 @@ pub mod commands {
                 exit_on_error: config.exit_on_error,
                 quit_on_completion: config.quit_on_completion,
                 raw: config.raw,
+                output_mode: config.output_mode,
+                pty: config.pty,
+                spawn_retries: config.spawn_retries,
+                stagger_ms: config.stagger_ms,
                 init_only: config.init_only,
                 no_init: config.no_init,
             }
@@ -382,62 +1496,458 @@ pub mod commands {
                 .map(|c| c.as_str().to_string())
                 .collect()
         }
+
+        pub fn find_command(&self, command: &str) -> Option<&CommandConfig> {
+            self.commands.iter().find(|c| c.matches(command))
+        }
+
+        /// Groups of the commands, in the order they first appear
+        pub fn groups(&self) -> Vec<&str> {
+            let mut groups = vec![];
+            for group in self.commands.iter().filter_map(|c| c.group()) {
+                if !groups.contains(&group) {
+                    groups.push(group);
+                }
+            }
+            groups
+        }
+
+        pub fn group_of(&self, command: &str) -> Option<&str> {
+            self.find_command(command).and_then(|c| c.group())
+        }
     }
 
     #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
     #[serde(untagged)]
     pub enum CommandConfig {
         Simple(String),
-        Detailed {
-            command: String,
-            alias: Option<String>,
-            #[serde(alias = "default")]
-            active: Option<bool>,
-            recipes: Option<Vec<String>>,
-        },
+        Detailed(Box<CommandDetails>),
+    }
+
+    #[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+    pub struct CommandDetails {
+        pub command: String,
+        pub alias: Option<String>,
+        #[serde(alias = "default")]
+        pub active: Option<bool>,
+        pub recipes: Option<Vec<String>>,
+        /// Label the command is listed under at runtime, e.g. `backend`
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub group: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub url: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub deprecated: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub pty: Option<bool>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub cwd: Option<String>,
+        /// Directories to run the command in, one process each, e.g. `services/*`
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub dirs: Option<Vec<String>>,
+        /// Values to run the command with, one process for each combination, e.g.
+        /// `service: [api, auth]` fills in `{service}`
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub matrix: Option<BTreeMap<String, Vec<String>>>,
+        /// Milliseconds to wait after the previous command started, instead of `stagger_ms`
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub delay: Option<u64>,
+        /// Condition to wait for before starting the command
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub wait_for: Option<WaitFor>,
+        /// Command to run when the command exits
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub on_exit: Option<String>,
+        /// Command to run when the command exits with a non-zero status
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub on_failure: Option<String>,
+        /// Actions to take when a line of the command's output matches a pattern
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub on_output: Option<Vec<OutputRule>>,
+        /// Which output of the command is shown
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub output: Option<CommandOutput>,
+        /// Pass the output of the command through unprefixed, instead of the top level `raw`
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub raw: Option<bool>,
+        /// Variables to set for the command. Values such as `secret://keychain/api-key` or
+        /// `op://vault/item/field` are read from a secret store when the command starts.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub env: Option<BTreeMap<String, String>>,
+        /// Inherit the environment together runs in, true by default. Otherwise only variables
+        /// a shell needs such as `PATH` and `HOME` are inherited.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub inherit_env: Option<bool>,
+        /// Only inherit these variables of the environment, along with those a shell needs.
+        /// Names ending in `*` match every variable starting with the rest, e.g. `AWS_*`.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub env_allowlist: Option<Vec<String>>,
+        /// Never inherit these variables of the environment, e.g. `DATABASE_URL`
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub env_blocklist: Option<Vec<String>>,
+        /// User to run the command as, a name or `uid[:gid]` (unix only)
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub user: Option<String>,
+        /// Host to run the command on over ssh, e.g. `user@devbox`, with `cwd` on the host
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub host: Option<String>,
+        /// Docker container to run the command in, a running container's name or an `image`
+        /// to start one from, with `cwd` in the container
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub container: Option<Container>,
+        /// Run the command in WSL on windows, `true` or the name of a distribution, with
+        /// `cwd` as a windows or linux path
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub wsl: Option<Wsl>,
+        /// Interval to run the command again at, e.g. `30s` or `5m`
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub every: Option<String>,
+        /// Cron expression to run the command again on, e.g. `*/5 * * * *`
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub schedule: Option<String>,
+        /// How long the command can run before it is stopped, e.g. `10m`
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub timeout: Option<String>,
+        /// What to do when the command runs past its timeout, killing it by default
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub on_timeout: Option<TimeoutAction>,
+        /// How long the command can go without printing output or using CPU before it counts
+        /// as idle, e.g. `30m`
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub idle_after: Option<String>,
+        /// What to do once the command is idle, notifying by default
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub on_idle: Option<IdleAction>,
+        /// Memory the command can use before it is restarted, e.g. `2GB`
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub max_memory: Option<String>,
+        /// The command is a task expected to finish, rather than a long-running process
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub oneshot: Option<bool>,
+        /// Exit statuses that don't count as the command failing, instead of just 0
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub success_codes: Option<Vec<i32>>,
+        /// Never count the command as failing, whatever its exit status
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub ignore_failure: Option<bool>,
+        /// Open the first local URL the command prints in the browser
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub open: Option<bool>,
+        /// Name the command is known by instead of its command, for the processes of `dirs`
+        /// that run the same command in different directories
+        #[serde(skip)]
+        pub key: Option<String>,
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+    #[serde(rename_all = "kebab-case")]
+    pub enum CommandOutput {
+        /// Stdout and stderr, prefixed with the process index
+        All,
+        /// Only stderr, unprefixed
+        StderrOnly,
+        None,
+        /// Stdout and stderr, unprefixed
+        Raw,
+    }
+
+    #[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+    #[serde(rename_all = "lowercase")]
+    pub enum TimeoutAction {
+        /// Kill the command, counting it as failed
+        #[default]
+        Kill,
+        /// Kill the command and start it again
+        Restart,
+    }
+
+    #[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+    #[serde(rename_all = "lowercase")]
+    pub enum IdleAction {
+        /// Log and show a desktop notification, leaving the command running
+        #[default]
+        Notify,
+        /// Kill the command
+        Stop,
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+    #[serde(rename_all = "lowercase")]
+    pub enum ConfirmPolicy {
+        /// Quit on the first press, without confirming anything
+        Never,
+        /// Confirm quitting and killing commands
+        Destructive,
+        /// Also confirm restarting commands
+        Always,
+    }
+
+    /// A condition checked before starting a command, e.g. `wait_for: { port: 5432 }`
+    #[derive(Debug, Clone, PartialEq, Deserialize)]
+    #[serde(rename_all = "snake_case")]
+    pub enum WaitFor {
+        /// A TCP port on localhost accepting connections
+        Port(u16),
+        /// An http URL responding with a 2xx status
+        Url(String),
+        /// A file existing, relative to the command's working directory
+        File(String),
+    }
+
+    /// Written as a map rather than the default enum representation, which YAML writes as
+    /// a tag such as `!port 5432` that can't be read back within an untagged `CommandConfig`
+    impl Serialize for WaitFor {
+        fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            use serde::ser::SerializeMap;
+            let mut map = serializer.serialize_map(Some(1))?;
+            match self {
+                Self::Port(port) => map.serialize_entry("port", port)?,
+                Self::Url(url) => map.serialize_entry("url", url)?,
+                Self::File(file) => map.serialize_entry("file", file)?,
+            }
+            map.end()
+        }
+    }
+
+    /// An action taken when a line of output matches `pattern`, a regular expression, e.g.
+    /// `{ pattern: OutOfMemory, action: restart }`
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    pub struct OutputRule {
+        pub pattern: String,
+        pub action: OutputAction,
+    }
+
+    #[derive(Debug, Clone, PartialEq, Deserialize)]
+    #[serde(rename_all = "snake_case")]
+    pub enum OutputAction {
+        /// Restart the command
+        Restart,
+        /// Kill the command
+        Kill,
+        /// Run another command, e.g. `action: { run: notify-send done }`
+        Run(String),
+    }
+
+    /// Written as a map for `run` for the same reason as `WaitFor`
+    impl Serialize for OutputAction {
+        fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            use serde::ser::SerializeMap;
+            match self {
+                Self::Restart => serializer.serialize_str("restart"),
+                Self::Kill => serializer.serialize_str("kill"),
+                Self::Run(command) => {
+                    let mut map = serializer.serialize_map(Some(1))?;
+                    map.serialize_entry("run", command)?;
+                    map.end()
+                }
+            }
+        }
+    }
+
+    impl std::fmt::Display for WaitFor {
+        fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+            match self {
+                Self::Port(port) => write!(f, "port {}", port),
+                Self::Url(url) => write!(f, "{}", url),
+                Self::File(file) => write!(f, "file '{}'", file),
+            }
+        }
     }
 
     impl CommandConfig {
         pub fn as_str(&self) -> &str {
             match self {
                 Self::Simple(s) => s,
-                Self::Detailed { command, .. } => command,
+                Self::Detailed(details) => details.key.as_deref().unwrap_or(&details.command),
             }
         }
 
-        pub fn alias(&self) -> Option<&str> {
+        /// The command line run, which differs from `as_str` for commands with a `key`
+        pub fn command_line(&self) -> &str {
             match self {
-                Self::Simple(_) => None,
-                Self::Detailed { alias, .. } => alias.as_deref(),
+                Self::Simple(s) => s,
+                Self::Detailed(details) => &details.command,
             }
         }
 
-        pub fn is_active(&self) -> bool {
+        pub fn details(&self) -> Option<&CommandDetails> {
             match self {
-                Self::Simple(_) => false,
-                Self::Detailed { active, .. } => active.unwrap_or(false),
+                Self::Simple(_) => None,
+                Self::Detailed(details) => Some(details.as_ref()),
             }
         }
 
+        pub fn alias(&self) -> Option<&str> {
+            self.details().and_then(|d| d.alias.as_deref())
+        }
+
+        pub fn url(&self) -> Option<&str> {
+            self.details().and_then(|d| d.url.as_deref())
+        }
+
+        pub fn deprecated(&self) -> Option<&str> {
+            self.details().and_then(|d| d.deprecated.as_deref())
+        }
+
+        pub fn cwd(&self) -> Option<&str> {
+            self.details().and_then(|d| d.cwd.as_deref())
+        }
+
+        pub fn dirs(&self) -> Option<&[String]> {
+            self.details().and_then(|d| d.dirs.as_deref())
+        }
+
+        pub fn matrix(&self) -> Option<&BTreeMap<String, Vec<String>>> {
+            self.details().and_then(|d| d.matrix.as_ref())
+        }
+
+        pub fn output(&self) -> Option<CommandOutput> {
+            self.details().and_then(|d| d.output)
+        }
+
+        pub fn env(&self) -> Option<&BTreeMap<String, String>> {
+            self.details().and_then(|d| d.env.as_ref())
+        }
+
+        pub fn user(&self) -> Option<&str> {
+            self.details().and_then(|d| d.user.as_deref())
+        }
+
+        pub fn host(&self) -> Option<&str> {
+            self.details().and_then(|d| d.host.as_deref())
+        }
+
+        pub fn container(&self) -> Option<&Container> {
+            self.details().and_then(|d| d.container.as_ref())
+        }
+
+        pub fn wsl(&self) -> Option<&Wsl> {
+            let wsl = self.details().and_then(|d| d.wsl.as_ref());
+            wsl.filter(|wsl| wsl.is_enabled())
+        }
+
+        /// Whether the command runs on this machine, rather than on a host, in a container or
+        /// in WSL. WSL is only used on windows, elsewhere the command runs directly.
+        pub fn is_local(&self) -> bool {
+            let wsl = cfg!(windows) && self.wsl().is_some();
+            self.host().is_none() && self.container().is_none() && !wsl
+        }
+
+        pub fn raw(&self) -> Option<bool> {
+            self.details().and_then(|d| d.raw)
+        }
+
+        pub fn pty(&self) -> Option<bool> {
+            self.details().and_then(|d| d.pty)
+        }
+
+        pub fn delay(&self) -> Option<std::time::Duration> {
+            self.details()
+                .and_then(|d| d.delay)
+                .map(std::time::Duration::from_millis)
+        }
+
+        /// Bytes of memory the command can use, if it has a valid limit
+        pub fn max_memory(&self) -> Option<u64> {
+            let max_memory = self.details().and_then(|d| d.max_memory.as_deref())?;
+            terminal::parse_bytes(max_memory).ok()
+        }
+
+        /// How long the command can run for, if it has a valid timeout
+        pub fn timeout(&self) -> Option<std::time::Duration> {
+            let timeout = self.details().and_then(|d| d.timeout.as_deref())?;
+            crate::recurring::parse_duration(timeout).ok()
+        }
+
+        pub fn on_timeout(&self) -> TimeoutAction {
+            self.details()
+                .and_then(|d| d.on_timeout)
+                .unwrap_or_default()
+        }
+
+        /// How long the command can be idle for, if it has a valid `idle_after`
+        pub fn idle_after(&self) -> Option<std::time::Duration> {
+            let idle_after = self.details().and_then(|d| d.idle_after.as_deref())?;
+            crate::recurring::parse_duration(idle_after).ok()
+        }
+
+        pub fn on_idle(&self) -> IdleAction {
+            self.details().and_then(|d| d.on_idle).unwrap_or_default()
+        }
+
+        pub fn every(&self) -> Option<&str> {
+            self.details().and_then(|d| d.every.as_deref())
+        }
+
+        pub fn schedule(&self) -> Option<&str> {
+            self.details().and_then(|d| d.schedule.as_deref())
+        }
+
+        pub fn wait_for(&self) -> Option<&WaitFor> {
+            self.details().and_then(|d| d.wait_for.as_ref())
+        }
+
+        pub fn output_rules(&self) -> &[OutputRule] {
+            self.details()
+                .and_then(|d| d.on_output.as_deref())
+                .unwrap_or_default()
+        }
+
+        /// Whether exiting with the given status counts as the command failing
+        pub fn is_failure(&self, status: i32) -> bool {
+            let Some(details) = self.details() else {
+                return status != 0;
+            };
+            if details.ignore_failure.unwrap_or(false) {
+                return false;
+            }
+            match &details.success_codes {
+                Some(codes) => !codes.contains(&status),
+                None => status != 0,
+            }
+        }
+
+        /// Hooks to run after the command exited with the given status
+        pub fn exit_hooks(&self, status: i32) -> impl Iterator<Item = &str> {
+            let details = self.details();
+            let on_exit = details.and_then(|d| d.on_exit.as_deref());
+            let on_failure = details
+                .filter(|_| self.is_failure(status))
+                .and_then(|d| d.on_failure.as_deref());
+            on_exit.into_iter().chain(on_failure)
+        }
+
+        pub fn opens_url(&self) -> bool {
+            self.details().and_then(|d| d.open).unwrap_or(false)
+        }
+
+        pub fn is_oneshot(&self) -> bool {
+            self.details().and_then(|d| d.oneshot).unwrap_or(false)
+        }
+
+        pub fn group(&self) -> Option<&str> {
+            self.details().and_then(|d| d.group.as_deref())
+        }
+
+        pub fn is_active(&self) -> bool {
+            self.details().and_then(|d| d.active).unwrap_or(false)
+        }
+
         pub fn matches(&self, other: &str) -> bool {
-            self.as_str() == other || self.alias().map_or(false, |a| a == other)
+            self.as_str() == other || self.alias() == Some(other)
         }
 
         pub fn recipes(&self) -> &[String] {
-            match self {
-                Self::Simple(_) => &[],
-                Self::Detailed { recipes, .. } => recipes.as_deref().unwrap_or(&[]),
-            }
+            self.details()
+                .and_then(|d| d.recipes.as_deref())
+                .unwrap_or(&[])
         }
 
         pub fn contains_recipe(&self, recipe: &str) -> bool {
             let recipe = recipe.trim();
-            match self {
-                Self::Simple(_) => false,
-                Self::Detailed { recipes, .. } => recipes
-                    .as_ref()
-                    .map_or(false, |r| r.iter().any(|x| x.eq_ignore_ascii_case(recipe))),
-            }
+            self.recipes()
+                .iter()
+                .any(|x| x.eq_ignore_ascii_case(recipe))
         }
     }
 
@@ -454,6 +1964,23 @@ pub mod commands {
         Alias(String),
     }
 
+    /// A startup or shutdown command, or a group of commands that run in parallel
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    #[serde(untagged)]
+    pub enum StartupEntry {
+        Command(CommandIndex),
+        Group(Vec<CommandIndex>),
+    }
+
+    impl StartupEntry {
+        pub fn commands(&self) -> &[CommandIndex] {
+            match self {
+                Self::Command(index) => std::slice::from_ref(index),
+                Self::Group(group) => group,
+            }
+        }
+    }
+
     impl CommandIndex {
         pub fn retrieve<'a>(&self, commands: &'a [CommandConfig]) -> Option<&'a CommandConfig> {
             match self {
@@ -478,3 +2005,188 @@ pub mod commands {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn details(value: serde_json::Value) -> commands::CommandDetails {
+        serde_json::from_value(value).unwrap()
+    }
+
+    fn commands(variants: &[commands::CommandDetails]) -> Vec<&str> {
+        variants.iter().map(|d| d.command.as_str()).collect()
+    }
+
+    fn aliases(variants: &[commands::CommandDetails]) -> Vec<&str> {
+        variants.iter().flat_map(|d| d.alias.as_deref()).collect()
+    }
+
+    /// A fresh directory with the given subdirectories, removed when dropped
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str, subdirs: &[&str]) -> Self {
+            let path =
+                std::env::temp_dir().join(format!("together-{}-{}", name, std::process::id()));
+            _ = std::fs::remove_dir_all(&path);
+            for subdir in subdirs {
+                std::fs::create_dir_all(path.join(subdir)).unwrap();
+            }
+            Self(path)
+        }
+
+        fn path(&self) -> Option<&str> {
+            self.0.to_str()
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[test]
+    fn expands_every_combination_of_a_matrix() {
+        let command = details(serde_json::json!({
+            "command": "run {svc} --env {env}",
+            "matrix": { "env": ["dev", "prod"], "svc": ["api", "web", "worker"] },
+        }));
+        let variants = expand_matrix(&command).unwrap();
+        assert_eq!(variants.len(), 6);
+        assert_eq!(
+            commands(&variants),
+            [
+                "run api --env dev",
+                "run api --env prod",
+                "run web --env dev",
+                "run web --env prod",
+                "run worker --env dev",
+                "run worker --env prod",
+            ]
+        );
+        // values are joined in the order their keys appear in the command
+        assert_eq!(aliases(&variants)[0], "api-dev");
+        assert!(variants.iter().all(|d| d.matrix.is_none()));
+        assert_eq!(
+            variants[0].recipes.as_deref(),
+            Some(&["api".to_string(), "dev".to_string()][..])
+        );
+    }
+
+    #[test]
+    fn fills_matrix_values_into_aliases() {
+        let filled = details(serde_json::json!({
+            "command": "run {svc}",
+            "alias": "svc-{svc}",
+            "matrix": { "svc": ["api", "web"] },
+        }));
+        assert_eq!(
+            aliases(&expand_matrix(&filled).unwrap()),
+            ["svc-api", "svc-web"]
+        );
+
+        let appended = details(serde_json::json!({
+            "command": "run {svc}",
+            "alias": "run",
+            "matrix": { "svc": ["api", "web"] },
+        }));
+        assert_eq!(
+            aliases(&expand_matrix(&appended).unwrap()),
+            ["run-api", "run-web"]
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_matrices() {
+        let missing_key = details(serde_json::json!({
+            "command": "run",
+            "matrix": { "svc": ["api"] },
+        }));
+        let no_values = details(serde_json::json!({
+            "command": "run {svc}",
+            "matrix": { "svc": [] },
+        }));
+        for command in [missing_key, no_values] {
+            match expand_matrix(&command) {
+                Err(TogetherError::Config(e)) => assert_eq!(e.key.as_deref(), Some("matrix")),
+                result => panic!("expected a matrix error, got {:?}", result.map(|v| v.len())),
+            }
+        }
+    }
+
+    #[test]
+    fn finds_comments_before_rewriting() {
+        assert!(has_comments("# dev services\ncommands:\n  - cargo run\n"));
+        assert!(has_comments("commands:\n  - cargo run # the api\n"));
+        assert!(!has_comments("commands:\n  - cargo run\n  - echo '#1'\n"));
+    }
+
+    #[test]
+    fn leaves_commands_without_a_matrix() {
+        let command = details(serde_json::json!({ "command": "run {svc}" }));
+        assert_eq!(expand_matrix(&command).unwrap(), [command]);
+    }
+
+    #[test]
+    fn expands_a_command_per_matching_dir() {
+        let root = TempDir::new(
+            "expand-dirs",
+            &["services/api", "services/web", "services/.cache", "docs"],
+        );
+        let command = details(serde_json::json!({
+            "command": "test {dir}",
+            "alias": "test-{dir}",
+            "dirs": ["services/*"],
+        }));
+        let variants = expand_dirs(root.path(), command).unwrap();
+        assert_eq!(commands(&variants), ["test api", "test web"]);
+        assert_eq!(aliases(&variants), ["test-api", "test-web"]);
+        let cwd = |d: &commands::CommandDetails| d.cwd.clone().map(PathBuf::from);
+        assert_eq!(cwd(&variants[0]), Some(Path::new("services").join("api")));
+        assert!(variants.iter().all(|d| d.dirs.is_none()));
+    }
+
+    #[test]
+    fn appends_dir_names_to_aliases_without_them() {
+        let root = TempDir::new("expand-dirs-alias", &["api", "web"]);
+        let command = details(serde_json::json!({
+            "command": "test {dir}",
+            "alias": "test",
+            "dirs": ["*"],
+        }));
+        let variants = expand_dirs(root.path(), command).unwrap();
+        assert_eq!(aliases(&variants), ["test-api", "test-web"]);
+    }
+
+    #[test]
+    fn tells_apart_commands_without_a_placeholder() {
+        let root = TempDir::new("expand-dirs-same", &["api", "web"]);
+        let command = details(serde_json::json!({ "command": "test", "dirs": ["*"] }));
+        let variants = expand_dirs(root.path(), command).unwrap();
+        assert_eq!(commands(&variants), ["test", "test"]);
+        let keys: Vec<_> = variants.iter().map(|d| d.key.as_deref()).collect();
+        assert_eq!(keys, [Some("test (api)"), Some("test (web)")]);
+
+        let aliased = details(serde_json::json!({
+            "command": "test",
+            "alias": "test",
+            "dirs": ["*"],
+        }));
+        let variants = expand_dirs(root.path(), aliased).unwrap();
+        let keys: Vec<_> = variants.iter().map(|d| d.key.as_deref()).collect();
+        assert_eq!(keys, [Some("test-api"), Some("test-web")]);
+    }
+
+    #[test]
+    fn rejects_invalid_dirs() {
+        let root = TempDir::new("expand-dirs-invalid", &["api"]);
+        let no_matches =
+            details(serde_json::json!({ "command": "test {dir}", "dirs": ["missing/*"] }));
+        match expand_dirs(root.path(), no_matches) {
+            Err(TogetherError::Config(e)) => assert_eq!(e.key.as_deref(), Some("dirs")),
+            result => panic!("expected a dirs error, got {:?}", result.map(|v| v.len())),
+        }
+    }
+}
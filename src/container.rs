@@ -0,0 +1,118 @@
+use std::{process::Command, thread::JoinHandle};
+
+use crate::{
+    log_err,
+    process::{ProcessId, ProcessSignal},
+    remote::{local_quote, posix_quote},
+};
+
+/// A docker container a command runs in
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(untagged)]
+pub enum Container {
+    /// An already running container, by name or id, that the command is run in with
+    /// `docker exec`
+    Exec(String),
+    /// An image that a new container is started from for each run, with `docker run`
+    Run {
+        image: String,
+        /// Extra options for `docker run`, e.g. `["-p", "5432:5432"]`
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        args: Vec<String>,
+    },
+}
+
+/// A command running in a container, which can be signalled through docker directly as
+/// signals sent to the docker CLI don't always reach the container
+#[derive(Debug, Clone)]
+pub struct RunningContainer {
+    container: Container,
+    /// The name of the container started by `docker run`, or the name of the pid file in
+    /// the container for `docker exec`
+    name: String,
+}
+
+impl RunningContainer {
+    pub fn new(container: Container, id: &ProcessId) -> Self {
+        Self {
+            container,
+            name: format!("together-{}-{}", std::process::id(), id.index()),
+        }
+    }
+
+    /// The local docker command that runs `command` in the container, in `cwd` in the
+    /// container when given. The names of `env` are passed on from the docker CLI.
+    pub fn command(&self, command: &str, cwd: Option<&str>, env: &[(String, String)]) -> String {
+        let mut docker = vec!["docker".to_string()];
+        let script = match &self.container {
+            Container::Exec(container) => {
+                docker.extend(["exec".to_string(), "-i".to_string()]);
+                docker.extend(options(cwd, env));
+                docker.push(local_quote(container));
+                // the pid is recorded so the command can be signalled from another exec
+                format!(
+                    "echo $$ > {} && exec sh -c {}",
+                    self.pid_file(),
+                    posix_quote(command)
+                )
+            }
+            Container::Run { image, args } => {
+                docker.extend(
+                    ["run", "--rm", "-i", "--init", "--sig-proxy=false", "--name"]
+                        .map(str::to_string),
+                );
+                docker.push(self.name.clone());
+                docker.extend(options(cwd, env));
+                docker.extend(args.iter().map(|arg| local_quote(arg)));
+                docker.push(local_quote(image));
+                command.to_string()
+            }
+        };
+        docker.extend(["sh".to_string(), "-c".to_string(), local_quote(&script)]);
+        docker.join(" ")
+    }
+
+    /// Sends the signal to the command in the container, from another thread
+    pub fn signal(&self, signal: Option<&ProcessSignal>) -> JoinHandle<()> {
+        let signal = match signal {
+            Some(ProcessSignal::SIGINT | ProcessSignal::SIGBREAK) => "INT",
+            Some(ProcessSignal::SIGKILL) => "KILL",
+            Some(ProcessSignal::SIGTERM) | None => "TERM",
+        };
+        let mut docker = Command::new("docker");
+        match &self.container {
+            Container::Exec(container) => {
+                let kill = format!("kill -s {} \"$(cat {})\"", signal, self.pid_file());
+                docker.args(["exec", container, "sh", "-c", &kill]);
+            }
+            Container::Run { .. } => {
+                docker.args(["kill", "--signal", signal, &self.name]);
+            }
+        }
+        docker
+            .stdout(std::process::Stdio::null())
+            .stderr(std::process::Stdio::null());
+        let name = self.name.clone();
+        std::thread::spawn(move || match docker.status() {
+            Ok(_) => {}
+            Err(e) => {
+                log_err!("Failed to signal container {}: {}", name, e);
+            }
+        })
+    }
+
+    fn pid_file(&self) -> String {
+        format!("/tmp/{}.pid", self.name)
+    }
+}
+
+fn options(cwd: Option<&str>, env: &[(String, String)]) -> Vec<String> {
+    let mut options = vec![];
+    if let Some(cwd) = cwd {
+        options.extend(["-w".to_string(), local_quote(cwd)]);
+    }
+    for (name, _) in env {
+        options.extend(["-e".to_string(), local_quote(name)]);
+    }
+    options
+}
@@ -0,0 +1,543 @@
+use std::{
+    io::{BufRead, BufReader, Read, Write},
+    net::{Ipv4Addr, TcpListener, TcpStream},
+    path::{Path, PathBuf},
+    sync::OnceLock,
+    time::Duration,
+};
+
+use crate::{
+    config::commands::CommandConfig,
+    errors::{ConfigError, ManagerError, TogetherInternalError, TogetherResult},
+    log, log_err,
+    manager::{ProcessManagerHandle, ProcessState, ProcessStatus},
+    process::{self, ProcessId},
+    report::ReportFormat,
+    t_println,
+    terminal::{CtlCommand, ReportCommand, StatusCommand, StatusFormat},
+};
+
+/// How long a connection has to send its request, or wait for the response
+const CONNECTION_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Longest request accepted, well above what any request needs
+const MAX_REQUEST_BYTES: u64 = 64 * 1024;
+
+/// Longest response read, enough for the report of a long session
+const MAX_RESPONSE_BYTES: u64 = 16 * 1024 * 1024;
+
+/// Instance file of this process, removed again when it exits
+static REGISTERED: OnceLock<PathBuf> = OnceLock::new();
+
+/// A running instance, as recorded in its instance file. The token has to be sent with every
+/// request, so only users able to read the file can control the instance.
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+struct Instance {
+    pid: u32,
+    port: u16,
+    token: String,
+    cwd: String,
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+#[serde(tag = "request", rename_all = "snake_case")]
+enum Request {
+    Status,
+    Report {
+        format: ReportFormat,
+    },
+    Ctl {
+        action: CtlAction,
+        targets: Vec<String>,
+    },
+}
+
+/// What `together ctl` does to the processes it's given
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CtlAction {
+    Kill,
+    Restart,
+}
+
+impl std::str::FromStr for CtlAction {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "kill" => Ok(Self::Kill),
+            "restart" => Ok(Self::Restart),
+            _ => Err(format!(
+                "unknown action '{}', expected 'kill' or 'restart'",
+                s
+            )),
+        }
+    }
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+struct Envelope {
+    token: String,
+    #[serde(flatten)]
+    request: Request,
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum Response {
+    Status(Vec<ProcessEntry>),
+    Report(String),
+    /// What was done to each process, for `together ctl`
+    Ctl(Vec<String>),
+    Error(String),
+}
+
+/// A process as listed by `together status`
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+struct ProcessEntry {
+    id: u32,
+    command: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    alias: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pid: Option<u32>,
+    state: String,
+    /// Seconds since the process started, or how long it ran for once exited
+    uptime: u64,
+    restarts: usize,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    crash_looping: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    exit_code: Option<i32>,
+}
+
+impl ProcessEntry {
+    fn new(status: ProcessStatus, commands: &[CommandConfig]) -> Self {
+        let command = commands.iter().find(|c| c.matches(status.id.command()));
+        let (state, exit_code) = match status.state {
+            ProcessState::Exited(code) => ("exited".to_string(), Some(code)),
+            state => (state.to_string(), None),
+        };
+        Self {
+            id: status.id.index(),
+            command: status.id.command().to_string(),
+            alias: command.and_then(|c| c.alias()).map(str::to_string),
+            pid: status.pid,
+            state,
+            uptime: status.uptime().as_secs(),
+            restarts: status.restarts,
+            crash_looping: status.crash_looping,
+            exit_code,
+        }
+    }
+}
+
+/// Listens for requests from other `together` invocations, such as `together status`, on a
+/// localhost port recorded in this instance's file
+pub fn serve(sender: ProcessManagerHandle, commands: Vec<CommandConfig>) {
+    let listener = match TcpListener::bind((Ipv4Addr::LOCALHOST, 0)) {
+        Ok(listener) => listener,
+        Err(e) => {
+            log_err!("Failed to open the control socket: {}", e);
+            return;
+        }
+    };
+    let instance = match listener.local_addr().and_then(|addr| Ok((addr, token()?))) {
+        Ok((addr, token)) => Instance {
+            pid: std::process::id(),
+            port: addr.port(),
+            token,
+            cwd: current_dir(),
+        },
+        Err(e) => {
+            log_err!("Failed to open the control socket: {}", e);
+            return;
+        }
+    };
+    if let Err(e) = register(&instance) {
+        log_err!("Failed to register with the control socket: {}", e);
+        return;
+    }
+    crate::debug!("control socket listening on port {}", instance.port);
+
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let Ok(stream) = stream else {
+                continue;
+            };
+            if let Err(e) = handle_connection(stream, &instance.token, &sender, &commands) {
+                crate::debug!("control connection failed: {}", e);
+            }
+        }
+    });
+}
+
+/// Removes the instance file of this process, so it is no longer found by other invocations
+pub fn unregister() {
+    if let Some(path) = REGISTERED.get() {
+        _ = std::fs::remove_file(path);
+    }
+}
+
+/// Prints the processes of a running instance, for `together status`
+pub fn status(command: &StatusCommand) -> TogetherResult<()> {
+    let instance = find_instance(command.pid)?;
+    let entries = match send(&instance, Request::Status)? {
+        Response::Status(entries) => entries,
+        Response::Error(e) => return Err(ManagerError::Remote(e).into()),
+        _ => return Err(TogetherInternalError::UnexpectedResponse.into()),
+    };
+    let output = match command.format {
+        StatusFormat::Json => serde_json::to_string_pretty(&entries)?,
+        StatusFormat::Yaml => serde_yml::to_string(&entries)?,
+    };
+    t_println!("{}", output.trim_end());
+    Ok(())
+}
+
+/// Prints or saves a report of how the commands of a running instance have run so far, for
+/// `together report`
+pub fn report(command: &ReportCommand) -> TogetherResult<()> {
+    let instance = find_instance(command.pid)?;
+    let format = command.format;
+    let report = match send(&instance, Request::Report { format })? {
+        Response::Report(report) => report,
+        Response::Error(e) => return Err(ManagerError::Remote(e).into()),
+        _ => return Err(TogetherInternalError::UnexpectedResponse.into()),
+    };
+    match &command.output {
+        Some(path) => {
+            std::fs::write(path, report)?;
+            log!("Wrote run report to {}", path.display());
+        }
+        None => {
+            t_println!("{}", report.trim_end());
+        }
+    }
+    Ok(())
+}
+
+/// Kills or restarts processes of a running instance by alias, for `together ctl`
+pub fn ctl(command: &CtlCommand) -> TogetherResult<()> {
+    let instance = find_instance(command.pid)?;
+    send_ctl(&instance, command.action, command.targets.clone())
+}
+
+/// Restarts the given commands in the instance already running in the current directory, for
+/// `--restart-on-start`. Returns false when there is none, so a new session is started instead.
+pub fn restart_on_start(targets: Option<&[String]>) -> TogetherResult<bool> {
+    let Some(targets) = targets.filter(|t| !t.is_empty()) else {
+        return Ok(false);
+    };
+    let cwd = current_dir();
+    let Some(instance) = running_instances().into_iter().find(|i| i.cwd == cwd) else {
+        return Ok(false);
+    };
+    log!(
+        "together is already running here (pid {}), restarting {} in it",
+        instance.pid,
+        targets.join(", ")
+    );
+    send_ctl(&instance, CtlAction::Restart, targets.to_vec())?;
+    Ok(true)
+}
+
+fn send_ctl(instance: &Instance, action: CtlAction, targets: Vec<String>) -> TogetherResult<()> {
+    match send(instance, Request::Ctl { action, targets })? {
+        Response::Ctl(done) => {
+            for message in done {
+                log!("{}", message);
+            }
+            Ok(())
+        }
+        Response::Error(e) => Err(ManagerError::Remote(e).into()),
+        _ => Err(TogetherInternalError::UnexpectedResponse.into()),
+    }
+}
+
+fn handle_connection(
+    stream: TcpStream,
+    token: &str,
+    sender: &ProcessManagerHandle,
+    commands: &[CommandConfig],
+) -> TogetherResult<()> {
+    stream.set_read_timeout(Some(CONNECTION_TIMEOUT))?;
+    let mut line = String::new();
+    BufReader::new((&stream).take(MAX_REQUEST_BYTES)).read_line(&mut line)?;
+    let response = match serde_json::from_str::<Envelope>(&line) {
+        Ok(envelope) if !tokens_match(&envelope.token, token) => {
+            Response::Error("Invalid token".to_string())
+        }
+        Ok(envelope) => {
+            crate::trace!("control request: {:?}", envelope.request);
+            respond(envelope.request, sender, commands)
+        }
+        Err(e) => Response::Error(format!("Invalid request: {}", e)),
+    };
+    let mut stream = stream;
+    serde_json::to_writer(&mut stream, &response)?;
+    stream.write_all(b"\n")?;
+    Ok(())
+}
+
+fn respond(
+    request: Request,
+    sender: &ProcessManagerHandle,
+    commands: &[CommandConfig],
+) -> Response {
+    match request {
+        Request::Status => match sender.status() {
+            Ok(statuses) => Response::Status(
+                statuses
+                    .into_iter()
+                    .map(|status| ProcessEntry::new(status, commands))
+                    .collect(),
+            ),
+            Err(e) => Response::Error(e.to_string()),
+        },
+        Request::Report { format } => match sender.report(format) {
+            Ok(report) => Response::Report(report),
+            Err(e) => Response::Error(e.to_string()),
+        },
+        Request::Ctl { action, targets } => match run_ctl(action, &targets, sender, commands) {
+            Ok(response) => response,
+            Err(e) => Response::Error(e.to_string()),
+        },
+    }
+}
+
+/// Kills or restarts every process matching the targets, or none of them if any target
+/// matches no running process
+fn run_ctl(
+    action: CtlAction,
+    targets: &[String],
+    sender: &ProcessManagerHandle,
+    commands: &[CommandConfig],
+) -> TogetherResult<Response> {
+    let running = sender.list()?;
+    let mut ids: Vec<ProcessId> = vec![];
+    for target in targets {
+        let matched: Vec<_> = running
+            .iter()
+            .filter(|id| matches_target(id, target, commands))
+            .collect();
+        if matched.is_empty() {
+            return Ok(Response::Error(format!(
+                "No running process matches '{}'",
+                target
+            )));
+        }
+        ids.extend(matched.into_iter().cloned());
+    }
+    ids.sort_by_key(|id| id.index());
+    ids.dedup();
+
+    let mut done = vec![];
+    for id in ids {
+        match action {
+            CtlAction::Kill => {
+                if sender.kill(id.clone())?.is_some() {
+                    done.push(format!("Killed {}", id));
+                }
+            }
+            CtlAction::Restart => {
+                if let Some(restarted) = sender.restart(id.clone())? {
+                    done.push(format!("Restarted {} as {}", id, restarted));
+                }
+            }
+        }
+    }
+    Ok(Response::Ctl(done))
+}
+
+/// A process matches its id, its command, or the alias of its command
+fn matches_target(id: &ProcessId, target: &str, commands: &[CommandConfig]) -> bool {
+    target.parse::<u32>().ok() == Some(id.index())
+        || commands
+            .iter()
+            .find(|c| c.as_str() == id.command())
+            .map_or(id.command() == target, |c| c.matches(target))
+}
+
+fn send(instance: &Instance, request: Request) -> TogetherResult<Response> {
+    let stream = TcpStream::connect((Ipv4Addr::LOCALHOST, instance.port))?;
+    stream.set_read_timeout(Some(CONNECTION_TIMEOUT))?;
+    let envelope = Envelope {
+        token: instance.token.clone(),
+        request,
+    };
+    let mut writer = &stream;
+    serde_json::to_writer(&mut writer, &envelope)?;
+    writer.write_all(b"\n")?;
+    let mut line = String::new();
+    BufReader::new((&stream).take(MAX_RESPONSE_BYTES)).read_line(&mut line)?;
+    Ok(serde_json::from_str(&line)?)
+}
+
+/// Compares tokens in time independent of where they differ, so it can't be guessed a
+/// character at a time
+fn tokens_match(given: &str, token: &str) -> bool {
+    let (given, token) = (given.as_bytes(), token.as_bytes());
+    let differences = given
+        .iter()
+        .zip(token)
+        .fold(0, |differences, (a, b)| differences | (a ^ b));
+    given.len() == token.len() && differences == 0
+}
+
+/// The instance with the given pid, or otherwise the only one running, preferring those
+/// started in the current directory
+fn find_instance(pid: Option<u32>) -> TogetherResult<Instance> {
+    let mut instances = running_instances();
+    if let Some(pid) = pid {
+        return instances
+            .into_iter()
+            .find(|instance| instance.pid == pid)
+            .ok_or_else(|| {
+                ConfigError::new(format!("No together instance with pid {}", pid)).into()
+            });
+    }
+    let cwd = current_dir();
+    if instances.iter().any(|instance| instance.cwd == cwd) {
+        instances.retain(|instance| instance.cwd == cwd);
+    }
+    match instances.len() {
+        0 => Err(ConfigError::new("No running together instance found").into()),
+        1 => Ok(instances.remove(0)),
+        _ => {
+            let listed: Vec<_> = instances
+                .iter()
+                .map(|instance| format!("  - {} in {}", instance.pid, instance.cwd))
+                .collect();
+            Err(ConfigError::new(format!(
+                "Several together instances are running, pick one with --pid:\n{}",
+                listed.join("\n")
+            ))
+            .into())
+        }
+    }
+}
+
+/// Instances whose control socket accepts connections, removing the files of any that exited
+/// without cleaning up
+fn running_instances() -> Vec<Instance> {
+    let Some(dir) = instances_dir() else {
+        return vec![];
+    };
+    let Ok(entries) = std::fs::read_dir(&dir) else {
+        return vec![];
+    };
+    let mut instances = vec![];
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path.extension().and_then(|e| e.to_str()) != Some("json") {
+            continue;
+        }
+        let instance = std::fs::read_to_string(&path)
+            .ok()
+            .and_then(|contents| serde_json::from_str::<Instance>(&contents).ok());
+        let Some(instance) = instance else {
+            continue;
+        };
+        // the port of an instance that exited could since have been taken by another program,
+        // which mustn't be sent the token
+        if !process::is_alive(instance.pid) {
+            _ = std::fs::remove_file(&path);
+            continue;
+        }
+        match TcpStream::connect((Ipv4Addr::LOCALHOST, instance.port)) {
+            Ok(_) => instances.push(instance),
+            Err(_) => {
+                _ = std::fs::remove_file(&path);
+            }
+        }
+    }
+    instances.sort_by_key(|instance| instance.pid);
+    instances
+}
+
+fn register(instance: &Instance) -> std::io::Result<()> {
+    let dir = instances_dir()
+        .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::NotFound, "no runtime directory"))?;
+    std::fs::create_dir_all(&dir)?;
+    let path = dir.join(format!("{}.json", instance.pid));
+    let mut file = create_private(&path)?;
+    serde_json::to_writer(&mut file, instance)?;
+    _ = REGISTERED.set(path);
+    Ok(())
+}
+
+/// Creates the file readable only by the current user, as it holds the token
+fn create_private(path: &Path) -> std::io::Result<std::fs::File> {
+    let mut options = std::fs::File::options();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    options.open(path)
+}
+
+/// `together/instances` in the user's runtime directory, e.g. `/run/user/1000`, or state
+/// directory where there is none
+fn instances_dir() -> Option<PathBuf> {
+    let dir = dirs::runtime_dir()
+        .or_else(dirs::state_dir)
+        .or_else(dirs::data_local_dir)?;
+    Some(dir.join("together").join("instances"))
+}
+
+fn current_dir() -> String {
+    std::env::current_dir()
+        .map(|dir| dir.display().to_string())
+        .unwrap_or_default()
+}
+
+/// A random token, from the random number generator of the operating system
+fn token() -> std::io::Result<String> {
+    let mut bytes = [0u8; 16];
+    random_bytes(&mut bytes)?;
+    Ok(bytes.iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+#[cfg(unix)]
+fn random_bytes(bytes: &mut [u8]) -> std::io::Result<()> {
+    std::fs::File::open("/dev/urandom")?.read_exact(bytes)
+}
+
+#[cfg(windows)]
+fn random_bytes(bytes: &mut [u8]) -> std::io::Result<()> {
+    use windows_sys::Win32::Security::Cryptography::{
+        BCryptGenRandom, BCRYPT_USE_SYSTEM_PREFERRED_RNG,
+    };
+    let status = unsafe {
+        BCryptGenRandom(
+            std::ptr::null_mut(),
+            bytes.as_mut_ptr(),
+            bytes.len() as u32,
+            BCRYPT_USE_SYSTEM_PREFERRED_RNG,
+        )
+    };
+    match status {
+        0 => Ok(()),
+        status => Err(std::io::Error::other(format!(
+            "BCryptGenRandom failed with status {:#x}",
+            status
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_only_identical_tokens() {
+        assert!(tokens_match("3f9a", "3f9a"));
+        assert!(!tokens_match("3f9b", "3f9a"));
+        assert!(!tokens_match("3f9", "3f9a"));
+        assert!(!tokens_match("3f9a0", "3f9a"));
+        assert!(!tokens_match("", "3f9a"));
+    }
+}
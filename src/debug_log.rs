@@ -0,0 +1,120 @@
+use std::{
+    fs::File,
+    io::Write,
+    path::{Path, PathBuf},
+    sync::{Mutex, OnceLock},
+    time::SystemTime,
+};
+
+use crate::{terminal::stdout::strip_ansi, timestamp};
+
+static LOG: OnceLock<DebugLog> = OnceLock::new();
+
+/// How much of what together does internally is written to the debug log
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    /// Decisions of the manager, such as spawning, signalling and exits, with `-v`
+    Debug = 1,
+    /// Every event handled by the manager, with `-vv`
+    Trace = 2,
+}
+
+/// A file that internal events are appended to, for finding out why together did something
+struct DebugLog {
+    level: u8,
+    file: Mutex<File>,
+}
+
+impl DebugLog {
+    fn append(&self, tag: &str, message: &str) {
+        let line = format!(
+            "{} {} {}\n",
+            timestamp::format(SystemTime::now()),
+            tag,
+            strip_ansi(message)
+        );
+        if let Ok(mut file) = self.file.lock() {
+            _ = file.write_all(line.as_bytes());
+        }
+    }
+}
+
+/// Starts writing to the debug log at `path`, or the default location, when `verbosity` is
+/// above zero or a path is given. Failing to open it is reported but otherwise ignored.
+pub fn init(verbosity: u8, path: Option<&Path>) {
+    if verbosity == 0 && path.is_none() {
+        return;
+    }
+    let Some(path) = path.map(Path::to_path_buf).or_else(default_path) else {
+        crate::log_err!("No directory to write the debug log to, use --debug-log instead");
+        return;
+    };
+    let file = path
+        .parent()
+        .map_or(Ok(()), std::fs::create_dir_all)
+        .and_then(|()| File::options().create(true).append(true).open(&path));
+    match file {
+        Ok(file) => {
+            crate::log!("Writing debug log to {}", path.display());
+            _ = LOG.set(DebugLog {
+                level: verbosity.max(1),
+                file: Mutex::new(file),
+            });
+            write(
+                Level::Debug,
+                format_args!("together {} started", env!("CARGO_PKG_VERSION")),
+            );
+        }
+        Err(e) => {
+            crate::log_err!("Failed to open debug log {}: {}", path.display(), e);
+        }
+    }
+}
+
+/// `together.log` in the user's state directory, e.g. `~/.local/state/together`
+fn default_path() -> Option<PathBuf> {
+    let dir = dirs::state_dir().or_else(dirs::data_local_dir)?;
+    Some(dir.join("together").join("together.log"))
+}
+
+pub fn enabled(level: Level) -> bool {
+    LOG.get().is_some_and(|log| log.level >= level as u8)
+}
+
+pub fn write(level: Level, args: std::fmt::Arguments) {
+    let Some(log) = LOG.get().filter(|log| log.level >= level as u8) else {
+        return;
+    };
+    let tag = match level {
+        Level::Debug => "DEBUG",
+        Level::Trace => "TRACE",
+    };
+    log.append(tag, &args.to_string());
+}
+
+/// Writes a line logged to the console, so the debug log has the full picture
+pub fn console(prefix: &str, message: &str) {
+    if let Some(log) = LOG.get() {
+        log.append(prefix, message);
+    }
+}
+
+/// macro for writing to the debug log with `-v`, like format!
+#[macro_export]
+macro_rules! debug {
+    ($($arg:tt)*) => {
+        if $crate::debug_log::enabled($crate::debug_log::Level::Debug) {
+            $crate::debug_log::write($crate::debug_log::Level::Debug, format_args!($($arg)*));
+        }
+    };
+}
+
+/// macro for writing to the debug log with `-vv`, like format!
+#[macro_export]
+macro_rules! trace {
+    ($($arg:tt)*) => {
+        if $crate::debug_log::enabled($crate::debug_log::Level::Trace) {
+            $crate::debug_log::write($crate::debug_log::Level::Trace, format_args!($($arg)*));
+        }
+    };
+}
@@ -1,4 +1,4 @@
-use std::sync::mpsc;
+use std::{path::PathBuf, sync::mpsc};
 
 pub type TogetherResult<T> = std::result::Result<T, TogetherError>;
 
@@ -6,19 +6,90 @@ pub type TogetherResult<T> = std::result::Result<T, TogetherError>;
 pub enum TogetherError {
     Io(std::io::Error),
     Yaml(serde_yml::Error),
+    Json(serde_json::Error),
     TomlSerialize(toml::ser::Error),
     TomlDeserialize(toml::de::Error),
     ChannelRecvError(mpsc::RecvError),
-    PopenErrorError(subprocess::PopenError),
     InternalError(TogetherInternalError),
-    DynError(Box<dyn std::error::Error>),
+    DynError(Box<dyn std::error::Error + Send + Sync>),
+    /// Invalid command line arguments or configuration, with a message for the user
+    Config(ConfigError),
+    /// A command couldn't be started
+    Spawn(SpawnError),
+    /// The process manager couldn't carry out an action
+    Manager(ManagerError),
+    /// Startup commands failed when running only the startup commands, with the commands
+    StartupFailed(Vec<String>),
+    /// No configuration file was found and no commands were given
+    NoConfigFile,
+    /// The user cancelled a prompt with Ctrl-C
+    Interrupted,
+}
+
+/// Invalid command line arguments or configuration, with where in the configuration file it is
+/// when known
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigError {
+    pub message: String,
+    /// The configuration file the error is in
+    pub path: Option<PathBuf>,
+    /// Line of the configuration file, starting at 1
+    pub line: Option<usize>,
+    /// The setting at fault, e.g. `timeout` or `crash_loop`
+    pub key: Option<String>,
+}
+
+impl ConfigError {
+    pub fn new(message: impl Into<String>) -> Self {
+        Self {
+            message: message.into(),
+            path: None,
+            line: None,
+            key: None,
+        }
+    }
+
+    pub fn with_path(mut self, path: impl Into<PathBuf>) -> Self {
+        self.path = Some(path.into());
+        self
+    }
+
+    pub fn with_line(mut self, line: usize) -> Self {
+        self.line = Some(line);
+        self
+    }
+
+    pub fn with_key(mut self, key: impl Into<String>) -> Self {
+        self.key = Some(key.into());
+        self
+    }
+}
+
+/// A command that couldn't be started, with why
+#[derive(Debug)]
+pub struct SpawnError {
+    pub command: String,
+    pub source: Box<TogetherError>,
+}
+
+/// An action the process manager couldn't carry out
+#[derive(Debug)]
+pub enum ManagerError {
+    SpawnFailed(SpawnError),
+    /// Killing a process failed, with the reason
+    KillFailed(String),
+    NoSuchProcess,
+    /// The process manager has stopped and no longer takes actions
+    Stopped,
+    /// An error reported by another together instance, e.g. for `together status`
+    Remote(String),
+    Unknown,
 }
 
 #[derive(Debug)]
 pub enum TogetherInternalError {
     ProcessFailedToExit,
     UnexpectedResponse,
-    InvalidConfigExtension,
 }
 
 impl std::fmt::Display for TogetherError {
@@ -27,20 +98,25 @@ impl std::fmt::Display for TogetherError {
         match self {
             TogetherError::Io(e) => write!(f, "IO error: {}", e),
             TogetherError::Yaml(e) => write!(f, "YAML error: {}", e),
+            TogetherError::Json(e) => write!(f, "JSON error: {}", e),
             TogetherError::TomlSerialize(e) => write!(f, "TOML serialization error: {}", e),
             TogetherError::TomlDeserialize(e) => write!(f, "TOML deserialization error: {}", e),
             TogetherError::ChannelRecvError(e) => write!(f, "Channel receive error: {}", e),
-            TogetherError::PopenErrorError(e) => write!(f, "Process error: {}", e),
             TogetherError::InternalError(TIE::ProcessFailedToExit) => {
                 write!(f, "Process failed to exit")
             }
             TogetherError::InternalError(TIE::UnexpectedResponse) => {
                 write!(f, "Unexpected response from process")
             }
-            TogetherError::InternalError(TIE::InvalidConfigExtension) => {
-                write!(f, "Invalid configuration file extension")
-            }
             TogetherError::DynError(e) => write!(f, "Error: {}", e),
+            TogetherError::Config(e) => write!(f, "{}", e),
+            TogetherError::Spawn(e) => write!(f, "{}", e),
+            TogetherError::Manager(e) => write!(f, "{}", e),
+            TogetherError::StartupFailed(commands) => {
+                write!(f, "{} startup command(s) failed", commands.len())
+            }
+            TogetherError::NoConfigFile => write!(f, "No configuration file found"),
+            TogetherError::Interrupted => write!(f, "Interrupted"),
         }
     }
 }
@@ -50,16 +126,126 @@ impl std::error::Error for TogetherError {
         match self {
             TogetherError::Io(e) => Some(e),
             TogetherError::Yaml(e) => Some(e),
+            TogetherError::Json(e) => Some(e),
             TogetherError::TomlSerialize(e) => Some(e),
             TogetherError::TomlDeserialize(e) => Some(e),
             TogetherError::ChannelRecvError(e) => Some(e),
-            TogetherError::PopenErrorError(e) => Some(e),
             TogetherError::InternalError(_) => None,
             TogetherError::DynError(e) => Some(e.as_ref()),
+            TogetherError::Spawn(e) => Some(e),
+            TogetherError::Manager(e) => Some(e),
+            TogetherError::Config(_)
+            | TogetherError::StartupFailed(_)
+            | TogetherError::NoConfigFile
+            | TogetherError::Interrupted => None,
+        }
+    }
+}
+
+impl TogetherError {
+    /// Points a configuration error at the file it came from, unless it already names one
+    pub fn in_file(self, path: Option<&std::path::Path>) -> Self {
+        match (self, path) {
+            (TogetherError::Config(e), Some(path)) if e.path.is_none() => {
+                let line = match (&e.line, &e.key) {
+                    (None, Some(key)) => std::fs::read_to_string(path)
+                        .ok()
+                        .and_then(|contents| key_line(&contents, key)),
+                    _ => None,
+                };
+                let e = e.with_path(path);
+                TogetherError::Config(match line {
+                    Some(line) => e.with_line(line),
+                    None => e,
+                })
+            }
+            (e, _) => e,
+        }
+    }
+}
+
+/// Finds the line of a YAML or TOML configuration file that sets `key`, if only one does, as
+/// a key set by several commands can't be told apart
+fn key_line(contents: &str, key: &str) -> Option<usize> {
+    let mut lines = contents.lines().enumerate().filter(|(_, line)| {
+        let line = line.trim_start().trim_start_matches("- ");
+        line.strip_prefix(key)
+            .is_some_and(|rest| rest.trim_start().starts_with([':', '=']))
+    });
+    match (lines.next(), lines.next()) {
+        (Some((index, _)), None) => Some(index + 1),
+        _ => None,
+    }
+}
+
+impl std::fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match (&self.path, self.line) {
+            (Some(path), Some(line)) => write!(f, "{}:{}: ", path.display(), line)?,
+            (Some(path), None) => write!(f, "{}: ", path.display())?,
+            _ => {}
+        }
+        if let Some(key) = &self.key {
+            write!(f, "{}: ", key)?;
+        }
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl std::fmt::Display for SpawnError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Failed to start '{}': {}", self.command, self.source)
+    }
+}
+
+impl std::error::Error for SpawnError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(self.source.as_ref())
+    }
+}
+
+impl std::fmt::Display for ManagerError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ManagerError::SpawnFailed(e) => write!(f, "{}", e),
+            ManagerError::KillFailed(reason) => write!(f, "Failed to kill process: {}", reason),
+            ManagerError::NoSuchProcess => write!(f, "No such process"),
+            ManagerError::Stopped => write!(f, "The process manager has stopped"),
+            ManagerError::Remote(message) => write!(f, "{}", message),
+            ManagerError::Unknown => write!(f, "Unknown error"),
         }
     }
 }
 
+impl std::error::Error for ManagerError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ManagerError::SpawnFailed(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<ConfigError> for TogetherError {
+    fn from(e: ConfigError) -> Self {
+        TogetherError::Config(e)
+    }
+}
+
+impl From<SpawnError> for TogetherError {
+    fn from(e: SpawnError) -> Self {
+        TogetherError::Spawn(e)
+    }
+}
+
+impl From<ManagerError> for TogetherError {
+    fn from(e: ManagerError) -> Self {
+        TogetherError::Manager(e)
+    }
+}
+
 impl From<std::io::Error> for TogetherError {
     fn from(e: std::io::Error) -> Self {
         TogetherError::Io(e)
@@ -72,6 +258,12 @@ impl From<serde_yml::Error> for TogetherError {
     }
 }
 
+impl From<serde_json::Error> for TogetherError {
+    fn from(e: serde_json::Error) -> Self {
+        TogetherError::Json(e)
+    }
+}
+
 impl From<toml::ser::Error> for TogetherError {
     fn from(e: toml::ser::Error) -> Self {
         TogetherError::TomlSerialize(e)
@@ -90,20 +282,14 @@ impl From<mpsc::RecvError> for TogetherError {
     }
 }
 
-impl From<subprocess::PopenError> for TogetherError {
-    fn from(e: subprocess::PopenError) -> Self {
-        TogetherError::PopenErrorError(e)
-    }
-}
-
 impl From<TogetherInternalError> for TogetherError {
     fn from(e: TogetherInternalError) -> Self {
         TogetherError::InternalError(e)
     }
 }
 
-impl From<Box<dyn std::error::Error>> for TogetherError {
-    fn from(e: Box<dyn std::error::Error>) -> Self {
+impl From<Box<dyn std::error::Error + Send + Sync>> for TogetherError {
+    fn from(e: Box<dyn std::error::Error + Send + Sync>) -> Self {
         TogetherError::DynError(e)
     }
 }
@@ -1,20 +1,43 @@
-use std::ops::ControlFlow;
+use std::{
+    collections::{BTreeMap, HashMap},
+    ops::ControlFlow,
+};
 
 use crate::{
-    config::{self, StartTogetherOptions},
+    config::{self, commands::ConfirmPolicy, StartTogetherOptions},
     errors::TogetherResult,
     log, log_err,
     manager::{self, ProcessAction},
+    output::OutputFilter,
     process, t_println,
-    terminal::Terminal,
+    terminal::{self, Terminal},
     terminal_ext::TerminalExt,
+    theme,
 };
 
+/// Number of lines shown by the tail action when no count is entered
+const TAIL_LINES: usize = 20;
+
 #[derive(Default)]
 struct InputState {
     requested_quit: bool,
     awaiting_quit_command: bool,
     last_command: Option<BufferedCommand>,
+    focused: bool,
+    bindings: KeyBindings,
+    /// Lines entered in command mode, oldest first
+    #[cfg(feature = "termion")]
+    history: Vec<String>,
+}
+
+impl InputState {
+    fn new(start_opts: &StartTogetherOptions) -> Self {
+        let bindings = &start_opts.config.start_options.keybindings;
+        Self {
+            bindings: KeyBindings::new(bindings).unwrap_or_default(),
+            ..Default::default()
+        }
+    }
 }
 
 enum BufferedCommand {
@@ -26,6 +49,78 @@ enum Key {
     #[cfg(feature = "termion")]
     CtrlC,
     Char(char),
+    /// The default key of an action that has been bound to another key
+    Unbound(char),
+}
+
+/// Actions that can be bound to other keys in the `keybindings` configuration, with their
+/// default keys
+const ACTIONS: &[(&str, char)] = &[
+    ("trigger", 't'),
+    ("repeat", '.'),
+    ("batch", 'b'),
+    ("switch_recipe", 'z'),
+    ("kill_recipe", 'B'),
+    ("kill", 'k'),
+    ("kill_signal", 'K'),
+    ("restart", 'r'),
+    ("restart_all", 'R'),
+    ("list", 'l'),
+    ("focus", 'f'),
+    ("grep", 'g'),
+    ("tail", 'T'),
+    ("copy", 'y'),
+    ("attach", 'a'),
+    ("broadcast", 'i'),
+    ("open", 'o'),
+    ("dump", 'd'),
+    ("command", ':'),
+    ("help", 'h'),
+    ("quit", 'q'),
+];
+
+/// Keys bound to actions in the configuration, mapped to the default keys of the actions
+#[derive(Debug, Clone, Default)]
+pub struct KeyBindings {
+    keys: HashMap<char, char>,
+}
+
+impl KeyBindings {
+    pub fn new(bindings: &BTreeMap<String, char>) -> Result<Self, String> {
+        let mut keys = HashMap::new();
+        for (action, key) in bindings {
+            let Some((_, default)) = ACTIONS.iter().find(|(name, _)| name == action) else {
+                let actions: Vec<_> = ACTIONS.iter().map(|(name, _)| *name).collect();
+                return Err(format!(
+                    "unknown action '{}', expected one of: {}",
+                    action,
+                    actions.join(", ")
+                ));
+            };
+            if keys.insert(*key, *default).is_some() {
+                return Err(format!("'{}' is bound to more than one action", key));
+            }
+        }
+        Ok(Self { keys })
+    }
+
+    /// The key of the action with the given default key
+    fn key(&self, default: char) -> char {
+        let bound = self.keys.iter().find(|(_, d)| **d == default);
+        bound.map_or(default, |(key, _)| *key)
+    }
+
+    /// Translates a pressed key to the default key of its action
+    fn translate(&self, key: Key) -> Key {
+        match key {
+            Key::Char(c) => match self.keys.get(&c) {
+                Some(default) => Key::Char(*default),
+                None if self.key(c) != c => Key::Unbound(c),
+                None => Key::Char(c),
+            },
+            key => key,
+        }
+    }
 }
 
 #[cfg(feature = "termion")]
@@ -56,7 +151,7 @@ pub fn block_for_user_input(
     // use termion::event::Key;
     use termion::input::TermRead;
 
-    let mut state = InputState::default();
+    let mut state = InputState::new(start_opts);
 
     // let mut stdout = std::io::stdout().into_raw_mode().unwrap();
     let mut stdout = std::io::stdout();
@@ -67,6 +162,7 @@ pub fn block_for_user_input(
             continue;
         };
 
+        let k = state.bindings.translate(k);
         match handle_key_press(k, &mut state, start_opts, &sender) {
             Ok(ControlFlow::Continue(_)) => {
                 write!(stdout, "{}", termion::cursor::Show).unwrap();
@@ -79,7 +175,6 @@ pub fn block_for_user_input(
         }
     }
 
-    drop(stdout);
     Ok(())
 }
 
@@ -88,27 +183,198 @@ pub fn block_for_user_input(
     start_opts: &StartTogetherOptions,
     sender: manager::ProcessManagerHandle,
 ) -> TogetherResult<()> {
-    let mut state = InputState::default();
+    let mut state = InputState::new(start_opts);
     let mut input = String::new();
+    log!("Type 'help' for a list of text commands");
     loop {
-        std::io::stdin().read_line(&mut input)?;
-        let Some(key) = input.trim().chars().next() else {
-            continue;
+        if std::io::stdin().read_line(&mut input)? == 0 {
+            log!("Input closed, waiting for all commands to complete...");
+            for id in sender.list()? {
+                sender.wait(id)?;
+            }
+            break;
+        }
+        // lines may start with ':' as in command mode
+        let line = input
+            .trim()
+            .trim_start_matches(':')
+            .trim_start()
+            .to_string();
+        input.clear();
+        let mut chars = line.chars();
+        let result = match (chars.next(), chars.next()) {
+            (None, _) => continue,
+            (Some(key), None) => {
+                let key = state.bindings.translate(key.into());
+                handle_key_press(key, &mut state, start_opts, &sender)
+            }
+            _ => handle_text_command(&line, &mut state, start_opts, &sender),
         };
 
-        match handle_key_press(key.into(), &mut state, start_opts, &sender) {
+        match result {
             Ok(ControlFlow::Continue(_)) => {}
             Ok(ControlFlow::Break(_)) => break,
             Err(e) => {
                 log_err!("Unexpected error: {:?}", e);
             }
         }
-
-        input.clear();
     }
     Ok(())
 }
 
+/// Handles a line based command such as `kill api` or `restart 3`, entered in command mode or
+/// on terminals without raw input
+fn handle_text_command(
+    line: &str,
+    state: &mut InputState,
+    start_opts: &StartTogetherOptions,
+    sender: &manager::ProcessManagerHandle,
+) -> TogetherResult<ControlFlow<()>> {
+    let start_options = &start_opts.config.start_options;
+    let (command, args) = line.split_once(' ').unwrap_or((line, ""));
+    let args = args.trim();
+
+    match (command, args) {
+        ("help", _) => {
+            log!("[text commands]");
+            t_println!("  start <command>      start a command by alias or command");
+            t_println!("  spawn <command>      start any command, even if not configured");
+            t_println!("  kill <process>       kill a running command");
+            t_println!("  restart <process>    restart a running command");
+            t_println!("  restart-all          restart all running commands");
+            t_println!("  tail <process> [n]   show the recent output of a command");
+            t_println!(
+                "  copy <process> [n|error]  copy recent output or the last error to the clipboard"
+            );
+            t_println!("  recipe <recipe>      start all commands of a recipe");
+            t_println!("  kill-recipe <recipe> kill all running commands of a recipe");
+            t_println!("  broadcast <input>    send a line of input to all running commands");
+            t_println!("  list                 list all commands with their state and uptime");
+            t_println!("  quit                 stop all commands and quit together");
+            t_println!("A <process> is a process index, alias or command, e.g. `restart 3`.");
+            #[cfg(not(feature = "termion"))]
+            t_println!("Single letter key commands are also accepted, see 'h'.");
+        }
+        ("list" | "ls", "") => {
+            return handle_key_press(Key::Char('l'), state, start_opts, sender);
+        }
+        ("quit" | "exit", "") => {
+            log!("Quitting together...");
+            crate::save_session(start_opts, sender);
+            sender.send(ProcessAction::KillAll)?;
+            return Ok(ControlFlow::Break(()));
+        }
+        ("start" | "run", command) if !command.is_empty() => {
+            let Some(command) = start_options.find_command(command) else {
+                log_err!("Unknown command: '{}'", command);
+                return Ok(ControlFlow::Continue(()));
+            };
+            let command = command.as_str();
+            config::warn_deprecated_commands(start_options, &[command]);
+            sender.spawn(command)?;
+            state.last_command = Some(BufferedCommand::Start(command.to_string()));
+        }
+        ("spawn", command) if !command.is_empty() => {
+            let command = start_options
+                .find_command(command)
+                .map_or(command, |c| c.as_str());
+            sender.spawn(command)?;
+            state.last_command = Some(BufferedCommand::Start(command.to_string()));
+        }
+        ("kill", target) if !target.is_empty() => {
+            if let Some(id) = find_process(start_opts, &sender.list()?, target) {
+                sender.kill(id)?;
+            }
+        }
+        ("restart", target) if !target.is_empty() => {
+            if let Some(id) = find_process(start_opts, &sender.list()?, target) {
+                let command = id.command().to_string();
+                if let Some(id) = sender.restart(id)? {
+                    state.last_command = Some(BufferedCommand::Restart(command, id));
+                }
+            }
+        }
+        ("restart-all", "") => {
+            return handle_key_press(Key::Char('R'), state, start_opts, sender);
+        }
+        ("tail", args) if !args.is_empty() => {
+            let (target, lines) = match args.rsplit_once(' ') {
+                Some((target, lines)) if lines.parse::<usize>().is_ok() => {
+                    (target.trim(), lines.parse().unwrap())
+                }
+                _ => (args, TAIL_LINES),
+            };
+            let ids: Vec<_> = sender.status()?.into_iter().map(|s| s.id).collect();
+            if let Some(id) = find_process(start_opts, &ids, target) {
+                for line in sender.tail(id, lines)?.unwrap_or_default() {
+                    t_println!("{}", line.trim_end_matches(['\r', '\n']));
+                }
+            }
+        }
+        ("copy", args) if !args.is_empty() => {
+            let (target, selection) = match args.rsplit_once(' ') {
+                Some((target, selection)) if parse_copy_selection(selection).is_some() => {
+                    (target.trim(), parse_copy_selection(selection))
+                }
+                _ => (args, Some(CopySelection::Lines(TAIL_LINES))),
+            };
+            let ids: Vec<_> = sender.status()?.into_iter().map(|s| s.id).collect();
+            if let (Some(id), Some(selection)) = (find_process(start_opts, &ids, target), selection)
+            {
+                copy_output(sender, &id, selection)?;
+            }
+        }
+        ("recipe", recipe) if !recipe.is_empty() => {
+            config::warn_deprecated_recipes(start_options, &[recipe]);
+            let commands = config::collect_commands_by_recipes(start_options, &[recipe]);
+            if commands.is_empty() {
+                log_err!("No commands found for recipe '{}'", recipe);
+            }
+            config::warn_deprecated_commands(start_options, &commands);
+            for command in commands {
+                sender.spawn(&command)?;
+            }
+        }
+        ("kill-recipe", recipe) if !recipe.is_empty() => {
+            let killed = sender.kill_by_recipe(start_options, recipe)?;
+            log!("Killed {} commands from recipe '{}'", killed.len(), recipe);
+        }
+        ("broadcast", input) if !input.is_empty() => {
+            let list = sender.list()?;
+            let sent = broadcast_input(sender, &list, input)?;
+            log!("Sent input to {} commands", sent.len());
+        }
+        _ => {
+            log_err!("Unknown command: '{}'", line);
+            log!("Type 'help' for a list of text commands");
+        }
+    }
+
+    Ok(ControlFlow::Continue(()))
+}
+
+/// Finds a process by its index, the alias of its command or the command itself
+fn find_process(
+    start_opts: &StartTogetherOptions,
+    list: &[process::ProcessId],
+    target: &str,
+) -> Option<process::ProcessId> {
+    let index = target.parse::<u32>().ok();
+    let found = list.iter().rev().find(|id| {
+        Some(id.index()) == index
+            || id.command() == target
+            || start_opts
+                .config
+                .start_options
+                .find_command(id.command())
+                .is_some_and(|c| c.alias() == Some(target))
+    });
+    if found.is_none() {
+        log_err!("No process found matching '{}'", target);
+    }
+    found.cloned()
+}
+
 fn handle_key_press(
     key: Key,
     state: &mut InputState,
@@ -124,17 +390,22 @@ fn handle_key_press(
         #[cfg(feature = "termion")]
         Key::CtrlC => {
             log!("Ctrl-C pressed, stopping all processes...");
+            crate::save_session(start_opts, sender);
             sender
                 .send(ProcessAction::KillAll)
                 .expect("Could not send signal on channel.");
         }
         Key::Char('h') | Key::Char('?') => {
+            let key = |default| state.bindings.key(default);
             log!("[help]");
             t_println!("together is a tool to run multiple commands in parallel selectively by an interactive prompt.");
 
             t_println!();
-            t_println!("Press 't' to trigger a one-time run");
-            t_println!("Press '.' to re-trigger the last one-time run or restart action");
+            t_println!("Press '{}' to trigger a one-time run", key('t'));
+            t_println!(
+                "Press '{}' to re-trigger the last one-time run or restart action",
+                key('.')
+            );
             if let Some(last) = &state.last_command {
                 t_println!(
                     "  (last command: [{}] {})",
@@ -148,14 +419,54 @@ fn handle_key_press(
                     }
                 );
             }
-            t_println!("Press 'b' to batch trigger commands by recipe");
-            t_println!("Press 'z' to switch to running a single recipe");
-            t_println!("Press 'k' to kill a running command");
-            t_println!("Press 'r' to restart a running command");
-            t_println!("Press 'l' to list all running commands");
-            t_println!("Press 'd' to dump the current configuration");
-            t_println!("Press 'h' or '?' to show this help message");
-            t_println!("Press 'q' to stop");
+            t_println!("Press '{}' to batch trigger commands by recipe", key('b'));
+            t_println!("Press '{}' to switch to running a single recipe", key('z'));
+            t_println!(
+                "Press '{}' to kill all running commands of a recipe",
+                key('B')
+            );
+            t_println!("Press '{}' to kill a running command", key('k'));
+            t_println!("Press '{}' to restart a running command", key('r'));
+            t_println!("Press '{}' to restart all running commands", key('R'));
+            t_println!(
+                "Press '{}' to list all commands with their state, uptime, CPU and memory usage",
+                key('l')
+            );
+            t_println!(
+                "Press '{}' to only show output from selected commands, or to show all output again",
+                key('f')
+            );
+            t_println!(
+                "Press '{}' to filter or highlight output by a regular expression",
+                key('g')
+            );
+            t_println!(
+                "Press '{}' to show the recent output of a command, even if it has exited",
+                key('T')
+            );
+            t_println!(
+                "Press '{}' to copy the recent output or last error of a command to the clipboard",
+                key('y')
+            );
+            t_println!(
+                "Press '{}' to attach to a running command, giving it exclusive use of the terminal",
+                key('a')
+            );
+            t_println!(
+                "Press '{}' to send typed lines to the input of several running commands at once",
+                key('i')
+            );
+            t_println!(
+                "Press '{}' to open the URL of a running command in the browser",
+                key('o')
+            );
+            t_println!("Press '{}' to dump the current configuration", key('d'));
+            t_println!(
+                "Press '{}' to enter a text command such as 'kill api' or 'recipe backend', 'help' lists them",
+                key(':')
+            );
+            t_println!("Press '{}' or '?' to show this help message", key('h'));
+            t_println!("Press '{}' to stop", key('q'));
             t_println!();
 
             t_println!();
@@ -164,7 +475,7 @@ fn handle_key_press(
                 Ok(list) => {
                     t_println!("together is running {} commands in parallel:", list.len());
                     for command in list {
-                        t_println!("  {}", command);
+                        t_println!("  {}", format_process(start_opts, &command));
                     }
                 }
                 Err(_) => {
@@ -173,89 +484,311 @@ fn handle_key_press(
             }
         }
         Key::Char('q') => {
-            if state.awaiting_quit_command {
+            let policy = start_opts.config.start_options.confirm;
+            if state.awaiting_quit_command || policy == Some(ConfirmPolicy::Never) {
                 log!("Quitting together...");
+                crate::save_session(start_opts, sender);
                 sender.send(ProcessAction::KillAll)?;
                 return Ok(ControlFlow::Break(()));
             }
 
-            log!("Press 'q' again to quit together");
+            log!("Press '{}' again to quit together", state.bindings.key('q'));
             state.requested_quit = true;
-            return Ok(ControlFlow::Break(()));
+            return Ok(ControlFlow::Continue(()));
         }
         Key::Char('l') => {
-            for command in sender.list()? {
-                t_println!("{}", command);
+            let statuses = sender.status()?;
+            let start_options = &start_opts.config.start_options;
+            let groups = start_options.groups();
+            if groups.is_empty() {
+                for status in &statuses {
+                    t_println!("{}", format_status(start_opts, status));
+                }
+                return Ok(ControlFlow::Continue(()));
+            }
+            // commands without a group are listed last
+            for group in groups.into_iter().map(Some).chain([None]) {
+                let members: Vec<_> = statuses
+                    .iter()
+                    .filter(|s| start_options.group_of(s.id.command()) == group)
+                    .collect();
+                if members.is_empty() {
+                    continue;
+                }
+                match group {
+                    Some(group) => t_println!("{}", format_group(start_opts, group)),
+                    None => t_println!("{}", theme::muted("ungrouped")),
+                }
+                for status in members {
+                    t_println!("  {}", format_status(start_opts, status));
+                }
+            }
+        }
+        Key::Char('f') if state.focused => {
+            let hidden = sender.unfocus()?;
+            state.focused = false;
+            log!("Showing output from all commands");
+            for (id, lines) in hidden {
+                log!("  {} lines hidden from {}", lines, id);
+            }
+        }
+        Key::Char('f') => {
+            let list = sender.list()?;
+            let commands = Terminal::select_multiple_processes(
+                "Select commands to show output from, or press 'q' to cancel",
+                sender,
+                &list,
+            )?;
+            if !commands.is_empty() {
+                log!(
+                    "Showing output from {} commands (press 'f' to show all)",
+                    commands.len()
+                );
+                sender.focus(commands.into_iter().cloned().collect())?;
+                state.focused = true;
+            }
+        }
+        Key::Char('g') => {
+            if let Some(filter) = prompt_output_filter()? {
+                match &filter {
+                    Some(filter) => {
+                        log!("Applying output filter: {}", filter);
+                    }
+                    None => {
+                        log!("Cleared output filter");
+                    }
+                }
+                sender.filter(filter)?;
+            }
+        }
+        Key::Char('T') => {
+            let ids: Vec<_> = sender.status()?.into_iter().map(|s| s.id).collect();
+            let command = Terminal::select_single_process(
+                "Pick command to show recent output from, or press 'q' to cancel",
+                sender,
+                &ids,
+            )?;
+            if let Some(command) = command {
+                let prompt = format!("Number of lines (default {})", TAIL_LINES);
+                let lines = match Terminal::input(&prompt)?.map(|n| n.parse()) {
+                    Some(Ok(lines)) => lines,
+                    Some(Err(e)) => {
+                        log_err!("Invalid number of lines: {}", e);
+                        return Ok(ControlFlow::Continue(()));
+                    }
+                    None => TAIL_LINES,
+                };
+                match sender.tail(command.clone(), lines)? {
+                    Some(lines) => {
+                        log!("Last {} lines from {}", lines.len(), command);
+                        for line in lines {
+                            t_println!("{}", line.trim_end_matches(['\r', '\n']));
+                        }
+                    }
+                    None => {
+                        log!("No output recorded for {}", command);
+                    }
+                }
+            }
+        }
+        Key::Char('y') => {
+            let ids: Vec<_> = sender.status()?.into_iter().map(|s| s.id).collect();
+            let command = Terminal::select_single_process(
+                "Pick command to copy output from, or press 'q' to cancel",
+                sender,
+                &ids,
+            )?;
+            if let Some(command) = command {
+                let prompt = format!(
+                    "Number of lines (default {}), or 'e' for the last error",
+                    TAIL_LINES
+                );
+                let selection = match Terminal::input(&prompt)? {
+                    Some(input) => parse_copy_selection(&input),
+                    None => Some(CopySelection::Lines(TAIL_LINES)),
+                };
+                match selection {
+                    Some(selection) => copy_output(sender, command, selection)?,
+                    None => {
+                        log_err!("Expected a number of lines or 'e'");
+                    }
+                }
+            }
+        }
+        Key::Char('a') => {
+            let list = sender.list()?;
+            let command = Terminal::select_single_process(
+                "Pick command to attach to, or press 'q' to cancel",
+                sender,
+                &list,
+            )?;
+            if let Some(command) = command {
+                attach_to_process(command, sender)?;
+            }
+        }
+        Key::Char('i') => {
+            let list = sender.list()?;
+            let commands = Terminal::select_multiple_processes(
+                "Select commands to send input to, or press 'q' to cancel",
+                sender,
+                &list,
+            )?;
+            let mut commands: Vec<_> = commands.into_iter().cloned().collect();
+            if !commands.is_empty() {
+                log!(
+                    "Sending input to {} commands (enter an empty line to stop)",
+                    commands.len()
+                );
+            }
+            while !commands.is_empty() {
+                let prompt = format!("Input for {} commands", commands.len());
+                let Some(input) = Terminal::input(&prompt)? else {
+                    break;
+                };
+                commands = broadcast_input(sender, &commands, &input)?;
+            }
+        }
+        Key::Char('o') => {
+            let list = sender.list()?;
+            let list: Vec<_> = list
+                .into_iter()
+                .filter(|c| command_url(start_opts, c).is_some())
+                .collect();
+            if list.is_empty() {
+                log!("No running commands have a configured URL");
+            } else {
+                let command = Terminal::select_single_process(
+                    "Pick command to open in the browser, or press 'q' to cancel",
+                    sender,
+                    &list,
+                )?;
+                if let Some(url) = command.and_then(|c| command_url(start_opts, c)) {
+                    log!("Opening {}", url);
+                    process::open_url(url)?;
+                }
             }
         }
         Key::Char('d') => {
             let list = sender.list()?;
             let running: Vec<_> = list.iter().map(|c| c.command()).collect();
-            let config = start_opts.config.clone();
-            let config = config.with_running(&running);
-            config::dump(&config)?;
+            config::dump(&start_opts.session(&running))?;
         }
         Key::Char('k') => {
             let list = sender.list()?;
-            let command = Terminal::select_single_process(
+            let target = select_process_or_group(
                 "Pick command to kill, or press 'q' to cancel",
-                &sender,
+                start_opts,
+                sender,
                 &list,
             )?;
-            if let Some(command) = command {
-                sender.kill(command.clone())?;
+            match target {
+                Some(Target::Process(command)) => {
+                    let prompt = format!("Kill {}?", command);
+                    if confirm(start_opts, ConfirmPolicy::Destructive, &prompt)? {
+                        sender.kill(command.clone())?;
+                    }
+                }
+                Some(Target::Group(group, commands)) => {
+                    let prompt = format!("Kill all {} commands of '{}'?", commands.len(), group);
+                    if confirm(start_opts, ConfirmPolicy::Destructive, &prompt)? {
+                        for command in commands {
+                            sender.kill(command.clone())?;
+                        }
+                    }
+                }
+                None => {}
             }
         }
         Key::Char('K') => {
             let list = sender.list()?;
             let command = Terminal::select_single_process(
                 "Pick command to kill, or press 'q' to cancel",
-                &sender,
+                sender,
                 &list,
             )?;
-            let signal = command.and_then(|_| {
-                Terminal::select_single(
+            #[cfg(not(windows))]
+            let signals = ["SIGINT", "SIGTERM", "SIGKILL"];
+            // graceful signals are all delivered as CTRL_BREAK_EVENT on windows
+            #[cfg(windows)]
+            let signals = ["SIGBREAK", "SIGKILL"];
+            let signal = match command {
+                Some(_) => Terminal::select_single(
                     "Pick signal to send, or press 'q' to cancel",
-                    &["SIGINT", "SIGTERM", "SIGKILL"],
-                )
-            });
+                    &signals,
+                )?,
+                None => None,
+            };
             let target = signal
                 .and_then(|signal| match *signal {
                     "SIGINT" => Some(process::ProcessSignal::SIGINT),
                     "SIGTERM" => Some(process::ProcessSignal::SIGTERM),
                     "SIGKILL" => Some(process::ProcessSignal::SIGKILL),
+                    "SIGBREAK" => Some(process::ProcessSignal::SIGBREAK),
                     _ => None,
                 })
                 .and_then(|signal| command.map(|command| (command, signal)));
             if let Some((command, signal)) = target {
+                let prompt = format!("Send {:?} to {}?", signal, command);
+                if !confirm(start_opts, ConfirmPolicy::Destructive, &prompt)? {
+                    return Ok(ControlFlow::Continue(()));
+                }
                 sender.send(ProcessAction::KillAdvanced(command.clone(), signal))?;
             }
         }
         Key::Char('r') => {
             let list = sender.list()?;
-            let command = Terminal::select_single_process(
+            let target = select_process_or_group(
                 "Pick command to restart, or press 'q' to cancel",
-                &sender,
+                start_opts,
+                sender,
                 &list,
             )?;
+            let command = match target {
+                Some(Target::Process(command)) => Some(command),
+                Some(Target::Group(group, commands)) => {
+                    let prompt = format!("Restart all {} commands of '{}'?", commands.len(), group);
+                    if confirm(start_opts, ConfirmPolicy::Always, &prompt)? {
+                        for command in commands {
+                            sender.restart(command.clone())?;
+                        }
+                        log!("Restarted group '{}'", group);
+                    }
+                    None
+                }
+                None => None,
+            };
+            let prompt = command.map(|command| format!("Restart {}?", command));
+            let command = match prompt {
+                Some(prompt) if !confirm(start_opts, ConfirmPolicy::Always, &prompt)? => None,
+                _ => command,
+            };
             if let Some(command) = command {
-                sender.send(ProcessAction::Kill(command.clone()))?;
-                let process_id = sender.spawn(command.command())?;
-                state.last_command = Some(BufferedCommand::Restart(
-                    command.command().to_string(),
-                    process_id,
-                ));
+                if let Some(process_id) = sender.restart(command.clone())? {
+                    state.last_command = Some(BufferedCommand::Restart(
+                        command.command().to_string(),
+                        process_id,
+                    ));
+                }
             }
         }
+        Key::Char('R') => {
+            let prompt = "Restart all running commands?";
+            if !confirm(start_opts, ConfirmPolicy::Always, prompt)? {
+                return Ok(ControlFlow::Continue(()));
+            }
+            let restarted = sender.restart_all()?;
+            log!("Restarted {} commands", restarted.len());
+        }
         Key::Char('t') => {
             let list = sender.list()?;
             let command = Terminal::select_single_command_with_running(
                 "Pick command to run, or press 'q' to cancel",
-                &sender,
+                sender,
                 &start_opts.config.start_options.commands,
                 &list,
             )?;
             if let Some(command) = command {
+                config::warn_deprecated_commands(&start_opts.config.start_options, &[command]);
                 sender.spawn(command)?;
                 state.last_command = Some(BufferedCommand::Start(command.to_string()));
             }
@@ -265,7 +798,7 @@ fn handle_key_press(
                 sender.spawn(command)?;
             }
             Some(BufferedCommand::Restart(command, process_id)) => {
-                match sender.restart(process_id.clone(), &command)? {
+                match sender.restart(process_id.clone())? {
                     Some(id) => {
                         let command = command.clone();
                         state.last_command = Some(BufferedCommand::Restart(command, id))
@@ -280,33 +813,61 @@ fn handle_key_press(
             }
         },
         Key::Char('b') => {
-            let all_recipes = config::get_unique_recipes(&start_opts.config.start_options);
-            let all_recipes = all_recipes.into_iter().cloned().collect::<Vec<_>>();
+            let all_recipes = config::get_ordered_recipes(&start_opts.config.start_options);
             let recipes = Terminal::select_multiple_recipes(
                 "Select one or more recipes to start running, or press 'q' to cancel",
-                &sender,
+                sender,
                 &all_recipes,
             )?;
+            config::warn_deprecated_recipes(&start_opts.config.start_options, &recipes);
             let commands =
                 config::collect_commands_by_recipes(&start_opts.config.start_options, &recipes);
+            config::warn_deprecated_commands(&start_opts.config.start_options, &commands);
             for command in commands {
                 sender.send(ProcessAction::Create(command.clone()))?;
             }
         }
+        Key::Char('B') => {
+            let all_recipes = config::get_ordered_recipes(&start_opts.config.start_options);
+            let recipe = Terminal::select_single_recipe(
+                "Select a recipe to stop running, or press 'q' to cancel",
+                sender,
+                &all_recipes,
+            )?;
+            let prompt = recipe.map(|recipe| format!("Kill all commands of recipe '{}'?", recipe));
+            let recipe = match prompt {
+                Some(prompt) if !confirm(start_opts, ConfirmPolicy::Destructive, &prompt)? => None,
+                _ => recipe,
+            };
+            if let Some(recipe) = recipe {
+                let killed = sender.kill_by_recipe(&start_opts.config.start_options, recipe)?;
+                log!("Killed {} commands from recipe '{}'", killed.len(), recipe);
+            }
+        }
         Key::Char('z') => {
-            let all_recipes = config::get_unique_recipes(&start_opts.config.start_options);
-            let all_recipes = all_recipes.into_iter().cloned().collect::<Vec<_>>();
+            let all_recipes = config::get_ordered_recipes(&start_opts.config.start_options);
             let recipe = Terminal::select_single_recipe(
                 "Select a recipe to start running, or press 'q' to cancel (note: this will stop all other commands)",
-                &sender,
+                sender,
                 &all_recipes,
             )?;
+            let prompt =
+                recipe.map(|recipe| format!("Stop all commands not in recipe '{}'?", recipe));
+            let recipe = match prompt {
+                Some(prompt) if !confirm(start_opts, ConfirmPolicy::Destructive, &prompt)? => None,
+                _ => recipe,
+            };
             if let Some(recipe) = recipe {
                 let recipe = recipe.clone();
+                config::warn_deprecated_recipes(&start_opts.config.start_options, &[&recipe]);
                 let recipe_commands = config::collect_commands_by_recipes(
                     &start_opts.config.start_options,
                     &[recipe],
                 );
+                config::warn_deprecated_commands(
+                    &start_opts.config.start_options,
+                    &recipe_commands,
+                );
                 let list = sender.list()?;
                 let kill_commands: Vec<_> = list
                     .iter()
@@ -321,13 +882,436 @@ fn handle_key_press(
                 }
             }
         }
-        Key::Char('\n') => {}
-        Key::Char(c) => {
+        #[cfg(feature = "termion")]
+        Key::Char(':') => {
+            let Some(line) = read_command_line(&state.history)? else {
+                return Ok(ControlFlow::Continue(()));
+            };
+            if state.history.last() != Some(&line) {
+                state.history.push(line.clone());
+            }
+            if handle_text_command(&line, state, start_opts, sender)?.is_break() {
+                return Ok(ControlFlow::Break(()));
+            }
+        }
+        #[cfg(not(feature = "termion"))]
+        Key::Char(':') => {
+            log!("Type a text command such as 'kill api', or 'help' to list them");
+        }
+        // the enter after a key in line buffered input doesn't cancel quitting
+        Key::Char('\n') => return Ok(ControlFlow::Continue(())),
+        Key::Char(c) | Key::Unbound(c) => {
             log_err!("Unknown command: '{}'", c);
-            log!("Press 'h' or '?' for help");
+            log!("Press '{}' or '?' for help", state.bindings.key('h'));
         }
     }
     state.awaiting_quit_command = false;
 
     Ok(ControlFlow::Continue(()))
 }
+
+/// Asks before an action when the `confirm` policy covers it, `level` being the least
+/// policy that does
+fn confirm(
+    start_opts: &StartTogetherOptions,
+    level: ConfirmPolicy,
+    prompt: &str,
+) -> TogetherResult<bool> {
+    match start_opts.config.start_options.confirm {
+        Some(policy) if policy >= level => Terminal::confirm(prompt),
+        _ => Ok(true),
+    }
+}
+
+/// Reads a text command after ':', with the cursor moved by the arrow keys and earlier commands
+/// recalled with up and down. Returns `None` when cancelled with Esc or Ctrl-C, or left empty.
+#[cfg(feature = "termion")]
+fn read_command_line(history: &[String]) -> TogetherResult<Option<String>> {
+    use std::io::Write;
+    use termion::{event::Key, input::TermRead, raw::IntoRawMode};
+
+    let mut stdout = std::io::stdout().into_raw_mode()?;
+    let mut line: Vec<char> = vec![];
+    let mut cursor = 0;
+    // position in the history, which is past the end while editing a new line
+    let mut recalled = history.len();
+    let mut draft = vec![];
+    let mut submitted = false;
+
+    write!(stdout, "\r{}:", termion::clear::CurrentLine)?;
+    stdout.flush()?;
+    for key in std::io::stdin().keys() {
+        match key? {
+            Key::Char('\n') | Key::Char('\r') => {
+                submitted = true;
+                break;
+            }
+            Key::Esc | Key::Ctrl('c') => break,
+            Key::Char(c) => {
+                line.insert(cursor, c);
+                cursor += 1;
+            }
+            Key::Backspace if cursor > 0 => {
+                cursor -= 1;
+                line.remove(cursor);
+            }
+            Key::Delete if cursor < line.len() => {
+                line.remove(cursor);
+            }
+            Key::Left => cursor = cursor.saturating_sub(1),
+            Key::Right => cursor = (cursor + 1).min(line.len()),
+            Key::Home | Key::Ctrl('a') => cursor = 0,
+            Key::End | Key::Ctrl('e') => cursor = line.len(),
+            Key::Ctrl('u') => {
+                line.drain(..cursor);
+                cursor = 0;
+            }
+            Key::Up if recalled > 0 => {
+                if recalled == history.len() {
+                    draft = line.clone();
+                }
+                recalled -= 1;
+                line = history[recalled].chars().collect();
+                cursor = line.len();
+            }
+            Key::Down if recalled < history.len() => {
+                recalled += 1;
+                line = match history.get(recalled) {
+                    Some(entry) => entry.chars().collect(),
+                    None => std::mem::take(&mut draft),
+                };
+                cursor = line.len();
+            }
+            _ => continue,
+        }
+        let text: String = line.iter().collect();
+        write!(stdout, "\r{}:{}", termion::clear::CurrentLine, text)?;
+        if cursor < line.len() {
+            write!(
+                stdout,
+                "{}",
+                termion::cursor::Left((line.len() - cursor) as u16)
+            )?;
+        }
+        stdout.flush()?;
+    }
+    write!(stdout, "\r\n")?;
+    stdout.flush()?;
+
+    let line: String = line.into_iter().collect();
+    let line = line.trim();
+    Ok((submitted && !line.is_empty()).then(|| line.to_string()))
+}
+
+/// Output of a process to copy to the clipboard
+enum CopySelection {
+    Lines(usize),
+    /// The lines around the last line mentioning an error, up to the blank lines around them
+    LastError,
+}
+
+fn parse_copy_selection(input: &str) -> Option<CopySelection> {
+    match input.trim() {
+        "e" | "error" => Some(CopySelection::LastError),
+        lines => lines.parse().ok().map(CopySelection::Lines),
+    }
+}
+
+fn copy_output(
+    sender: &manager::ProcessManagerHandle,
+    id: &process::ProcessId,
+    selection: CopySelection,
+) -> TogetherResult<()> {
+    let limit = match selection {
+        CopySelection::Lines(lines) => lines,
+        CopySelection::LastError => usize::MAX,
+    };
+    let Some(lines) = sender.tail(id.clone(), limit)? else {
+        log!("No output recorded for {}", id);
+        return Ok(());
+    };
+    let lines: Vec<_> = lines
+        .iter()
+        .map(|line| terminal::stdout::strip_ansi(line.trim_end_matches(['\r', '\n'])))
+        .collect();
+    let lines = match selection {
+        CopySelection::Lines(_) => &lines[..],
+        CopySelection::LastError => match last_error_block(&lines) {
+            Some(block) => block,
+            None => {
+                log!("No error found in the output of {}", id);
+                return Ok(());
+            }
+        },
+    };
+    crate::clipboard::copy(&lines.join("\n"))?;
+    log!("Copied {} lines from {} to the clipboard", lines.len(), id);
+    Ok(())
+}
+
+/// The block of lines, between blank lines, around the last line that looks like an error,
+/// e.g. a panic message with its backtrace or a python traceback
+fn last_error_block<S: AsRef<str>>(lines: &[S]) -> Option<&[S]> {
+    let pattern = regex::Regex::new(r"(?i)\b(error|panicked|exception|traceback|fatal)\b").ok()?;
+    let last = lines.iter().rposition(|l| pattern.is_match(l.as_ref()))?;
+    let blank = |l: &S| l.as_ref().trim().is_empty();
+    let start = lines[..last].iter().rposition(blank).map_or(0, |i| i + 1);
+    let end = lines[last..]
+        .iter()
+        .position(blank)
+        .map_or(lines.len(), |i| last + i);
+    Some(&lines[start..end])
+}
+
+/// Writes a line to the stdin of each process, returning those still running to send it to
+fn broadcast_input(
+    sender: &manager::ProcessManagerHandle,
+    ids: &[process::ProcessId],
+    input: &str,
+) -> TogetherResult<Vec<process::ProcessId>> {
+    let mut running = vec![];
+    for id in ids {
+        let line = format!("{}\n", input).into_bytes();
+        match sender.input(id.clone(), line)? {
+            Some(()) => running.push(id.clone()),
+            None => {
+                log_err!("{} is no longer running", id);
+            }
+        }
+    }
+    Ok(running)
+}
+
+/// Gives a process exclusive use of the terminal until the detach key is pressed,
+/// sending typed lines to its stdin and printing only its output without a prefix
+#[cfg(feature = "termion")]
+fn attach_to_process(
+    id: &process::ProcessId,
+    sender: &manager::ProcessManagerHandle,
+) -> TogetherResult<()> {
+    use std::io::{Read, Write};
+    use termion::raw::IntoRawMode;
+
+    const DETACH: u8 = 0x1d; // Ctrl-]
+    const BACKSPACE: [u8; 2] = [0x08, 0x7f];
+
+    log!("Attached to {} (press Ctrl-] to detach)", id);
+    if sender.attach(id.clone())?.is_none() {
+        log_err!("Could not find process to attach to");
+        return Ok(());
+    }
+
+    let mut stdout = std::io::stdout().into_raw_mode()?;
+    let mut line = Vec::new();
+    for byte in std::io::stdin().lock().bytes() {
+        match byte? {
+            DETACH => break,
+            b'\r' | b'\n' => {
+                write!(stdout, "\r\n")?;
+                line.push(b'\n');
+                if sender
+                    .input(id.clone(), std::mem::take(&mut line))?
+                    .is_none()
+                {
+                    break;
+                }
+            }
+            b if BACKSPACE.contains(&b) => {
+                if line.pop().is_some() {
+                    write!(stdout, "\x08 \x08")?;
+                }
+            }
+            b => {
+                stdout.write_all(&[b])?;
+                line.push(b);
+            }
+        }
+        stdout.flush()?;
+    }
+    drop(stdout);
+
+    detach_from_process(id, sender)
+}
+
+/// Line based fallback when raw terminal input is unavailable, detaching on a `~.` line
+#[cfg(not(feature = "termion"))]
+fn attach_to_process(
+    id: &process::ProcessId,
+    sender: &manager::ProcessManagerHandle,
+) -> TogetherResult<()> {
+    log!("Attached to {} (enter '~.' to detach)", id);
+    if sender.attach(id.clone())?.is_none() {
+        log_err!("Could not find process to attach to");
+        return Ok(());
+    }
+
+    let mut line = String::new();
+    while std::io::stdin().read_line(&mut line)? > 0 {
+        if line.trim_end() == "~." {
+            break;
+        }
+        if sender
+            .input(id.clone(), std::mem::take(&mut line).into_bytes())?
+            .is_none()
+        {
+            break;
+        }
+    }
+
+    detach_from_process(id, sender)
+}
+
+fn detach_from_process(
+    id: &process::ProcessId,
+    sender: &manager::ProcessManagerHandle,
+) -> TogetherResult<()> {
+    let hidden = sender.detach()?;
+    log!("Detached from {}", id);
+    for (id, lines) in hidden {
+        log!("  {} lines hidden from {}", lines, id);
+    }
+    Ok(())
+}
+
+/// Prompts for a new output filter, returning `Some(None)` to clear the current filter
+fn prompt_output_filter() -> TogetherResult<Option<Option<OutputFilter>>> {
+    let modes = ["Filter lines", "Highlight matches", "Clear"];
+    let Some(mode) = Terminal::select_single_index(
+        "Pick how to apply the pattern, or press 'q' to cancel",
+        &modes,
+    )?
+    else {
+        return Ok(None);
+    };
+    if mode == 2 {
+        return Ok(Some(None));
+    }
+    let Some(pattern) = Terminal::input("Regular expression")? else {
+        return Ok(None);
+    };
+    let regex = match regex::Regex::new(&pattern) {
+        Ok(regex) => regex,
+        Err(e) => {
+            log_err!("Invalid regular expression: {}", e);
+            return Ok(None);
+        }
+    };
+    match mode {
+        0 => Ok(Some(Some(OutputFilter::Grep(regex)))),
+        _ => Ok(Some(Some(OutputFilter::Highlight(regex)))),
+    }
+}
+
+/// A running process, or all running processes of a group, picked to act on
+enum Target<'a> {
+    Process(&'a process::ProcessId),
+    Group(&'a str, Vec<&'a process::ProcessId>),
+}
+
+/// Picks a running process, or when commands have groups, a whole group listed after them
+fn select_process_or_group<'a>(
+    prompt: &'a str,
+    start_opts: &'a StartTogetherOptions,
+    sender: &'a manager::ProcessManagerHandle,
+    list: &'a [process::ProcessId],
+) -> TogetherResult<Option<Target<'a>>> {
+    let start_options = &start_opts.config.start_options;
+    let groups: Vec<_> = start_options
+        .groups()
+        .into_iter()
+        .map(|group| {
+            let members: Vec<_> = list
+                .iter()
+                .filter(|id| start_options.group_of(id.command()) == Some(group))
+                .collect();
+            (group, members)
+        })
+        .filter(|(_, members)| !members.is_empty())
+        .collect();
+    if groups.is_empty() {
+        let command = Terminal::select_single_process(prompt, sender, list)?;
+        return Ok(command.map(Target::Process));
+    }
+
+    let group_order = start_options.groups();
+    let mut processes: Vec<_> = list.iter().collect();
+    processes.sort_by_key(|id| {
+        let group = start_options.group_of(id.command());
+        let group = group.and_then(|g| group_order.iter().position(|o| *o == g));
+        (group.unwrap_or(usize::MAX), id.index())
+    });
+    let items: Vec<_> = processes
+        .iter()
+        .map(|id| id.to_string())
+        .chain(groups.iter().map(|(group, members)| {
+            let running = theme::muted(&format!("({} running)", members.len()));
+            format!("all of {} {}", format_group(start_opts, group), running)
+        }))
+        .collect();
+    let target = Terminal::select_single_index(prompt, &items)?.map(|index| {
+        match index.checked_sub(processes.len()) {
+            None => Target::Process(processes[index]),
+            Some(group) => {
+                let (group, members) = &groups[group];
+                Target::Group(group, members.clone())
+            }
+        }
+    });
+    Ok(target)
+}
+
+/// Colors a group by the order it first appears in
+fn format_group(start_opts: &StartTogetherOptions, group: &str) -> String {
+    let groups = start_opts.config.start_options.groups();
+    let index = groups.iter().position(|g| *g == group).unwrap_or_default();
+    theme::group(group, index)
+}
+
+fn format_status(start_opts: &StartTogetherOptions, status: &manager::ProcessStatus) -> String {
+    let usage = match (status.cpu, status.memory) {
+        (Some(cpu), Some(memory)) => {
+            format!(", {:.1}% cpu, {}", cpu, terminal::format_bytes(memory))
+        }
+        _ => String::new(),
+    };
+    let cwd = match &status.cwd {
+        Some(cwd) => format!(", in {}", cwd),
+        None => String::new(),
+    };
+    let crash_looping = match status.crash_looping {
+        true => format!(" {}", theme::alert("crash-looping")),
+        false => String::new(),
+    };
+    let details = format!(
+        "[{}, {}{}{}]",
+        status.state,
+        terminal::format_duration(status.uptime()),
+        usage,
+        cwd
+    );
+    format!(
+        "{} {}{}",
+        format_process(start_opts, &status.id),
+        theme::muted(&details),
+        crash_looping
+    )
+}
+
+fn format_process(start_opts: &StartTogetherOptions, process: &process::ProcessId) -> String {
+    match command_url(start_opts, process) {
+        // format: "command (url)" with gray color for parentheses
+        Some(url) => format!("{} {}", process, theme::muted(&format!("({})", url))),
+        None => process.to_string(),
+    }
+}
+
+fn command_url<'a>(
+    start_opts: &'a StartTogetherOptions,
+    process: &process::ProcessId,
+) -> Option<&'a str> {
+    start_opts
+        .config
+        .start_options
+        .find_command(process.command())
+        .and_then(|c| c.url())
+}
@@ -1,17 +1,44 @@
-use std::sync::{Arc, Mutex};
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
 
 use config::StartTogetherOptions;
-use errors::TogetherResult;
+use errors::{TogetherError, TogetherResult};
 use manager::ProcessAction;
 use terminal_ext::TerminalExt;
 
+pub mod clipboard;
 pub mod config;
+pub mod container;
+pub mod control;
+pub mod debug_log;
 pub mod errors;
 pub mod kb;
+pub mod links;
+pub mod log_sink;
 pub mod manager;
+pub mod orphans;
+pub mod output;
+pub mod ports;
 pub mod process;
+pub mod recurring;
+pub mod remote;
+pub mod report;
+pub mod restarts;
+pub mod secrets;
+pub mod session;
+pub mod supervisor;
+pub mod telemetry;
 pub mod terminal;
 pub mod terminal_ext;
+pub mod theme;
+pub mod timestamp;
+pub mod wait;
+pub mod workspace;
+pub mod wsl;
+
+pub use session::Session;
 
 pub fn start(options: StartTogetherOptions) -> TogetherResult<()> {
     let StartTogetherOptions {
@@ -20,30 +47,78 @@ pub fn start(options: StartTogetherOptions) -> TogetherResult<()> {
         ..
     } = &options;
 
+    timestamp::init(options.timestamp_format.clone());
+    theme::init(
+        config.start_options.theme.clone(),
+        &config.start_options.commands,
+    );
+
     let manager = manager::ProcessManager::new()
         .with_raw_mode(config.start_options.raw)
         .with_exit_on_error(config.start_options.exit_on_error)
-        .with_quit_on_completion(config.start_options.quit_on_completion)
+        .with_quit_on_completion(
+            config.start_options.quit_on_completion && !config.start_options.init_only,
+        )
         .with_working_directory(working_directory.to_owned())
+        // init-only returns once startup commands finish, so their status can be reported
+        .with_process_exit(!config.start_options.init_only)
+        .with_commands(config.start_options.commands.clone())
+        .with_pty(config.start_options.pty)
+        .with_spawn_retries(config.start_options.spawn_retries.unwrap_or_default())
+        .with_kill_timeout(
+            config
+                .start_options
+                .kill_timeout
+                .map_or(manager::DEFAULT_KILL_TIMEOUT, Duration::from_secs),
+        )
+        .with_output_mode(config.start_options.output_mode)
+        .with_log_sink(config.start_options.log_sink)
+        .with_log_forward(config.start_options.log_forward.clone())
+        .with_otlp_endpoint(config.start_options.otlp_endpoint.clone())
+        .with_report(config.start_options.report.clone())
+        .with_ports(config.start_options.ports)
+        .with_hyperlink_format(config.start_options.hyperlink_format.clone())
+        .with_timestamps(config.start_options.timestamps)
+        .with_output_filter(options.output_filter.clone())
+        .with_supervisor(options.supervise.clone())
+        .with_crash_loop_limit(config.start_options.crash_loop.as_ref())
+        .with_shutdown_commands(match config.start_options.init_only {
+            true => vec![],
+            false => command_groups(config, config.shutdown.as_deref()),
+        })
         .start();
 
     let sender = manager.subscribe();
-    handle_ctrl_signal(sender);
+    handle_ctrl_signal(sender, Some(options.clone()));
+    // only long-running sessions can be controlled, init-only ones exit after startup
+    if !config.start_options.init_only {
+        control::serve(manager.subscribe(), config.start_options.commands.clone());
+    }
 
-    let selected_commands = collect_together_commands(&manager, &options)?;
+    let selected_commands = if config.start_options.init_only {
+        vec![]
+    } else {
+        collect_together_commands(&manager, &options)?
+    };
 
-    if config.start_options.no_init {
+    let failed_startup_commands = if config.start_options.no_init {
         log!("Skipping startup commands...");
+        vec![]
     } else {
-        execute_startup_commands(&manager, &config)?;
-    }
+        execute_startup_commands(&manager, config)?
+    };
 
     if config.start_options.init_only {
-        log!("Finished running startup commands, waiting for user input... (press '?' for help)");
-    } else {
-        execute_together_commands(&manager, selected_commands)?;
+        if !failed_startup_commands.is_empty() {
+            return Err(TogetherError::StartupFailed(failed_startup_commands));
+        }
+        log!("Finished running startup commands, exiting...");
+        std::mem::drop(manager);
+        return Ok(());
     }
 
+    execute_together_commands(&manager, config, selected_commands)?;
+
     let sender = manager.subscribe();
     kb::block_for_user_input(&options, sender)?;
 
@@ -51,19 +126,71 @@ pub fn start(options: StartTogetherOptions) -> TogetherResult<()> {
     Ok(())
 }
 
-pub fn handle_ctrl_signal(sender: manager::ProcessManagerHandle) {
+/// Reports an error from `start` or building its options, and exits the process with the
+/// matching status
+pub fn exit_with_error(error: TogetherError) -> ! {
+    match error {
+        TogetherError::Interrupted => std::process::exit(0),
+        TogetherError::NoConfigFile => {
+            _ = <terminal::TogetherArgs as clap::CommandFactory>::command().print_long_help();
+        }
+        e @ (TogetherError::Config(_) | TogetherError::Spawn(_) | TogetherError::Manager(_)) => {
+            log_err!("{}", e);
+        }
+        TogetherError::StartupFailed(commands) => {
+            log_err!("{} startup command(s) failed, exiting...", commands.len());
+            for command in &commands {
+                log_err!("  - {}", command);
+            }
+        }
+        e => {
+            log_err!("Unexpected error: {}", e);
+        }
+    }
+    std::process::exit(1);
+}
+
+/// Saves the configuration with the currently running commands as the last session, so
+/// `together rerun` starts them again
+pub fn save_session(options: &StartTogetherOptions, sender: &manager::ProcessManagerHandle) {
+    let Ok(list) = sender.list() else {
+        return;
+    };
+    let running: Vec<_> = list.iter().map(|id| id.command()).collect();
+    if let Err(e) = config::save_session(&options.session(&running)) {
+        log_err!("Failed to save the session: {}", e);
+    }
+}
+
+/// Stops all processes on Ctrl-C, first saving the session when `options` are given. Pressing
+/// Ctrl-C again force kills them and exits.
+pub fn handle_ctrl_signal(
+    sender: manager::ProcessManagerHandle,
+    options: Option<StartTogetherOptions>,
+) {
     let state = Arc::new(Mutex::new(false));
     let handler = ctrlc::set_handler(move || {
         {
             let mut state = state.lock().unwrap();
             if *state {
-                log!("Ctrl-C pressed again, exiting immediately...");
+                log!("Ctrl-C pressed again, force killing all processes...");
+                _ = sender.send(ProcessAction::ForceKillAll);
                 std::process::exit(1);
             }
             *state = true;
         }
 
-        log!("Ctrl-C pressed, stopping all processes...");
+        let kill_timeout = options
+            .as_ref()
+            .and_then(|options| options.config.start_options.kill_timeout)
+            .map_or(manager::DEFAULT_KILL_TIMEOUT, Duration::from_secs);
+        log!(
+            "Ctrl-C pressed, stopping all processes (force killing any left after {}s)...",
+            kill_timeout.as_secs()
+        );
+        if let Some(options) = &options {
+            save_session(options, &sender);
+        }
         sender
             .send(ProcessAction::KillAll)
             .expect("Could not send signal on channel.");
@@ -75,11 +202,31 @@ fn collect_together_commands(
     manager: &manager::ProcessManagerHandle,
     options: &StartTogetherOptions,
 ) -> TogetherResult<Vec<String>> {
-    if let Some(recipes) = &options.active_recipes {
+    let excluded = options.excluded_recipes();
+    let exclude = |commands| {
+        config::exclude_commands_by_recipes(&options.config.start_options, commands, &excluded)
+    };
+
+    if let Some(only) = &options.only {
+        log!("Running selected commands...");
+        config::warn_deprecated_commands(&options.config.start_options, only);
+        return Ok(only.clone());
+    }
+
+    if options.active_recipes.is_some() || options.exclude_recipes.is_some() {
         log!("Running commands from recipes...");
         let config_opts = &options.config.start_options;
 
-        let selected_commands = config::collect_commands_by_recipes(&config_opts, recipes);
+        let selected_commands = match &options.active_recipes {
+            Some(recipes) => {
+                config::warn_deprecated_recipes(config_opts, recipes);
+                config::collect_commands_by_recipes(config_opts, recipes)
+            }
+            // only recipes to exclude were given, so every other command runs
+            None => config_opts.as_commands(),
+        };
+        let selected_commands = exclude(selected_commands);
+        config::warn_deprecated_commands(config_opts, &selected_commands);
 
         log!("Commands selected by recipes:");
         for command in &selected_commands {
@@ -93,14 +240,18 @@ fn collect_together_commands(
     let selected_commands = match &config.running_commands() {
         Some(commands) => {
             log!("Running commands from configuration...");
-            commands.into_iter().map(|c| c.to_string()).collect()
+            exclude(commands.iter().map(|c| c.to_string()).collect())
         }
         None if config.start_options.all => {
             log!("Running all commands...");
-            config.start_options.as_commands()
+            exclude(config.start_options.as_commands())
         }
         None => {
-            let all_commands = config.start_options.as_commands();
+            let mut all_commands = exclude(config.start_options.as_commands());
+            all_commands.sort_by_key(|c| {
+                let command = config.start_options.find_command(c);
+                command.and_then(|c| c.deprecated()).is_some()
+            });
             let sender = manager.subscribe();
             let commands = terminal::Terminal::select_multiple_commands(
                 "Select commands to run together",
@@ -110,25 +261,23 @@ fn collect_together_commands(
             commands.into_iter().cloned().collect()
         }
     };
+    config::warn_deprecated_commands(&config.start_options, &selected_commands);
     Ok(selected_commands)
 }
 
+/// Runs the startup commands in order, with the commands of a group running in parallel,
+/// returning the commands that exited with a non-zero status.
 fn execute_startup_commands(
     manager: &manager::ProcessManagerHandle,
     config: &config::TogetherConfigFile,
-) -> TogetherResult<()> {
-    let Some(startup) = &config.startup else {
-        return Ok(());
-    };
+) -> TogetherResult<Vec<String>> {
+    if config.startup.is_none() {
+        return Ok(vec![]);
+    }
 
     log!("Running startup commands...");
     let sender = manager.subscribe();
-
-    let commands = startup
-        .iter()
-        .flat_map(|index| index.retrieve(&config.start_options.commands))
-        .map(|c| c.as_str().to_string())
-        .collect::<Vec<_>>();
+    let groups = command_groups(config, config.startup.as_deref());
 
     let opts = if config.start_options.quiet_startup {
         manager::CreateOptions::default().with_stderr_only()
@@ -136,22 +285,85 @@ fn execute_startup_commands(
         manager::CreateOptions::default()
     };
 
-    for command in commands {
-        let id = sender.spawn_advanced(&command, &opts)?;
-        sender.wait(id)?;
-        log!("Startup command '{}' completed", command);
+    let mut failed = vec![];
+    for group in groups {
+        let mut running = vec![];
+        for command in group {
+            let id = sender.spawn_advanced(&command, &opts)?;
+            running.push((command, sender.exit_receiver(id)?));
+        }
+        for (command, exited) in running {
+            match exited.recv()? {
+                0 => {
+                    log!("Startup command '{}' completed", command);
+                }
+                status => {
+                    log_err!(
+                        "Startup command '{}' failed with status {}",
+                        command,
+                        status
+                    );
+                    failed.push(command);
+                }
+            }
+        }
     }
 
-    Ok(())
+    Ok(failed)
 }
 
+/// Resolves startup or shutdown entries to groups of commands
+fn command_groups(
+    config: &config::TogetherConfigFile,
+    entries: Option<&[config::commands::StartupEntry]>,
+) -> Vec<Vec<String>> {
+    let entries = entries.unwrap_or_default().iter();
+    entries
+        .map(|entry| {
+            entry
+                .commands()
+                .iter()
+                .flat_map(|index| index.retrieve(&config.start_options.commands))
+                .map(|c| c.as_str().to_string())
+                .collect()
+        })
+        .collect()
+}
+
+/// Starts the selected commands in their `launch_order`, spaced out by `stagger_ms` or their
+/// own `delay`
 fn execute_together_commands(
     manager: &manager::ProcessManagerHandle,
+    config: &config::TogetherConfigFile,
     selected_commands: Vec<String>,
 ) -> TogetherResult<()> {
-    let sender = manager.subscribe();
-    for command in selected_commands {
-        sender.send(ProcessAction::Create(command.clone()))?;
+    let start_options = &config.start_options;
+    let stagger = Duration::from_millis(start_options.stagger_ms.unwrap_or_default());
+    let mut selected_commands = selected_commands;
+    if let Some(order) = &config.launch_order {
+        let position = |command: &String| {
+            order
+                .iter()
+                .position(|index| {
+                    index
+                        .retrieve(&start_options.commands)
+                        .is_some_and(|c| c.matches(command))
+                })
+                .unwrap_or(order.len())
+        };
+        selected_commands.sort_by_key(position);
     }
-    Ok(())
+    let commands = selected_commands
+        .into_iter()
+        .enumerate()
+        .map(|(index, command)| {
+            let delay = start_options.find_command(&command).and_then(|c| c.delay());
+            let delay = match index {
+                0 => delay.unwrap_or_default(),
+                _ => delay.unwrap_or(stagger),
+            };
+            (command, delay)
+        })
+        .collect();
+    manager.schedule(commands)
 }
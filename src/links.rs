@@ -0,0 +1,136 @@
+use std::{
+    borrow::Cow,
+    path::{Path, PathBuf},
+    sync::OnceLock,
+};
+
+/// Underlines URLs and `path:line:col` references in output, wrapping them in OSC 8
+/// hyperlinks when the terminal supports them so they can be clicked
+#[derive(Debug, Clone)]
+pub struct Linker {
+    hyperlinks: bool,
+    /// Link for files, with `{path}`, `{line}` and `{column}` filled in
+    format: Option<String>,
+}
+
+impl Linker {
+    /// A linker for output written to the terminal, or none if output isn't colored
+    pub fn detect(format: Option<String>) -> Option<Self> {
+        if !crate::terminal::stdout::color() {
+            return None;
+        }
+        Some(Self {
+            hyperlinks: supports_hyperlinks(),
+            format,
+        })
+    }
+
+    /// Links the line, resolving relative paths against `cwd`. Paths are only linked if the
+    /// file exists, so things like times aren't mistaken for them.
+    pub fn apply<'a>(&self, line: &'a str, cwd: Option<&Path>) -> Cow<'a, str> {
+        let regex = link_regex();
+        if !regex.is_match(line) {
+            return line.into();
+        }
+        let mut linked = String::with_capacity(line.len());
+        let mut last = 0;
+        for captures in regex.captures_iter(line) {
+            let (text, target) = match (captures.name("url"), captures.name("path")) {
+                (Some(url), _) => {
+                    let url = url
+                        .as_str()
+                        .trim_end_matches(['.', ',', ';', ':', ')', ']']);
+                    (url, url.to_string())
+                }
+                (None, Some(path)) => {
+                    let Some(file) = resolve(path.as_str(), cwd) else {
+                        continue;
+                    };
+                    let reference = captures.get(0).unwrap().as_str();
+                    let line = captures.name("line").map_or("1", |m| m.as_str());
+                    let column = captures.name("column").map_or("1", |m| m.as_str());
+                    (reference, self.file_link(&file, line, column))
+                }
+                (None, None) => continue,
+            };
+            let start = captures.get(0).unwrap().start();
+            linked.push_str(&line[last..start]);
+            match self.hyperlinks {
+                true => linked.push_str(&format!(
+                    "\x1b]8;;{}\x1b\\\x1b[4m{}\x1b[24m\x1b]8;;\x1b\\",
+                    target, text
+                )),
+                false => linked.push_str(&format!("\x1b[4m{}\x1b[24m", text)),
+            }
+            last = start + text.len();
+        }
+        if last == 0 {
+            return line.into();
+        }
+        linked.push_str(&line[last..]);
+        linked.into()
+    }
+
+    fn file_link(&self, file: &Path, line: &str, column: &str) -> String {
+        let path = file.to_string_lossy();
+        match &self.format {
+            Some(format) => format
+                .replace("{path}", &path)
+                .replace("{line}", line)
+                .replace("{column}", column),
+            None => format!("file://{}", path.replace('\\', "/")),
+        }
+    }
+}
+
+fn link_regex() -> &'static regex::Regex {
+    static LINK: OnceLock<regex::Regex> = OnceLock::new();
+    LINK.get_or_init(|| {
+        let url = r#"(?P<url>https?://[^\s\x1b'"<>]+)"#;
+        // a file with an extension, followed by a line and optionally a column
+        let path = r"(?P<path>(?:[A-Za-z]:)?[\w.\-/\\~]*\w\.[A-Za-z0-9]+)";
+        let position = r":(?P<line>\d+)(?::(?P<column>\d+))?";
+        regex::Regex::new(&format!("{}|{}{}", url, path, position)).unwrap()
+    })
+}
+
+fn resolve(path: &str, cwd: Option<&Path>) -> Option<PathBuf> {
+    let path = match path.strip_prefix("~/") {
+        Some(path) => dirs::home_dir()?.join(path),
+        None => PathBuf::from(path),
+    };
+    let path = match (path.is_absolute(), cwd) {
+        (true, _) => path,
+        (false, Some(cwd)) => cwd.join(path),
+        (false, None) => std::env::current_dir().ok()?.join(path),
+    };
+    path.is_file().then(|| path.canonicalize().unwrap_or(path))
+}
+
+/// Whether the terminal is known to support OSC 8 hyperlinks, or they are forced on or off
+/// with `FORCE_HYPERLINK`
+fn supports_hyperlinks() -> bool {
+    let var = |name| std::env::var(name).ok();
+    if let Some(force) = var("FORCE_HYPERLINK") {
+        return force != "0";
+    }
+    if var("WT_SESSION").is_some()
+        || var("KITTY_WINDOW_ID").is_some()
+        || var("KONSOLE_VERSION").is_some()
+        || var("DOMTERM").is_some()
+    {
+        return true;
+    }
+    let vte = var("VTE_VERSION").and_then(|v| v.parse::<u32>().ok());
+    if vte.is_some_and(|v| v >= 5000) {
+        return true;
+    }
+    let program = var("TERM_PROGRAM").unwrap_or_default();
+    let term = var("TERM").unwrap_or_default();
+    matches!(
+        program.as_str(),
+        "iTerm.app" | "WezTerm" | "vscode" | "ghostty" | "Hyper" | "rio"
+    ) || ["kitty", "alacritty", "foot", "ghostty", "wezterm"]
+        .iter()
+        .any(|t| term.contains(t))
+}
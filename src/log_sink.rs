@@ -0,0 +1,323 @@
+use std::{
+    collections::HashMap,
+    io::Write,
+    net::{TcpStream, UdpSocket},
+    sync::{mpsc, Arc},
+    time::{Duration, SystemTime},
+};
+
+use crate::{
+    config::commands::CommandConfig, log, log_err, process::ProcessId,
+    terminal::stdout::strip_ansi, timestamp,
+};
+
+/// The system logger that process output and lifecycle events are forwarded to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogSinkKind {
+    /// The local syslog daemon, through `/dev/log`
+    Syslog,
+    /// systemd-journald, through its native protocol
+    Journald,
+}
+
+impl std::fmt::Display for LogSinkKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            LogSinkKind::Syslog => write!(f, "syslog"),
+            LogSinkKind::Journald => write!(f, "journald"),
+        }
+    }
+}
+
+/// Syslog severities used for forwarded messages
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Priority {
+    Error = 3,
+    Notice = 5,
+    Info = 6,
+}
+
+/// Forwards lines to syslog or journald, identified by the alias of the process they came
+/// from. Messages that can't be sent are dropped, so logging never holds up processes.
+pub struct LogSink {
+    kind: LogSinkKind,
+    /// Identifiers of configured commands, by command
+    identifiers: HashMap<String, String>,
+    socket: Socket,
+}
+
+impl LogSink {
+    pub fn connect(kind: LogSinkKind, commands: &[CommandConfig]) -> std::io::Result<Self> {
+        Ok(Self {
+            kind,
+            identifiers: identifiers(commands),
+            socket: open(kind)?,
+        })
+    }
+
+    /// Forwards a line of output or an event of a process
+    pub fn send(&self, id: &ProcessId, priority: Priority, message: &str) {
+        let message = strip_ansi(message.trim_end_matches(['\r', '\n']));
+        let identifier = self.identifier(id.command());
+        let datagram = match self.kind {
+            LogSinkKind::Syslog => {
+                // the user facility, RFC 3164 style as expected by `/dev/log`
+                let pri = 8 + priority as u8;
+                format!(
+                    "<{}>{}[{}]: {}",
+                    pri,
+                    identifier,
+                    std::process::id(),
+                    message
+                )
+            }
+            LogSinkKind::Journald => format!(
+                "SYSLOG_IDENTIFIER={}\nPRIORITY={}\nTOGETHER_COMMAND={}\nMESSAGE={}\n",
+                identifier,
+                priority as u8,
+                single_line(id.command()),
+                single_line(&message)
+            ),
+        };
+        self.write(datagram.as_bytes());
+    }
+
+    #[cfg(unix)]
+    fn write(&self, datagram: &[u8]) {
+        _ = self.socket.send(datagram);
+    }
+
+    #[cfg(not(unix))]
+    fn write(&self, _datagram: &[u8]) {
+        match self.socket {}
+    }
+
+    fn identifier<'a>(&'a self, command: &'a str) -> &'a str {
+        match self.identifiers.get(command) {
+            Some(identifier) => identifier,
+            None => program(command),
+        }
+    }
+}
+
+#[cfg(unix)]
+type Socket = std::os::unix::net::UnixDatagram;
+
+/// A sink can't be connected without unix sockets
+#[cfg(not(unix))]
+type Socket = std::convert::Infallible;
+
+#[cfg(unix)]
+fn open(kind: LogSinkKind) -> std::io::Result<Socket> {
+    let socket = std::os::unix::net::UnixDatagram::unbound()?;
+    socket.connect(match kind {
+        LogSinkKind::Syslog => "/dev/log",
+        LogSinkKind::Journald => "/run/systemd/journal/socket",
+    })?;
+    Ok(socket)
+}
+
+#[cfg(not(unix))]
+fn open(kind: LogSinkKind) -> std::io::Result<Socket> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        format!("{} is only supported on unix", kind),
+    ))
+}
+
+/// The alias of each command, or its program name when it has none
+fn identifiers(commands: &[CommandConfig]) -> HashMap<String, String> {
+    commands
+        .iter()
+        .map(|command| {
+            let identifier = match command.alias() {
+                Some(alias) => alias.split_whitespace().collect::<Vec<_>>().join("-"),
+                None => program(command.as_str()).to_string(),
+            };
+            (command.as_str().to_string(), identifier)
+        })
+        .collect()
+}
+
+fn program(command: &str) -> &str {
+    let program = command.split_whitespace().next().unwrap_or("together");
+    program.rsplit('/').next().unwrap_or(program)
+}
+
+/// Journald fields end at a newline, so they have to be kept to one line
+fn single_line(value: &str) -> std::borrow::Cow<'_, str> {
+    match value.contains('\n') {
+        true => value.replace('\n', " ").into(),
+        false => value.into(),
+    }
+}
+
+/// A remote collector that output lines are streamed to, as `tcp://host:port` or
+/// `udp://host:port`
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum ForwardTarget {
+    Tcp(String),
+    Udp(String),
+}
+
+impl std::str::FromStr for ForwardTarget {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (target, address) = match s.split_once("://") {
+            Some(("tcp", address)) => (Self::Tcp as fn(String) -> Self, address),
+            Some(("udp", address)) => (Self::Udp as fn(String) -> Self, address),
+            _ => {
+                return Err(format!(
+                    "log forward target '{}' should be 'tcp://host:port' or 'udp://host:port'",
+                    s
+                ))
+            }
+        };
+        match address.rsplit_once(':') {
+            Some((host, port)) if !host.is_empty() && port.parse::<u16>().is_ok() => {
+                Ok(target(address.to_string()))
+            }
+            _ => Err(format!("log forward target '{}' is missing a port", s)),
+        }
+    }
+}
+
+impl TryFrom<String> for ForwardTarget {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl From<ForwardTarget> for String {
+    fn from(target: ForwardTarget) -> Self {
+        target.to_string()
+    }
+}
+
+impl std::fmt::Display for ForwardTarget {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ForwardTarget::Tcp(address) => write!(f, "tcp://{}", address),
+            ForwardTarget::Udp(address) => write!(f, "udp://{}", address),
+        }
+    }
+}
+
+/// Lines waiting to be forwarded, beyond which new lines are dropped while the collector
+/// can't keep up or is unreachable
+const FORWARD_BUFFER: usize = 10_000;
+
+/// Longest wait between attempts to reconnect to the collector
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(30);
+
+/// Streams output lines as ndjson to a remote collector from its own thread, reconnecting
+/// with backoff whenever the connection is lost
+#[derive(Clone)]
+pub struct LogForwarder {
+    sender: mpsc::SyncSender<String>,
+    aliases: Arc<HashMap<String, String>>,
+}
+
+impl LogForwarder {
+    pub fn start(target: ForwardTarget, commands: &[CommandConfig]) -> Self {
+        let (sender, receiver) = mpsc::sync_channel(FORWARD_BUFFER);
+        std::thread::spawn(move || forward_loop(target, receiver));
+        let aliases = commands
+            .iter()
+            .filter_map(|c| Some((c.as_str().to_string(), c.alias()?.to_string())))
+            .collect();
+        Self {
+            sender,
+            aliases: Arc::new(aliases),
+        }
+    }
+
+    pub fn send(&self, id: &ProcessId, stream: &str, line: &str) {
+        let line = serde_json::json!({
+            "timestamp": timestamp::format(SystemTime::now()),
+            "process": id.index(),
+            "command": id.command(),
+            "alias": self.aliases.get(id.command()),
+            "stream": stream,
+            "line": strip_ansi(line.trim_end_matches(['\r', '\n'])),
+        });
+        // drop the line rather than hold up the process when the buffer is full
+        _ = self.sender.try_send(line.to_string());
+    }
+}
+
+enum Connection {
+    Tcp(TcpStream),
+    Udp(UdpSocket),
+}
+
+impl Connection {
+    fn open(target: &ForwardTarget) -> std::io::Result<Self> {
+        match target {
+            ForwardTarget::Tcp(address) => Ok(Self::Tcp(TcpStream::connect(address)?)),
+            ForwardTarget::Udp(address) => {
+                let socket = UdpSocket::bind(("0.0.0.0", 0))?;
+                socket.connect(address)?;
+                Ok(Self::Udp(socket))
+            }
+        }
+    }
+
+    fn write(&mut self, line: &str) -> std::io::Result<()> {
+        match self {
+            Connection::Tcp(stream) => {
+                stream.write_all(line.as_bytes())?;
+                stream.write_all(b"\n")
+            }
+            Connection::Udp(socket) => socket.send(format!("{}\n", line).as_bytes()).map(|_| ()),
+        }
+    }
+}
+
+fn forward_loop(target: ForwardTarget, receiver: mpsc::Receiver<String>) {
+    let mut connection = None;
+    let mut delay = Duration::from_millis(500);
+    let mut logged = false;
+    for line in receiver {
+        loop {
+            let current = match &mut connection {
+                Some(connection) => connection,
+                None => match Connection::open(&target) {
+                    Ok(opened) => {
+                        if logged {
+                            log!("Reconnected to {}", target);
+                        }
+                        delay = Duration::from_millis(500);
+                        logged = false;
+                        connection.insert(opened)
+                    }
+                    Err(e) => {
+                        // only report the first failure until the collector is back
+                        if !logged {
+                            log_err!("Failed to forward logs to {}, retrying: {}", target, e);
+                            logged = true;
+                        }
+                        std::thread::sleep(delay);
+                        delay = (delay * 2).min(MAX_RECONNECT_DELAY);
+                        continue;
+                    }
+                },
+            };
+            match current.write(&line) {
+                Ok(()) => break,
+                Err(e) => {
+                    if !logged {
+                        log_err!("Lost connection to {}, reconnecting: {}", target, e);
+                        logged = true;
+                    }
+                    connection = None;
+                }
+            }
+        }
+    }
+}
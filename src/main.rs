@@ -1,12 +1,25 @@
 use clap::Parser;
-use together_rs::{config, log_err, start, terminal};
+use together_rs::{config, control, debug_log, exit_with_error, orphans, start, terminal};
 
 fn main() {
     let args = terminal::TogetherArgs::parse();
-    let options = config::to_start_options(args);
-    let result = start(options);
+    terminal::stdout::init(args.color);
+    debug_log::init(args.verbose, args.debug_log.as_deref());
+    let result = match &args.command {
+        Some(terminal::ArgsCommands::Add(add)) => config::add_command(add),
+        Some(terminal::ArgsCommands::Remove(remove)) => config::remove_command(remove),
+        Some(terminal::ArgsCommands::Order(order)) => config::order_commands(order),
+        Some(terminal::ArgsCommands::Status(status)) => control::status(status),
+        Some(terminal::ArgsCommands::Report(report)) => control::report(report),
+        Some(terminal::ArgsCommands::Ctl(ctl)) => control::ctl(ctl),
+        Some(terminal::ArgsCommands::Cleanup(cleanup)) => orphans::cleanup(cleanup),
+        _ => match control::restart_on_start(args.restart_on_start.as_deref()) {
+            Ok(true) => Ok(()),
+            Ok(false) => config::to_start_options(args).and_then(start),
+            Err(e) => Err(e),
+        },
+    };
     if let Err(e) = result {
-        log_err!("Unexpected error: {}", e);
-        std::process::exit(1);
+        exit_with_error(e);
     }
 }
@@ -1,11 +1,42 @@
-use std::{collections::HashMap, sync::mpsc};
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    ops::ControlFlow,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc, Arc,
+    },
+    time::{Duration, Instant},
+};
 
 use crate::{
-    errors::{TogetherError, TogetherInternalError, TogetherResult},
+    config::{
+        self,
+        commands::{
+            CommandConfig, CommandOutput, ConfigFileStartOptions, IdleAction, OutputAction,
+            TimeoutAction, WaitFor,
+        },
+    },
+    container::RunningContainer,
+    control, debug,
+    errors::{ManagerError, SpawnError, TogetherError, TogetherInternalError, TogetherResult},
+    links::Linker,
     log, log_err,
-    process::{Process, ProcessId, ProcessSignal, ProcessStdio},
+    log_sink::{ForwardTarget, LogForwarder, LogSink, LogSinkKind, Priority},
+    orphans::ProcessGroups,
+    output::{OutputFilter, OutputHandle, OutputMode},
+    ports::{PortAssignments, PortsMode},
+    process::{self, Process, ProcessId, ProcessSignal, ProcessStdio},
+    recurring::Recurrence,
+    report::{ReportFormat, ReportTarget, RunReport},
+    restarts::{CrashLoopLimit, RestartHistory},
+    secrets,
+    supervisor::{Supervisor, SupervisorOptions},
+    telemetry::{OtlpEndpoint, Tracer},
+    terminal, theme, trace, wait,
+    wsl::{self, WslProcess},
 };
 
+#[derive(Debug)]
 pub enum ProcessAction {
     Create(String),
     CreateAdvanced(String, CreateOptions),
@@ -13,28 +44,47 @@ pub enum ProcessAction {
     Kill(ProcessId),
     KillAdvanced(ProcessId, ProcessSignal),
     KillAll,
+    /// Send SIGKILL to all processes, including shutdown commands
+    ForceKillAll,
+    Restart(ProcessId),
+    RestartAll,
     List,
+    Status,
+    Focus(Vec<ProcessId>),
+    Unfocus,
+    Filter(Option<OutputFilter>),
+    Attach(ProcessId),
+    Detach,
+    Input(ProcessId, Vec<u8>),
+    Tail(ProcessId, usize),
+    /// Render a report of how each command has run so far
+    Report(ReportFormat),
+    /// Start commands one after another, each after its delay from the previous one starting
+    Schedule(Vec<(String, Duration)>),
 }
 
 #[derive(Debug)]
 pub enum ProcessActionResponse {
     Created(ProcessId),
-    Waited(mpsc::Receiver<()>),
+    Waited(mpsc::Receiver<i32>),
     Killed,
     KilledAll,
+    RestartedAll(Vec<ProcessId>),
     List(Vec<ProcessId>),
-    Error(ProcessManagerError),
-}
-
-#[derive(Debug)]
-pub enum ProcessManagerError {
-    SpawnChildFailed(String),
-    KillChildFailed(String),
-    NoSuchProcess,
-    Unknown,
+    Status(Vec<ProcessStatus>),
+    Focused,
+    Unfocused(Vec<(ProcessId, usize)>),
+    Filtered,
+    Attached,
+    Detached(Vec<(ProcessId, usize)>),
+    InputSent,
+    Tail(Vec<String>),
+    Report(String),
+    Scheduled,
+    Error(ManagerError),
 }
 
-#[derive(Default, Clone)]
+#[derive(Debug, Default, Clone)]
 pub struct CreateOptions {
     pub stdio: Option<ProcessStdio>,
     pub cwd: Option<String>,
@@ -47,19 +97,229 @@ impl CreateOptions {
     }
 }
 
+#[derive(Debug, Clone)]
+pub struct ProcessStatus {
+    pub id: ProcessId,
+    pub state: ProcessState,
+    pub started_at: Instant,
+    pub exited_at: Option<Instant>,
+    pub cwd: Option<String>,
+    /// Percentage of a CPU core used since the previous status, while running
+    pub cpu: Option<f32>,
+    /// Resident memory in bytes, while running
+    pub memory: Option<u64>,
+    pub pid: Option<u32>,
+    /// Times the command was restarted before this process was started
+    pub restarts: usize,
+    /// The command restarted too often recently and is no longer restarted automatically
+    pub crash_looping: bool,
+}
+
+impl ProcessStatus {
+    pub fn uptime(&self) -> Duration {
+        self.exited_at
+            .unwrap_or_else(Instant::now)
+            .duration_since(self.started_at)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProcessState {
+    /// Waiting for the command's `wait_for` condition before starting
+    Waiting,
+    Running,
+    Stopping,
+    Restarting,
+    Exited(i32),
+}
+
+impl std::fmt::Display for ProcessState {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ProcessState::Waiting => write!(f, "waiting"),
+            ProcessState::Running => write!(f, "running"),
+            ProcessState::Stopping => write!(f, "stopping"),
+            ProcessState::Restarting => write!(f, "restarting"),
+            ProcessState::Exited(code) => write!(f, "exited ({})", code),
+        }
+    }
+}
+
+/// Number of exited processes to keep around for status listings
+const MAX_EXITED_STATUSES: usize = 32;
+
+/// Backoff before the first retry of a process that failed to start, doubled for each retry
+const SPAWN_RETRY_BACKOFF: Duration = Duration::from_millis(250);
+const MAX_SPAWN_RETRY_BACKOFF: Duration = Duration::from_secs(4);
+
+/// How long to wait before acting on all processes having exited, or after killing them, in
+/// case new commands are about to be started. Also the polling interval if process exits
+/// can't be watched.
+const IDLE_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// How often the memory of processes with a `max_memory` limit is checked
+const MEMORY_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+/// How often processes with an `idle_after` policy are checked for output and CPU use
+const ACTIVITY_CHECK_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Share of the time since the last check a process has to spend on the CPU to count as active,
+/// so watchers polling for changes still count as idle
+const ACTIVE_CPU_SHARE: f64 = 0.01;
+
+/// Exit status of processes killed for running past their timeout, as with `timeout(1)`
+const TIMED_OUT_STATUS: i32 = 124;
+
+/// How long processes have to exit after being killed before they are sent SIGKILL
+pub const DEFAULT_KILL_TIMEOUT: Duration = Duration::from_secs(10);
+
 pub struct Message(ProcessAction, mpsc::Sender<ProcessActionResponse>);
 
+enum ManagerEvent {
+    Message(Message),
+    /// A child process may have exited
+    ChildExited,
+    /// The `wait_for` condition of a waiting process was met
+    Ready(ProcessId),
+    /// A line of a process's output matched one of its `on_output` rules
+    OutputMatched(ProcessId, OutputAction),
+}
+
+/// A process waiting for its `wait_for` condition to be met before it is spawned. Dropping it
+/// stops checking the condition.
+struct WaitingProcess {
+    cwd: Option<String>,
+    stdio: ProcessStdio,
+    cancelled: Arc<AtomicBool>,
+    /// Set when waiting to try starting the process again after it failed to start
+    retry: Option<SpawnRetry>,
+}
+
+struct SpawnRetry {
+    /// How many times starting the process has been retried, including this one
+    attempt: u32,
+    at: Instant,
+}
+
+impl Drop for WaitingProcess {
+    fn drop(&mut self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+}
+
+/// A process running its command in a container or in WSL, where signals sent to the local
+/// process don't reach it, so it's signalled through docker or WSL as well
+enum Guest {
+    Container(RunningContainer),
+    Wsl(WslProcess),
+}
+
+impl Guest {
+    fn signal(&self, signal: Option<&ProcessSignal>) -> std::thread::JoinHandle<()> {
+        match self {
+            Guest::Container(container) => container.signal(signal),
+            Guest::Wsl(wsl) => wsl.signal(signal),
+        }
+    }
+}
+
+/// When a process with an `idle_after` policy was last seen printing output or using CPU
+struct Activity {
+    active_at: Instant,
+    sampled_at: Instant,
+    cpu_time: Duration,
+    /// The process has been reported idle, and isn't again until it is active
+    reported: bool,
+}
+
+impl Activity {
+    fn new() -> Self {
+        let now = Instant::now();
+        Self {
+            active_at: now,
+            sampled_at: now,
+            cpu_time: Duration::ZERO,
+            reported: false,
+        }
+    }
+}
+
 pub struct ProcessManager {
     processes: HashMap<ProcessId, Process>,
-    receiver: mpsc::Receiver<Message>,
-    sender: mpsc::Sender<Message>,
-    wait_handles: HashMap<ProcessId, mpsc::Sender<()>>,
+    receiver: mpsc::Receiver<ManagerEvent>,
+    sender: mpsc::Sender<ManagerEvent>,
+    wait_handles: HashMap<ProcessId, mpsc::Sender<i32>>,
+    options: HashMap<ProcessId, CreateOptions>,
+    waiting: HashMap<ProcessId, WaitingProcess>,
+    /// Commands waiting to be started, with their delay from the previous command starting
+    scheduled: VecDeque<(String, Duration)>,
+    next_scheduled: Option<Instant>,
+    /// Commands run again on an interval or schedule, with when they are next due
+    recurring: HashMap<String, (Recurrence, Instant)>,
+    /// Commands to run in order once stopping, with the commands of a group in parallel
+    shutdown: VecDeque<Vec<String>>,
+    /// Shutdown commands have started, all other processes have exited
+    shutting_down: bool,
+    kill_timeout: Duration,
+    /// When processes still running after killing all of them are sent SIGKILL
+    force_kill_at: Option<Instant>,
+    /// When processes with a `timeout` are stopped
+    deadlines: HashMap<ProcessId, Instant>,
+    /// Processes killed for running past their timeout, which count as failed
+    timed_out: HashSet<ProcessId>,
+    /// When the memory of processes was last checked against their limits
+    memory_checked_at: Instant,
+    /// Activity of processes with an `idle_after` policy
+    activity: HashMap<ProcessId, Activity>,
+    /// When processes were last checked against their `idle_after` policy
+    activity_checked_at: Instant,
+    /// How many times each command has been restarted, by hand or automatically
+    restarts: HashMap<String, usize>,
+    /// Recent restarts of each command, to stop restarting those that are crash-looping
+    restart_history: RestartHistory,
+    statuses: Vec<ProcessStatus>,
+    /// CPU time of running processes when their status was last requested
+    cpu_samples: HashMap<ProcessId, (Instant, Duration)>,
     index: u32,
     raw_stdio: bool,
     exit_on_error: bool,
     quit_on_completion: bool,
+    /// Exit the process when the manager stops, rather than just ending its thread
+    exit_process: bool,
     killed: bool,
+    /// All processes have exited, checked again after `IDLE_TIMEOUT`
+    all_exited: bool,
+    /// Check processes for exits every `IDLE_TIMEOUT`, when they can't be watched
+    poll_exits: bool,
     cwd: Option<String>,
+    commands: Vec<CommandConfig>,
+    pty: bool,
+    spawn_retries: u32,
+    output_mode: OutputMode,
+    output_timestamps: bool,
+    output_filter: Option<OutputFilter>,
+    output: Option<OutputHandle>,
+    log_sink: Option<LogSinkKind>,
+    sink: Option<Arc<LogSink>>,
+    log_forward: Option<ForwardTarget>,
+    otlp_endpoint: Option<OtlpEndpoint>,
+    tracer: Option<Tracer>,
+    report_target: Option<ReportTarget>,
+    report: Option<RunReport>,
+    ports_mode: Option<PortsMode>,
+    ports: PortAssignments,
+    hyperlink_format: Option<String>,
+    /// Processes running in a docker container or WSL, which are signalled there too
+    guests: HashMap<ProcessId, Guest>,
+    /// Process groups recorded for `together cleanup`
+    groups: Option<ProcessGroups>,
+    supervisor: Option<Supervisor>,
+}
+
+impl Default for ProcessManager {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl ProcessManager {
@@ -70,12 +330,53 @@ impl ProcessManager {
             receiver,
             sender,
             wait_handles: HashMap::new(),
+            options: HashMap::new(),
+            waiting: HashMap::new(),
+            scheduled: VecDeque::new(),
+            next_scheduled: None,
+            recurring: HashMap::new(),
+            shutdown: VecDeque::new(),
+            shutting_down: false,
+            kill_timeout: DEFAULT_KILL_TIMEOUT,
+            force_kill_at: None,
+            deadlines: HashMap::new(),
+            timed_out: HashSet::new(),
+            memory_checked_at: Instant::now(),
+            activity: HashMap::new(),
+            activity_checked_at: Instant::now(),
+            restarts: HashMap::new(),
+            restart_history: RestartHistory::new(None),
+            statuses: Vec::new(),
+            cpu_samples: HashMap::new(),
             index: 0,
             raw_stdio: false,
             exit_on_error: false,
             quit_on_completion: true,
+            exit_process: true,
             killed: false,
+            all_exited: false,
+            poll_exits: false,
             cwd: None,
+            commands: Vec::new(),
+            pty: false,
+            spawn_retries: 0,
+            output_mode: OutputMode::default(),
+            output_timestamps: false,
+            output_filter: None,
+            output: None,
+            log_sink: None,
+            sink: None,
+            log_forward: None,
+            otlp_endpoint: None,
+            tracer: None,
+            report_target: None,
+            report: None,
+            ports_mode: None,
+            ports: PortAssignments::default(),
+            hyperlink_format: None,
+            guests: HashMap::new(),
+            groups: None,
+            supervisor: None,
         }
     }
 
@@ -94,12 +395,170 @@ impl ProcessManager {
         self
     }
 
+    pub fn with_process_exit(mut self, exit_process: bool) -> Self {
+        self.exit_process = exit_process;
+        self
+    }
+
     pub fn with_working_directory(mut self, working_directory: Option<String>) -> Self {
         self.cwd = working_directory;
         self
     }
 
-    pub fn start(self) -> ProcessManagerHandle {
+    /// Configured commands, used to look up per-command settings when they are started
+    pub fn with_commands(mut self, commands: Vec<CommandConfig>) -> Self {
+        self.commands = commands;
+        self
+    }
+
+    pub fn with_pty(mut self, pty: bool) -> Self {
+        self.pty = pty;
+        self
+    }
+
+    /// How long processes have to exit after being killed before they are sent SIGKILL
+    pub fn with_kill_timeout(mut self, kill_timeout: Duration) -> Self {
+        self.kill_timeout = kill_timeout;
+        self
+    }
+
+    /// Retry starting a process this many times if it fails to start, waiting longer before
+    /// each attempt
+    pub fn with_spawn_retries(mut self, spawn_retries: u32) -> Self {
+        self.spawn_retries = spawn_retries;
+        self
+    }
+
+    pub fn with_output_mode(mut self, output_mode: OutputMode) -> Self {
+        self.output_mode = output_mode;
+        self
+    }
+
+    pub fn with_timestamps(mut self, timestamps: bool) -> Self {
+        self.output_timestamps = timestamps;
+        self
+    }
+
+    pub fn with_output_filter(mut self, output_filter: Option<OutputFilter>) -> Self {
+        self.output_filter = output_filter;
+        self
+    }
+
+    /// Forward process output and lifecycle events to syslog or journald
+    pub fn with_log_sink(mut self, log_sink: Option<LogSinkKind>) -> Self {
+        self.log_sink = log_sink;
+        self
+    }
+
+    /// Stream process output as ndjson to a remote collector
+    pub fn with_log_forward(mut self, log_forward: Option<ForwardTarget>) -> Self {
+        self.log_forward = log_forward;
+        self
+    }
+
+    /// Export a span for each process, from when it is spawned until it exits, to an OTLP
+    /// collector
+    pub fn with_otlp_endpoint(mut self, otlp_endpoint: Option<OtlpEndpoint>) -> Self {
+        self.otlp_endpoint = otlp_endpoint;
+        self
+    }
+
+    /// Write a report of how each command ran to a file when the session ends
+    pub fn with_report(mut self, report_target: Option<ReportTarget>) -> Self {
+        self.report_target = report_target;
+        self
+    }
+
+    /// Pick a port for each configured command when starting, passed to all of them through
+    /// the environment
+    pub fn with_ports(mut self, ports_mode: Option<PortsMode>) -> Self {
+        self.ports_mode = ports_mode;
+        self
+    }
+
+    /// Link file references in output with this format instead of `file://` URLs, filling
+    /// in `{path}`, `{line}` and `{column}`
+    pub fn with_hyperlink_format(mut self, hyperlink_format: Option<String>) -> Self {
+        self.hyperlink_format = hyperlink_format;
+        self
+    }
+
+    pub fn with_supervisor(mut self, options: Option<SupervisorOptions>) -> Self {
+        self.supervisor = options.map(Supervisor::new);
+        self
+    }
+
+    /// How often a command can be restarted before it counts as crash-looping and is no longer
+    /// restarted automatically
+    pub fn with_crash_loop_limit(mut self, limit: Option<&CrashLoopLimit>) -> Self {
+        self.restart_history = RestartHistory::new(limit);
+        self
+    }
+
+    /// Commands to run once all processes have been killed or have exited, before the manager
+    /// stops. The commands of a group run in parallel.
+    pub fn with_shutdown_commands(mut self, groups: Vec<Vec<String>>) -> Self {
+        self.shutdown = groups.into_iter().filter(|g| !g.is_empty()).collect();
+        self
+    }
+
+    pub fn start(mut self) -> ProcessManagerHandle {
+        if let Some(PortsMode::Auto) = self.ports_mode {
+            match PortAssignments::assign(&self.commands) {
+                Ok(ports) => {
+                    ports.log();
+                    self.ports = ports;
+                }
+                Err(e) => {
+                    log_err!("Failed to assign ports: {}", e);
+                }
+            }
+        }
+        self.sink = self
+            .log_sink
+            .and_then(|kind| match LogSink::connect(kind, &self.commands) {
+                Ok(sink) => Some(Arc::new(sink)),
+                Err(e) => {
+                    log_err!("Failed to connect to {}, not forwarding logs: {}", kind, e);
+                    None
+                }
+            });
+        let forwarder = self
+            .log_forward
+            .take()
+            .map(|target| LogForwarder::start(target, &self.commands));
+        let output = OutputHandle::new(self.output_mode, self.output_timestamps)
+            .with_log_sink(self.sink.clone())
+            .with_log_forwarder(forwarder)
+            .with_linker(Linker::detect(self.hyperlink_format.take()))
+            .with_triggers(&self.commands, {
+                let matched = self.sender.clone();
+                Box::new(move |id, action| {
+                    _ = matched.send(ManagerEvent::OutputMatched(id.clone(), action.clone()));
+                })
+            });
+        output.filter(self.output_filter.take());
+        output.open_urls(
+            self.commands
+                .iter()
+                .filter(|c| c.opens_url())
+                .map(|c| c.as_str().to_string()),
+        );
+        self.output = Some(output);
+        self.tracer = self
+            .otlp_endpoint
+            .take()
+            .map(|endpoint| Tracer::start(endpoint, &self.commands));
+        self.report = Some(RunReport::new(&self.commands));
+        self.groups = ProcessGroups::create();
+
+        let exits = self.sender.clone();
+        let notify = move || exits.send(ManagerEvent::ChildExited).is_ok();
+        if let Err(e) = process::watch_exits(notify) {
+            log_err!("Failed to watch for process exits, polling instead: {}", e);
+            self.poll_exits = true;
+        }
+
         let sender = self.sender.clone();
         let thread = std::thread::spawn(move || self.rx_message_loop());
         ProcessManagerHandle {
@@ -109,132 +568,753 @@ impl ProcessManager {
     }
 
     fn rx_message_loop(mut self) {
-        let timeout = std::time::Duration::from_millis(100);
         loop {
-            match self.receiver.recv_timeout(timeout) {
-                Ok(message) => {
+            let event = match self.next_timeout() {
+                Some(timeout) => self.receiver.recv_timeout(timeout),
+                None => self
+                    .receiver
+                    .recv()
+                    .map_err(|_| mpsc::RecvTimeoutError::Disconnected),
+            };
+            match event {
+                Ok(ManagerEvent::Message(message)) => {
+                    trace!("message: {:?}", message.0);
                     let response = self.process_message(message.0);
+                    trace!("response: {:?}", response);
                     message.1.send(response).unwrap();
                 }
+                Ok(ManagerEvent::ChildExited) => {
+                    trace!("child exited");
+                    self.handle_exits();
+                }
+                Ok(ManagerEvent::Ready(id)) => {
+                    trace!("wait condition met: {}", id);
+                    self.start_waiting(id);
+                }
+                Ok(ManagerEvent::OutputMatched(id, action)) => {
+                    trace!("output matched: {} {:?}", id, action);
+                    self.run_output_action(id, action);
+                }
                 Err(mpsc::RecvTimeoutError::Timeout) => {
-                    if self.killed {
+                    trace!(
+                        "timed out waiting for events, killed: {}, all exited: {}",
+                        self.killed,
+                        self.all_exited
+                    );
+                    let stopping =
+                        self.killed || (self.all_exited && self.handle_all_exited().is_break());
+                    if stopping && self.shutdown().is_break() {
                         break;
                     }
-                    if !self.processes.is_empty() {
-                        self.cleanup_dead_processes();
-
-                        if self.processes.is_empty() {
-                            if self.quit_on_completion || self.killed {
-                                log!("All processes have exited, stopping...");
-                                std::process::exit(0);
-                            }
-
-                            match self
-                                .receiver
-                                .recv_timeout(std::time::Duration::from_millis(100))
-                            {
-                                Ok(Message(ProcessAction::KillAll, _)) => {
-                                    std::process::exit(0);
-                                }
-                                Ok(message) => {
-                                    let response = self.process_message(message.0);
-                                    message.1.send(response).unwrap();
-                                }
-                                Err(mpsc::RecvTimeoutError::Timeout) => {
-                                    log!("No more processes running, waiting for new commands...");
-                                }
-                                Err(mpsc::RecvTimeoutError::Disconnected) => {
-                                    break;
-                                }
-                            }
-                        }
+                    if self.poll_exits {
+                        self.handle_exits();
                     }
                 }
                 Err(mpsc::RecvTimeoutError::Disconnected) => {
+                    debug!("all handles dropped, stopping the message loop");
                     break;
                 }
             }
+            self.start_scheduled();
+            self.retry_spawns();
+            self.run_recurring();
+            self.force_kill_overdue();
+            self.stop_timed_out();
+            self.check_memory();
+            self.check_idle();
+            self.supervise();
+        }
+
+        debug!("message loop stopped");
+        control::unregister();
+        if let Some(groups) = &self.groups {
+            groups.close();
+        }
+        if let Some(tracer) = &mut self.tracer {
+            tracer.flush();
+        }
+        if let (Some(report), Some(target)) = (&self.report, &self.report_target) {
+            report.write(target);
+        }
+        if self.exit_process {
+            std::process::exit(0);
+        }
+    }
+
+    /// How long to wait for the next event before there is something else to do, if at all
+    fn next_timeout(&self) -> Option<Duration> {
+        let idle = self.killed || self.all_exited || self.poll_exits;
+        let supervise = self
+            .supervisor
+            .as_ref()
+            .map(|s| s.next_due().saturating_duration_since(Instant::now()));
+        let scheduled = self
+            .next_scheduled
+            .map(|at| at.saturating_duration_since(Instant::now()));
+        let force_kill = self
+            .force_kill_at
+            .map(|at| at.saturating_duration_since(Instant::now()));
+        let recurring = self
+            .recurring
+            .values()
+            .map(|(_, at)| at.saturating_duration_since(Instant::now()));
+        let deadlines = self
+            .deadlines
+            .values()
+            .map(|at| at.saturating_duration_since(Instant::now()));
+        let retries = self
+            .waiting
+            .values()
+            .filter_map(|w| w.retry.as_ref())
+            .map(|retry| retry.at.saturating_duration_since(Instant::now()));
+        let memory_check = self
+            .has_memory_limits()
+            .then(|| self.memory_checked_at + MEMORY_CHECK_INTERVAL)
+            .map(|at| at.saturating_duration_since(Instant::now()));
+        let activity_check = (!self.killed && !self.activity.is_empty())
+            .then(|| self.activity_checked_at + ACTIVITY_CHECK_INTERVAL)
+            .map(|at| at.saturating_duration_since(Instant::now()));
+        idle.then_some(IDLE_TIMEOUT)
+            .into_iter()
+            .chain(supervise)
+            .chain(scheduled)
+            .chain(recurring)
+            .chain(force_kill)
+            .chain(deadlines)
+            .chain(retries)
+            .chain(memory_check)
+            .chain(activity_check)
+            .min()
+    }
+
+    fn handle_exits(&mut self) {
+        if self.processes.is_empty() {
+            return;
+        }
+        self.cleanup_dead_processes();
+        self.all_exited = self.is_complete();
+    }
+
+    fn handle_all_exited(&mut self) -> ControlFlow<()> {
+        self.all_exited = false;
+        if !self.is_complete() {
+            return ControlFlow::Continue(());
+        }
+        if self.quit_on_completion {
+            log!("All processes have exited, stopping...");
+            // oneshot commands are not waited for once the long-running commands have exited
+            if !self.processes.is_empty() {
+                self.process_message(ProcessAction::KillAll);
+            }
+            return ControlFlow::Break(());
+        }
+        log!("No more processes running, waiting for new commands...");
+        ControlFlow::Continue(())
+    }
+
+    /// Runs the next group of shutdown commands once all processes have exited, breaking
+    /// when there are none left
+    fn shutdown(&mut self) -> ControlFlow<()> {
+        self.killed = true;
+        // processes are force killed if they haven't exited by the kill timeout
+        if !self.processes.is_empty() {
+            return ControlFlow::Continue(());
+        }
+        if !self.shutting_down && self.shutdown.is_empty() {
+            return ControlFlow::Break(());
+        }
+        let Some(group) = self.shutdown.pop_front() else {
+            return ControlFlow::Break(());
+        };
+        if !self.shutting_down {
+            log!("Running shutdown commands...");
+            self.shutting_down = true;
+        }
+        for command in group {
+            let id = self.index;
+            self.index += 1;
+
+            let cwd = self.command_cwd(&command);
+            let stdio = self.command_stdio(&command);
+            let response = self.start_new_process(command.clone(), cwd, stdio, id);
+            if let ProcessActionResponse::Error(e) = response {
+                log_err!("Shutdown command failed: {}", e);
+            }
+        }
+        ControlFlow::Continue(())
+    }
+
+    /// Whether every process has exited, with nothing left to start. Oneshot commands still
+    /// running are only waited for when no long-running commands were started.
+    fn is_complete(&self) -> bool {
+        if self.has_pending_starts() {
+            return false;
+        }
+        let long_running = |id: &ProcessId| !self.is_oneshot(id.command());
+        let running = self.processes.keys().any(long_running);
+        let started = self.statuses.iter().any(|s| long_running(&s.id));
+        !running && (self.processes.is_empty() || started)
+    }
+
+    /// Whether processes are waiting to be restarted, started from the schedule, run again
+    /// on their `every` or `schedule` setting or waiting for their `wait_for` condition
+    fn has_pending_starts(&self) -> bool {
+        !self.scheduled.is_empty()
+            || !self.waiting.is_empty()
+            || !self.recurring.is_empty()
+            || self.supervisor.as_ref().is_some_and(|s| s.has_pending())
+    }
+
+    fn start_scheduled(&mut self) {
+        while let Some(at) = self.next_scheduled {
+            if at > Instant::now() {
+                return;
+            }
+            let Some((command, _)) = self.scheduled.pop_front() else {
+                break;
+            };
+            let response = self.process_message(ProcessAction::Create(command.clone()));
+            if let ProcessActionResponse::Error(e) = response {
+                log_err!("{}", e);
+            }
+            self.next_scheduled = self.schedule_next();
+        }
+        self.next_scheduled = None;
+    }
+
+    /// Starts recurring commands that are due, skipping a run while the previous one is
+    /// still going
+    fn run_recurring(&mut self) {
+        let now = Instant::now();
+        let due: Vec<_> = self
+            .recurring
+            .iter()
+            .filter(|(_, (_, at))| *at <= now)
+            .map(|(command, _)| command.clone())
+            .collect();
+        for command in due {
+            let running = self
+                .statuses
+                .iter()
+                .filter(|s| matches!(s.state, ProcessState::Running | ProcessState::Waiting))
+                .find(|s| s.id.command() == command);
+            match running {
+                Some(status) => {
+                    log!("Skipping scheduled run of {}, still running", status.id);
+                }
+                None => {
+                    let response = self.process_message(ProcessAction::Create(command.clone()));
+                    if let ProcessActionResponse::Error(e) = response {
+                        log_err!("{}", e);
+                    }
+                }
+            }
+            let Some((recurrence, _)) = self.recurring.get(&command) else {
+                continue;
+            };
+            match recurrence.next(now) {
+                Some(next) => self.recurring.insert(command, (recurrence.clone(), next)),
+                None => self.recurring.remove(&command),
+            };
+        }
+    }
+
+    /// Sends SIGKILL to processes still running once the kill timeout has passed
+    fn force_kill_overdue(&mut self) {
+        let Some(at) = self.force_kill_at else {
+            return;
+        };
+        if self.processes.is_empty() || self.shutting_down {
+            self.force_kill_at = None;
+            return;
+        }
+        if at > Instant::now() {
+            return;
+        }
+        self.force_kill_at = None;
+        log_err!(
+            "Processes still running {}s after being killed, force killing them...",
+            self.kill_timeout.as_secs()
+        );
+        self.force_kill_all();
+    }
+
+    fn force_kill_all(&mut self) {
+        debug!("force killing {} processes", self.processes.len());
+        let mut signalled = vec![];
+        for (id, child) in self.processes.iter_mut() {
+            if let Err(e) = child.kill(Some(&ProcessSignal::SIGKILL)) {
+                log_err!("Failed to force kill {}: {}", id, e);
+            }
+            if let Some(guest) = self.guests.get(id) {
+                signalled.push(guest.signal(Some(&ProcessSignal::SIGKILL)));
+            }
+        }
+        // guests have to be killed before exiting, unlike on a graceful kill where docker or
+        // WSL only exit once the command they run has
+        for handle in signalled {
+            _ = handle.join();
+        }
+    }
+
+    /// Kills or restarts processes that have run past their timeout
+    fn stop_timed_out(&mut self) {
+        let now = Instant::now();
+        let overdue: Vec<_> = self
+            .deadlines
+            .iter()
+            .filter(|(_, at)| **at <= now)
+            .map(|(id, _)| id.clone())
+            .collect();
+        for id in overdue {
+            self.deadlines.remove(&id);
+            if self.killed || !matches!(self.state(&id), Some(ProcessState::Running)) {
+                continue;
+            }
+            let Some(command) = self.commands.iter().find(|c| c.matches(id.command())) else {
+                continue;
+            };
+            let timeout = command.details().and_then(|d| d.timeout.clone());
+            let timeout = timeout.unwrap_or_default();
+            let response = match command.on_timeout() {
+                TimeoutAction::Kill => {
+                    log_err!("{} timed out after {}, killing it", id, timeout);
+                    self.timed_out.insert(id.clone());
+                    self.process_message(ProcessAction::Kill(id.clone()))
+                }
+                TimeoutAction::Restart => {
+                    log_err!("{} timed out after {}, restarting it", id, timeout);
+                    self.restart_unless_crash_looping(id.clone())
+                }
+            };
+            if let ProcessActionResponse::Error(e) = response {
+                log_err!("Failed to stop {} after its timeout: {}", id, e);
+            }
+        }
+    }
+
+    fn has_memory_limits(&self) -> bool {
+        let limited = |id: &ProcessId| self.command_max_memory(id.command()).is_some();
+        !self.killed && self.processes.keys().any(limited)
+    }
+
+    /// Restarts processes using more memory than their `max_memory`
+    fn check_memory(&mut self) {
+        if self.memory_checked_at.elapsed() < MEMORY_CHECK_INTERVAL || !self.has_memory_limits() {
+            return;
+        }
+        self.memory_checked_at = Instant::now();
+        let over_limit: Vec<_> = self
+            .processes
+            .iter()
+            .filter_map(|(id, child)| {
+                let limit = self.command_max_memory(id.command())?;
+                let memory = child.usage()?.memory;
+                (memory > limit).then(|| (id.clone(), memory, limit))
+            })
+            .collect();
+        for (id, memory, limit) in over_limit {
+            if !matches!(self.state(&id), Some(ProcessState::Running)) {
+                continue;
+            }
+            log_err!(
+                "{} is using {}, over its max_memory of {}, restarting it",
+                id,
+                terminal::format_bytes(memory),
+                terminal::format_bytes(limit)
+            );
+            if let ProcessActionResponse::Error(e) = self.restart_unless_crash_looping(id.clone()) {
+                log_err!("Failed to restart {}: {}", id, e);
+            }
+        }
+    }
+
+    /// Notifies about or stops processes that haven't printed output or used CPU for their
+    /// `idle_after`
+    fn check_idle(&mut self) {
+        if self.killed
+            || self.activity.is_empty()
+            || self.activity_checked_at.elapsed() < ACTIVITY_CHECK_INTERVAL
+        {
+            return;
+        }
+        let now = Instant::now();
+        self.activity_checked_at = now;
+        let mut idle = vec![];
+        for (id, activity) in &mut self.activity {
+            let Some(child) = self.processes.get(id) else {
+                continue;
+            };
+            if let Some(usage) = child.usage() {
+                let used = usage.cpu_time.saturating_sub(activity.cpu_time);
+                let elapsed = now.duration_since(activity.sampled_at);
+                if used.as_secs_f64() > elapsed.as_secs_f64() * ACTIVE_CPU_SHARE {
+                    activity.active_at = now;
+                }
+                activity.cpu_time = usage.cpu_time;
+                activity.sampled_at = now;
+            }
+            let last_output = self.output.as_ref().and_then(|o| o.last_output(id));
+            if let Some(last_output) = last_output.filter(|at| *at > activity.active_at) {
+                activity.active_at = last_output;
+            }
+            let command = self.commands.iter().find(|c| c.matches(id.command()));
+            let Some(idle_after) = command.and_then(|c| c.idle_after()) else {
+                continue;
+            };
+            let idle_for = now.duration_since(activity.active_at);
+            if idle_for < idle_after {
+                activity.reported = false;
+            } else if !activity.reported {
+                activity.reported = true;
+                let action = command.map(|c| c.on_idle()).unwrap_or_default();
+                idle.push((id.clone(), idle_for, action));
+            }
+        }
+        for (id, idle_for, action) in idle {
+            if !matches!(self.state(&id), Some(ProcessState::Running)) {
+                continue;
+            }
+            let idle_for = terminal::format_duration(idle_for);
+            match action {
+                IdleAction::Notify => {
+                    let message = format!("{} has been idle for {}", id, idle_for);
+                    log_err!("{}", message);
+                    process::notify("together", &message);
+                }
+                IdleAction::Stop => {
+                    log_err!("{} has been idle for {}, stopping it", id, idle_for);
+                    if let ProcessActionResponse::Error(e) =
+                        self.process_message(ProcessAction::Kill(id.clone()))
+                    {
+                        log_err!("Failed to stop {}: {}", id, e);
+                    }
+                }
+            }
         }
+    }
 
-        std::process::exit(0);
+    fn stop_recurring(&mut self, id: &ProcessId) {
+        if self.recurring.remove(id.command()).is_some() {
+            log!("Stopped running '{}' on its schedule", id.command());
+        }
+    }
+
+    fn schedule_next(&self) -> Option<Instant> {
+        let (command, delay) = self.scheduled.front()?;
+        if !delay.is_zero() {
+            log!("Starting '{}' in {}ms", command, delay.as_millis());
+        }
+        Some(Instant::now() + *delay)
     }
 
     fn process_message(&mut self, payload: ProcessAction) -> ProcessActionResponse {
         match payload {
+            // nothing new starts once all processes are being stopped
+            ProcessAction::Create(_) | ProcessAction::CreateAdvanced(..) if self.killed => {
+                ProcessActionResponse::Error(ManagerError::Stopped)
+            }
             ProcessAction::Create(command) => {
+                self.restart_history.reset(&command);
                 let id = self.index;
                 self.index += 1;
 
-                self.start_new_process(command, self.cwd.clone(), self.raw_stdio.into(), id)
+                let cwd = self.command_cwd(&command);
+                let stdio = self.command_stdio(&command);
+                self.start_new_process(command, cwd, stdio, id)
             }
             ProcessAction::CreateAdvanced(command, options) => {
+                self.restart_history.reset(&command);
                 let id = self.index;
                 self.index += 1;
 
-                let raw = options.stdio.unwrap_or(self.raw_stdio.into());
-                let cwd = options.cwd.clone().or_else(|| self.cwd.clone());
+                let raw = options.stdio.unwrap_or(self.command_stdio(&command));
+                let cwd = options.cwd.clone().or_else(|| self.command_cwd(&command));
 
                 self.start_new_process(command, cwd, raw, id)
             }
-            ProcessAction::Wait(id) => match self.processes.get(&id) {
-                Some(_) => {
-                    let (sender, receiver) = mpsc::channel();
-                    self.wait_handles.insert(id.clone(), sender);
-                    ProcessActionResponse::Waited(receiver)
+            ProcessAction::Wait(id) => {
+                let (sender, receiver) = mpsc::channel();
+                let alive = self.processes.contains_key(&id) || self.waiting.contains_key(&id);
+                match (alive, self.state(&id)) {
+                    (true, _) => {
+                        self.wait_handles.insert(id.clone(), sender);
+                    }
+                    // the process may exit before the wait arrives
+                    (false, Some(ProcessState::Exited(status))) => sender.send(status).unwrap(),
+                    (false, _) => return ProcessActionResponse::Error(ManagerError::NoSuchProcess),
                 }
-                None => ProcessActionResponse::Error(ProcessManagerError::NoSuchProcess),
-            },
+                ProcessActionResponse::Waited(receiver)
+            }
+            ProcessAction::Kill(id) | ProcessAction::KillAdvanced(id, _)
+                if self.waiting.contains_key(&id) =>
+            {
+                self.cancel_waiting(&id);
+                self.stop_recurring(&id);
+                ProcessActionResponse::Killed
+            }
             ProcessAction::Kill(id) => match self.processes.get_mut(&id) {
                 Some(child) => match child.kill(None) {
                     Ok(_) => {
                         log!("Killing {}", id);
+                        if let Some(guest) = self.guests.get(&id) {
+                            guest.signal(None);
+                        }
+                        self.set_state(&id, ProcessState::Stopping);
+                        self.log_event(&id, Priority::Notice, "killing");
+                        self.stop_recurring(&id);
                         ProcessActionResponse::Killed
                     }
-                    Err(e) => ProcessActionResponse::Error(ProcessManagerError::KillChildFailed(
-                        e.to_string(),
-                    )),
+                    Err(e) => ProcessActionResponse::Error(ManagerError::KillFailed(e.to_string())),
                 },
-                None => ProcessActionResponse::Error(ProcessManagerError::NoSuchProcess),
+                None => ProcessActionResponse::Error(ManagerError::NoSuchProcess),
             },
             ProcessAction::KillAdvanced(id, signal) => match self.processes.get_mut(&id) {
                 Some(child) => match child.kill(Some(&signal)) {
                     Ok(_) => {
                         log!("Killing {} with signal {:?}", id, signal);
+                        if let Some(guest) = self.guests.get(&id) {
+                            guest.signal(Some(&signal));
+                        }
+                        self.set_state(&id, ProcessState::Stopping);
+                        self.log_event(
+                            &id,
+                            Priority::Notice,
+                            &format!("killing with {:?}", signal),
+                        );
+                        self.stop_recurring(&id);
                         ProcessActionResponse::Killed
                     }
-                    Err(e) => ProcessActionResponse::Error(ProcessManagerError::KillChildFailed(
-                        e.to_string(),
-                    )),
+                    Err(e) => ProcessActionResponse::Error(ManagerError::KillFailed(e.to_string())),
                 },
-                None => ProcessActionResponse::Error(ProcessManagerError::NoSuchProcess),
+                None => ProcessActionResponse::Error(ManagerError::NoSuchProcess),
             },
+            // shutdown commands are left to finish
+            ProcessAction::KillAll if self.shutting_down => ProcessActionResponse::KilledAll,
+            ProcessAction::ForceKillAll => {
+                self.killed = true;
+                self.force_kill_all();
+                ProcessActionResponse::KilledAll
+            }
             ProcessAction::KillAll => {
+                debug!(
+                    "killing all {} processes, force killing after {}s",
+                    self.processes.len(),
+                    self.kill_timeout.as_secs()
+                );
                 self.killed = true;
+                self.force_kill_at = Some(Instant::now() + self.kill_timeout);
+                self.scheduled.clear();
+                self.next_scheduled = None;
+                self.recurring.clear();
+                for id in self.waiting.keys().cloned().collect::<Vec<_>>() {
+                    self.cancel_waiting(&id);
+                }
 
                 let mut errors = vec![];
                 for (id, child) in self.processes.iter_mut() {
                     match child.kill(None) {
                         Ok(_) => {
                             log!("Killing {}", id);
+                            if let Some(guest) = self.guests.get(id) {
+                                guest.signal(None);
+                            }
+                            if let Some(sink) = &self.sink {
+                                sink.send(id, Priority::Notice, "killing");
+                            }
                         }
                         Err(e) => {
-                            errors.push(ProcessManagerError::KillChildFailed(e.to_string()));
+                            errors.push(ManagerError::KillFailed(e.to_string()));
                         }
                     }
                 }
                 if errors.is_empty() {
                     ProcessActionResponse::KilledAll
                 } else {
-                    ProcessActionResponse::Error(ProcessManagerError::Unknown)
+                    ProcessActionResponse::Error(ManagerError::Unknown)
+                }
+            }
+            ProcessAction::Restart(id) => self.restart_process(id),
+            ProcessAction::RestartAll => {
+                let running: Vec<_> = self
+                    .statuses
+                    .iter()
+                    .filter(|s| s.state == ProcessState::Running)
+                    .map(|s| s.id.clone())
+                    .collect();
+
+                let mut restarted = vec![];
+                let mut errors = vec![];
+                for id in running {
+                    match self.restart_process(id) {
+                        ProcessActionResponse::Created(id) => restarted.push(id),
+                        ProcessActionResponse::Error(e) => errors.push(e),
+                        _ => errors.push(ManagerError::Unknown),
+                    }
+                }
+                for e in &errors {
+                    log_err!("Failed to restart process: {}", e);
                 }
+                ProcessActionResponse::RestartedAll(restarted)
             }
             ProcessAction::List => {
-                let list = self.processes.keys().cloned().collect();
+                let processes = self.processes.keys().chain(self.waiting.keys());
+                let list = processes.cloned().collect();
                 ProcessActionResponse::List(list)
             }
+            ProcessAction::Status => {
+                self.sample_usage();
+                let mut statuses = self.statuses.clone();
+                for status in &mut statuses {
+                    status.crash_looping =
+                        self.restart_history.is_crash_looping(status.id.command());
+                }
+                ProcessActionResponse::Status(statuses)
+            }
+            ProcessAction::Focus(ids) => {
+                if let Some(output) = &self.output {
+                    output.focus(ids);
+                }
+                ProcessActionResponse::Focused
+            }
+            ProcessAction::Unfocus => {
+                let hidden = self.output.as_ref().map(|o| o.unfocus());
+                ProcessActionResponse::Unfocused(hidden.unwrap_or_default())
+            }
+            ProcessAction::Filter(filter) => {
+                if let Some(output) = &self.output {
+                    output.filter(filter);
+                }
+                ProcessActionResponse::Filtered
+            }
+            ProcessAction::Attach(id) => {
+                if !self.processes.contains_key(&id) {
+                    return ProcessActionResponse::Error(ManagerError::NoSuchProcess);
+                }
+                if let Some(output) = &self.output {
+                    output.attach(id);
+                }
+                ProcessActionResponse::Attached
+            }
+            ProcessAction::Detach => {
+                let hidden = self.output.as_ref().map(|o| o.detach());
+                ProcessActionResponse::Detached(hidden.unwrap_or_default())
+            }
+            ProcessAction::Input(id, input) => match self.processes.get_mut(&id) {
+                Some(process) => match process.write_stdin(&input) {
+                    Ok(()) => ProcessActionResponse::InputSent,
+                    Err(e) => {
+                        log_err!("Failed to write to {}: {}", id, e);
+                        ProcessActionResponse::Error(ManagerError::NoSuchProcess)
+                    }
+                },
+                None => ProcessActionResponse::Error(ManagerError::NoSuchProcess),
+            },
+            ProcessAction::Schedule(commands) => {
+                let idle = self.scheduled.is_empty();
+                self.scheduled.extend(commands);
+                if idle {
+                    self.next_scheduled = self.schedule_next();
+                }
+                ProcessActionResponse::Scheduled
+            }
+            ProcessAction::Tail(id, lines) => {
+                match self.output.as_ref().and_then(|o| o.tail(&id, lines)) {
+                    Some(lines) => ProcessActionResponse::Tail(lines),
+                    None => ProcessActionResponse::Error(ManagerError::NoSuchProcess),
+                }
+            }
+            ProcessAction::Report(format) => match &self.report {
+                Some(report) => ProcessActionResponse::Report(report.render(format)),
+                None => ProcessActionResponse::Error(ManagerError::Unknown),
+            },
+        }
+    }
+
+    /// Runs the action of an `on_output` rule, unless the process is already stopping
+    fn run_output_action(&mut self, id: ProcessId, action: OutputAction) {
+        if self.killed || !matches!(self.state(&id), Some(ProcessState::Running)) {
+            return;
+        }
+        let response = match action {
+            OutputAction::Restart => {
+                log!("Output of {} matched, restarting it", id);
+                self.restart_unless_crash_looping(id.clone())
+            }
+            OutputAction::Kill => {
+                log!("Output of {} matched, killing it", id);
+                self.process_message(ProcessAction::Kill(id.clone()))
+            }
+            OutputAction::Run(command) => {
+                log!("Output of {} matched, running '{}'", id, command);
+                let options = self.options.get(&id).cloned().unwrap_or_default();
+                let index = self.index;
+                self.index += 1;
+                self.start_new_process(command, options.cwd, self.raw_stdio.into(), index)
+            }
+        };
+        if let ProcessActionResponse::Error(e) = response {
+            log_err!("Failed to run the output action of {}: {}", id, e);
         }
     }
 
+    fn restart_process(&mut self, id: ProcessId) -> ProcessActionResponse {
+        let Some(child) = self.processes.get_mut(&id) else {
+            return ProcessActionResponse::Error(ManagerError::NoSuchProcess);
+        };
+        if let Err(e) = child.kill(None) {
+            return ProcessActionResponse::Error(ManagerError::KillFailed(e.to_string()));
+        }
+        log!("Restarting {}", id);
+        self.log_event(&id, Priority::Notice, "restarting");
+        if let Some(tracer) = &mut self.tracer {
+            tracer.restarted(id.command());
+        }
+        if let Some(report) = &mut self.report {
+            report.restarted(id.command());
+        }
+        *self.restarts.entry(id.command().to_string()).or_default() += 1;
+        self.record_restart(id.command());
+        self.set_state(&id, ProcessState::Restarting);
+
+        let options = self.options.get(&id).cloned().unwrap_or_default();
+        let new_id = self.index;
+        self.index += 1;
+
+        let raw = options.stdio.unwrap_or(self.raw_stdio.into());
+        let cwd = options.cwd.clone().or_else(|| self.cwd.clone());
+
+        self.start_new_process(id.command().to_string(), cwd, raw, new_id)
+    }
+
+    /// Restarts a process for a policy such as `on_timeout`, or kills it instead once its
+    /// command is crash-looping
+    fn restart_unless_crash_looping(&mut self, id: ProcessId) -> ProcessActionResponse {
+        if self.restart_history.is_crash_looping(id.command()) {
+            log_err!(
+                "{} is crash-looping, killing it instead of restarting it",
+                id
+            );
+            return self.process_message(ProcessAction::Kill(id));
+        }
+        self.restart_process(id)
+    }
+
+    /// Adds a restart to the history of the command, warning when it starts crash-looping
+    fn record_restart(&mut self, command: &str) {
+        let Some(restarts) = self.restart_history.record(command) else {
+            return;
+        };
+        let message = format!(
+            "'{}' is crash-looping after {} restarts in {}, it won't be restarted automatically",
+            command,
+            restarts,
+            terminal::format_duration(self.restart_history.window())
+        );
+        log_err!("{}", theme::emphasis(&message));
+        process::notify("together", &message);
+    }
+
     fn start_new_process(
         &mut self,
         command: String,
@@ -242,34 +1322,425 @@ impl ProcessManager {
         stdio: ProcessStdio,
         id: u32,
     ) -> ProcessActionResponse {
-        match Process::spawn(&command, cwd.as_deref(), stdio) {
+        let stdio = match stdio {
+            ProcessStdio::Inherit if self.use_pty(&command) => ProcessStdio::Pty,
+            stdio => stdio,
+        };
+        let id = ProcessId::new(id, command);
+        if !self.killed && !self.recurring.contains_key(id.command()) {
+            if let Some(recurrence) = self.recurrence(id.command()) {
+                let next = recurrence.next(Instant::now());
+                if let Some(next) = next {
+                    self.recurring
+                        .insert(id.command().to_string(), (recurrence, next));
+                }
+            }
+        }
+        match self.wait_condition(id.command()) {
+            Some(condition) => self.wait_for_process(id, cwd, stdio, condition),
+            None => self.spawn_process(id, cwd, stdio, 0),
+        }
+    }
+
+    /// Starts the process, or schedules another attempt if it fails to start and `attempt`
+    /// is below `spawn_retries`
+    fn spawn_process(
+        &mut self,
+        id: ProcessId,
+        cwd: Option<String>,
+        stdio: ProcessStdio,
+        attempt: u32,
+    ) -> ProcessActionResponse {
+        let guest = self.command_guest(&id);
+        match self.spawn_child(id.command(), cwd.as_deref(), stdio, guest.as_ref()) {
             Ok(mut child) => {
-                let id = ProcessId::new(id, command);
-                if let ProcessStdio::Inherit = stdio {
-                    child.forward_stdio(&id);
+                if let Some(guest) = guest {
+                    self.guests.insert(id.clone(), guest);
+                }
+                if let Some(timeout) = self.command_timeout(id.command()) {
+                    self.deadlines.insert(id.clone(), Instant::now() + timeout);
+                }
+                let idle_after = self.commands.iter().find(|c| c.matches(id.command()));
+                if idle_after.and_then(|c| c.idle_after()).is_some() {
+                    self.activity.insert(id.clone(), Activity::new());
+                }
+                if let (ProcessStdio::Inherit | ProcessStdio::Pty, Some(output)) =
+                    (stdio, &self.output)
+                {
+                    child.forward_stdio(&id, output);
+                    if let Some(cwd) = &cwd {
+                        output.set_cwd(&id, cwd);
+                    }
+                }
+                let pid = child.pid();
+                if let (Some(groups), Some(pid)) = (&mut self.groups, pid) {
+                    groups.add(&id, pid);
                 }
                 self.processes.insert(id.clone(), child);
+                self.options.insert(
+                    id.clone(),
+                    CreateOptions {
+                        stdio: Some(stdio),
+                        cwd: cwd.clone(),
+                    },
+                );
+                let status = ProcessStatus {
+                    id: id.clone(),
+                    state: ProcessState::Running,
+                    started_at: Instant::now(),
+                    exited_at: None,
+                    cwd,
+                    cpu: None,
+                    memory: None,
+                    pid,
+                    restarts: self.restarts.get(id.command()).copied().unwrap_or_default(),
+                    crash_looping: false,
+                };
+                match self.statuses.iter_mut().find(|s| s.id == id) {
+                    Some(waiting) => *waiting = status,
+                    None => self.statuses.push(status),
+                }
                 log!("Started  {}", id);
+                self.log_event(&id, Priority::Notice, "started");
+                if let Some(tracer) = &mut self.tracer {
+                    tracer.spawned(&id);
+                }
+                if let Some(report) = &mut self.report {
+                    report.spawned(&id);
+                }
                 ProcessActionResponse::Created(id)
             }
-            Err(e) => {
-                ProcessActionResponse::Error(ProcessManagerError::SpawnChildFailed(e.to_string()))
+            // configuration errors such as unreadable secrets won't go away by retrying
+            Err(e) if attempt < self.spawn_retries && !matches!(e, TogetherError::Config(_)) => {
+                let backoff = SPAWN_RETRY_BACKOFF.saturating_mul(1 << attempt.min(16));
+                let backoff = backoff.min(MAX_SPAWN_RETRY_BACKOFF);
+                log_err!(
+                    "Failed to start '{}' ({}), retrying in {}ms ({}/{})",
+                    id.command(),
+                    e,
+                    backoff.as_millis(),
+                    attempt + 1,
+                    self.spawn_retries
+                );
+                let retry = SpawnRetry {
+                    attempt: attempt + 1,
+                    at: Instant::now() + backoff,
+                };
+                self.retry_spawn(id, cwd, stdio, retry)
+            }
+            Err(e) => ProcessActionResponse::Error(ManagerError::SpawnFailed(SpawnError {
+                command: id.command().to_string(),
+                source: Box::new(e),
+            })),
+        }
+    }
+
+    /// Waits to start a process that failed to start, without holding up other actions
+    fn retry_spawn(
+        &mut self,
+        id: ProcessId,
+        cwd: Option<String>,
+        stdio: ProcessStdio,
+        retry: SpawnRetry,
+    ) -> ProcessActionResponse {
+        if !self.statuses.iter().any(|s| s.id == id) {
+            self.statuses.push(ProcessStatus {
+                id: id.clone(),
+                state: ProcessState::Waiting,
+                started_at: Instant::now(),
+                exited_at: None,
+                cwd: cwd.clone(),
+                cpu: None,
+                memory: None,
+                pid: None,
+                restarts: self.restarts.get(id.command()).copied().unwrap_or_default(),
+                crash_looping: false,
+            });
+        }
+        self.waiting.insert(
+            id.clone(),
+            WaitingProcess {
+                cwd,
+                stdio,
+                cancelled: Arc::new(AtomicBool::new(false)),
+                retry: Some(retry),
+            },
+        );
+        ProcessActionResponse::Created(id)
+    }
+
+    /// Tries again to start the processes whose retry is due
+    fn retry_spawns(&mut self) {
+        let now = Instant::now();
+        let due: Vec<_> = self
+            .waiting
+            .iter()
+            .filter(|(_, w)| w.retry.as_ref().is_some_and(|r| r.at <= now))
+            .map(|(id, _)| id.clone())
+            .collect();
+        for id in due {
+            self.start_waiting(id);
+        }
+    }
+
+    /// Checks the condition on another thread, spawning the process once it is met
+    fn wait_for_process(
+        &mut self,
+        id: ProcessId,
+        cwd: Option<String>,
+        stdio: ProcessStdio,
+        condition: WaitFor,
+    ) -> ProcessActionResponse {
+        log!("Waiting for {} before starting {}", condition, id);
+        let cancelled = Arc::new(AtomicBool::new(false));
+        {
+            let (id, cwd, cancelled) = (id.clone(), cwd.clone(), cancelled.clone());
+            let ready = self.sender.clone();
+            std::thread::spawn(move || {
+                if wait::wait_until(&condition, cwd.as_deref(), &cancelled) {
+                    _ = ready.send(ManagerEvent::Ready(id));
+                }
+            });
+        }
+        self.statuses.push(ProcessStatus {
+            id: id.clone(),
+            state: ProcessState::Waiting,
+            started_at: Instant::now(),
+            exited_at: None,
+            cwd: cwd.clone(),
+            cpu: None,
+            memory: None,
+            pid: None,
+            restarts: self.restarts.get(id.command()).copied().unwrap_or_default(),
+            crash_looping: false,
+        });
+        self.waiting.insert(
+            id.clone(),
+            WaitingProcess {
+                cwd,
+                stdio,
+                cancelled,
+                retry: None,
+            },
+        );
+        ProcessActionResponse::Created(id)
+    }
+
+    fn start_waiting(&mut self, id: ProcessId) {
+        // the process may have been killed since its condition was met
+        let Some(waiting) = self.waiting.remove(&id) else {
+            return;
+        };
+        let cwd = waiting.cwd.clone();
+        let attempt = waiting.retry.as_ref().map_or(0, |r| r.attempt);
+        if let ProcessActionResponse::Error(e) =
+            self.spawn_process(id.clone(), cwd, waiting.stdio, attempt)
+        {
+            log_err!("{}", e);
+            self.statuses.retain(|s| s.id != id);
+            self.wait_handles.remove(&id);
+            self.all_exited = self.is_complete();
+        }
+    }
+
+    fn cancel_waiting(&mut self, id: &ProcessId) {
+        if self.waiting.remove(id).is_none() {
+            return;
+        }
+        log!("Cancelled {}", id);
+        self.statuses.retain(|s| &s.id != id);
+        self.wait_handles.remove(id);
+        self.all_exited = self.is_complete();
+    }
+
+    fn spawn_child(
+        &self,
+        command: &str,
+        cwd: Option<&str>,
+        stdio: ProcessStdio,
+        guest: Option<&Guest>,
+    ) -> TogetherResult<Process> {
+        let user = self.command_user(command);
+        let inherit = self.command_env_filter(command);
+        let mut env = self.command_env(command);
+        let line = self.command_line(command);
+        let remote = match guest {
+            Some(Guest::Container(container)) => {
+                Some(container.command(line, self.remote_cwd(command), &env))
+            }
+            Some(Guest::Wsl(wsl)) => {
+                env.extend(wsl::shared_env(&env));
+                Some(wsl.command(line, self.wsl_cwd(command).as_deref()))
             }
+            None => self.remote_command(command),
+        };
+        // the user is switched to in WSL instead
+        let user = user.as_deref().filter(|_| guest.is_none());
+        let run = remote.as_deref().unwrap_or(line);
+        debug!(
+            "spawning {:?}, cwd: {:?}, user: {:?}, stdio: {:?}, env: {:?}, inherit: {:?}",
+            run, cwd, user, stdio, env, inherit
+        );
+        // secrets are read after logging, so only their references are written to the log
+        let env = secrets::resolve_env(env)?;
+        Process::spawn(run, cwd, stdio, user, &env, &inherit)
+    }
+
+    /// The command line run for a command, which is only the command itself unless it's
+    /// known by a different name
+    fn command_line<'a>(&'a self, command: &'a str) -> &'a str {
+        let config = self.commands.iter().find(|c| c.matches(command));
+        config.map_or(command, |c| c.command_line())
+    }
+
+    fn command_cwd(&self, command: &str) -> Option<String> {
+        let command = self.commands.iter().find(|c| c.matches(command));
+        // remote commands change to their directory on the host or in the container instead
+        let cwd = command.filter(|c| c.is_local()).and_then(|c| c.cwd());
+        config::resolve_cwd(self.cwd.as_deref(), cwd)
+    }
+
+    fn remote_cwd(&self, command: &str) -> Option<&str> {
+        let command = self.commands.iter().find(|c| c.matches(command));
+        command.filter(|c| !c.is_local()).and_then(|c| c.cwd())
+    }
+
+    /// The ssh command that runs the command on its host, if it has one
+    fn remote_command(&self, command: &str) -> Option<String> {
+        let config = self.commands.iter().find(|c| c.matches(command))?;
+        let host = config.host()?;
+        Some(crate::remote::ssh_command(
+            host,
+            config.command_line(),
+            self.remote_cwd(command),
+        ))
+    }
+
+    /// The directory of a command run in WSL, resolved against the `--cwd` base directory
+    /// unless it's already a linux path
+    fn wsl_cwd(&self, command: &str) -> Option<String> {
+        match self.remote_cwd(command) {
+            Some(cwd) if cwd.starts_with(['/', '~']) => Some(cwd.to_string()),
+            cwd => config::resolve_cwd(self.cwd.as_deref(), cwd),
         }
     }
 
+    fn command_max_memory(&self, command: &str) -> Option<u64> {
+        let command = self.commands.iter().find(|c| c.matches(command));
+        command.and_then(|c| c.max_memory())
+    }
+
+    fn command_timeout(&self, command: &str) -> Option<Duration> {
+        let command = self.commands.iter().find(|c| c.matches(command));
+        command.and_then(|c| c.timeout())
+    }
+
+    fn command_guest(&self, id: &ProcessId) -> Option<Guest> {
+        let command = self.commands.iter().find(|c| c.matches(id.command()))?;
+        if let Some(container) = command.container() {
+            return Some(Guest::Container(RunningContainer::new(
+                container.clone(),
+                id,
+            )));
+        }
+        match command.wsl() {
+            Some(wsl) if cfg!(windows) => {
+                Some(Guest::Wsl(WslProcess::new(wsl, command.user(), id)))
+            }
+            _ => None,
+        }
+    }
+
+    fn wait_condition(&self, command: &str) -> Option<WaitFor> {
+        let command = self.commands.iter().find(|c| c.matches(command));
+        command.and_then(|c| c.wait_for()).cloned()
+    }
+
+    fn is_failure(&self, command: &str, status: i32) -> bool {
+        let command = self.commands.iter().find(|c| c.matches(command));
+        command.map_or(status != 0, |c| c.is_failure(status))
+    }
+
+    fn log_event(&self, id: &ProcessId, priority: Priority, event: &str) {
+        if let Some(sink) = &self.sink {
+            sink.send(id, priority, event);
+        }
+    }
+
+    fn is_oneshot(&self, command: &str) -> bool {
+        let command = self.commands.iter().find(|c| c.matches(command));
+        command.is_some_and(|c| c.is_oneshot())
+    }
+
+    fn recurrence(&self, command: &str) -> Option<Recurrence> {
+        let command = self.commands.iter().find(|c| c.matches(command));
+        command.and_then(Recurrence::of).and_then(Result::ok)
+    }
+
+    fn exit_hooks(&self, command: &str, status: i32) -> Vec<String> {
+        let command = self.commands.iter().find(|c| c.matches(command));
+        let hooks = command.into_iter().flat_map(|c| c.exit_hooks(status));
+        hooks.map(|hook| hook.to_string()).collect()
+    }
+
+    fn command_env_filter(&self, command: &str) -> process::EnvFilter {
+        let command = self.commands.iter().find(|c| c.matches(command));
+        let details = command.and_then(|c| c.details());
+        process::EnvFilter::new(
+            details.and_then(|d| d.inherit_env).unwrap_or(true),
+            details.and_then(|d| d.env_allowlist.as_deref()),
+            details.and_then(|d| d.env_blocklist.as_deref()),
+        )
+    }
+
+    /// Variables set for the command in the configuration file, followed by those set by
+    /// together such as `PORT`, with secrets still to be resolved
+    fn command_env(&self, command: &str) -> Vec<(String, String)> {
+        let config = self.commands.iter().find(|c| c.matches(command));
+        let env = config.and_then(|c| c.env()).into_iter().flatten();
+        let env = env.map(|(name, value)| (name.clone(), value.clone()));
+        env.chain(self.ports.env(command)).collect()
+    }
+
+    fn command_user(&self, command: &str) -> Option<String> {
+        let command = self.commands.iter().find(|c| c.matches(command));
+        command.and_then(|c| c.user()).map(|u| u.to_string())
+    }
+
+    /// Output of the command, as configured for it or by raw mode otherwise
+    fn command_stdio(&self, command: &str) -> ProcessStdio {
+        let command = self.commands.iter().find(|c| c.matches(command));
+        match command.and_then(|c| c.output()) {
+            Some(CommandOutput::All) => ProcessStdio::Inherit,
+            Some(CommandOutput::StderrOnly) => ProcessStdio::StderrOnly,
+            Some(CommandOutput::None) => ProcessStdio::Null,
+            Some(CommandOutput::Raw) => ProcessStdio::Raw,
+            None => command
+                .and_then(|c| c.raw())
+                .unwrap_or(self.raw_stdio)
+                .into(),
+        }
+    }
+
+    fn use_pty(&self, command: &str) -> bool {
+        let command = self.commands.iter().find(|c| c.matches(command));
+        command.and_then(|c| c.pty()).unwrap_or(self.pty)
+    }
+
     fn cleanup_dead_processes(&mut self) {
         let mut remove = vec![];
+        let mut hooks = vec![];
         let mut kill_all = false;
 
         for (id, child) in self.processes.iter_mut() {
             match child.try_wait() {
                 Ok(Some(status)) => {
-                    remove.push(id.clone());
-                    if status != 0 && self.exit_on_error {
-                        log_err!("{}: exited with non-zero status", id);
-                        kill_all = true;
-                    }
+                    debug!("{} exited with status {}", id, status);
+                    let status = match self.timed_out.remove(id) {
+                        true => TIMED_OUT_STATUS,
+                        false => status,
+                    };
+                    remove.push((id.clone(), status));
                 }
                 Ok(None) => {}
                 Err(e) => {
@@ -278,37 +1749,191 @@ impl ProcessManager {
             }
         }
 
-        for id in remove {
-            if let Some(handle) = self.wait_handles.remove(&id) {
-                handle.send(()).unwrap();
+        for (id, status) in &remove {
+            // processes killed to be restarted aren't failures
+            let restarting = matches!(self.state(id), Some(ProcessState::Restarting));
+            if self.exit_on_error
+                && !self.killed
+                && !restarting
+                && self.is_failure(id.command(), *status)
+            {
+                log_err!("{}: exited with non-zero status", id);
+                debug!("exit_on_error is set, killing all processes");
+                kill_all = true;
             }
+        }
+
+        for (id, status) in remove {
+            let awaited = match self.wait_handles.remove(&id) {
+                Some(handle) => {
+                    debug!("completing wait handle of {}", id);
+                    handle.send(status).unwrap();
+                    true
+                }
+                None => false,
+            };
             self.processes.remove(&id);
+            if let Some(groups) = &mut self.groups {
+                groups.remove(&id);
+            }
+            self.cpu_samples.remove(&id);
+            self.guests.remove(&id);
+            self.deadlines.remove(&id);
+            self.activity.remove(&id);
+            let options = self.options.remove(&id);
+            if !self.killed && !kill_all {
+                let cwd = options.as_ref().and_then(|o| o.cwd.clone());
+                for hook in self.exit_hooks(id.command(), status) {
+                    hooks.push((id.clone(), hook, cwd.clone()));
+                }
+            }
+            let crashed = self.is_failure(id.command(), status)
+                && !awaited
+                && !self.killed
+                && !kill_all
+                && !self.is_oneshot(id.command());
+            if let (true, Some(ProcessState::Running), Some(supervisor)) =
+                (crashed, self.state(&id), &mut self.supervisor)
+            {
+                let options = options.unwrap_or_default();
+                supervisor.on_crash(&id, status, options, &self.restart_history);
+            }
+            let stopped = self.killed
+                || kill_all
+                || matches!(
+                    self.state(&id),
+                    Some(ProcessState::Stopping | ProcessState::Restarting)
+                );
+            self.set_state(&id, ProcessState::Exited(status));
             log!("Finished {}", id);
+            let failed = self.is_failure(id.command(), status);
+            if let Some(tracer) = &mut self.tracer {
+                tracer.exited(&id, status, failed);
+            }
+            if let Some(report) = &mut self.report {
+                report.exited(&id, status, failed, stopped);
+            }
+            let priority = match failed {
+                true => Priority::Error,
+                false => Priority::Notice,
+            };
+            self.log_event(&id, priority, &format!("exited with status {}", status));
         }
-        if kill_all {
-            for (id, mut child) in self.processes.drain() {
-                match child.kill(None) {
-                    Ok(_) => {}
-                    Err(e) => {
-                        log_err!("Failed to kill {id} => {}", e);
-                    }
-                }
+        self.trim_statuses();
+        for (id, hook, cwd) in hooks {
+            let hook_id = self.index;
+            self.index += 1;
+
+            log!("Running exit hook of {}", id);
+            let response = self.start_new_process(hook, cwd, self.raw_stdio.into(), hook_id);
+            if let ProcessActionResponse::Error(e) = response {
+                log_err!("Failed to run exit hook of {}: {}", id, e);
             }
         }
+        // the remaining processes are stopped like on quitting, and cleaned up as they exit
+        if kill_all && !self.killed {
+            if let ProcessActionResponse::Error(e) = self.process_message(ProcessAction::KillAll) {
+                log_err!("Failed to kill all processes: {}", e);
+            }
+        }
+    }
+
+    fn supervise(&mut self) {
+        let Some(supervisor) = &mut self.supervisor else {
+            return;
+        };
+        supervisor.heartbeat(&self.statuses, &self.restart_history);
+        for (command, options) in supervisor.take_due_restarts() {
+            if self.restart_history.is_crash_looping(&command) {
+                log_err!("'{}' is crash-looping, not restarting it", command);
+                continue;
+            }
+            let id = self.index;
+            self.index += 1;
+
+            let raw = options.stdio.unwrap_or(self.raw_stdio.into());
+            let cwd = options.cwd.clone().or_else(|| self.cwd.clone());
+
+            if let Some(tracer) = &mut self.tracer {
+                tracer.restarted(&command);
+            }
+            if let Some(report) = &mut self.report {
+                report.restarted(&command);
+            }
+            *self.restarts.entry(command.clone()).or_default() += 1;
+            self.record_restart(&command);
+            if let ProcessActionResponse::Error(e) = self.start_new_process(command, cwd, raw, id) {
+                log_err!("Failed to restart process: {}", e);
+            }
+        }
+    }
+
+    fn sample_usage(&mut self) {
+        let now = Instant::now();
+        for status in self.statuses.iter_mut() {
+            let usage = self.processes.get(&status.id).and_then(|p| p.usage());
+            let Some(usage) = usage else {
+                status.cpu = None;
+                status.memory = None;
+                continue;
+            };
+            let (since, cpu_time) = self
+                .cpu_samples
+                .insert(status.id.clone(), (now, usage.cpu_time))
+                .unwrap_or((status.started_at, Duration::ZERO));
+            let elapsed = now.duration_since(since).as_secs_f32();
+            let used = usage.cpu_time.saturating_sub(cpu_time).as_secs_f32();
+            status.cpu = (elapsed > 0.0).then(|| used / elapsed * 100.0);
+            status.memory = Some(usage.memory);
+        }
+    }
+
+    fn state(&self, id: &ProcessId) -> Option<ProcessState> {
+        self.statuses.iter().find(|s| &s.id == id).map(|s| s.state)
+    }
+
+    fn set_state(&mut self, id: &ProcessId, state: ProcessState) {
+        let Some(status) = self.statuses.iter_mut().find(|s| &s.id == id) else {
+            return;
+        };
+        if let ProcessState::Exited(_) = state {
+            status.exited_at = Some(Instant::now());
+        }
+        status.state = state;
+    }
+
+    fn trim_statuses(&mut self) {
+        let exited = self
+            .statuses
+            .iter()
+            .filter(|s| matches!(s.state, ProcessState::Exited(_)))
+            .count();
+        let mut excess = exited.saturating_sub(MAX_EXITED_STATUSES);
+        let output = self.output.as_ref();
+        self.statuses.retain(|s| {
+            if excess > 0 && matches!(s.state, ProcessState::Exited(_)) {
+                excess -= 1;
+                if let Some(output) = output {
+                    output.remove(&s.id);
+                }
+                return false;
+            }
+            true
+        });
     }
 }
 
 pub struct ProcessManagerHandle {
     thread: Option<std::thread::JoinHandle<()>>,
-    sender: mpsc::Sender<Message>,
+    sender: mpsc::Sender<ManagerEvent>,
 }
 
 impl ProcessManagerHandle {
     pub fn send(&self, action: ProcessAction) -> TogetherResult<ProcessActionResponse> {
         let (sender, receiver) = mpsc::channel();
         self.sender
-            .send(Message(action, sender))
-            .map_err(|e| TogetherError::DynError(e.into()))?;
+            .send(ManagerEvent::Message(Message(action, sender)))
+            .map_err(|_| ManagerError::Stopped)?;
         receiver.recv().map_err(|e| e.into())
     }
     pub fn subscribe(&self) -> ProcessManagerHandle {
@@ -327,6 +1952,8 @@ impl ProcessManagerHandle {
         self.send(ProcessAction::Create(command.to_string()))
             .and_then(|r| match r {
                 ProcessActionResponse::Created(id) => Ok(id),
+                ProcessActionResponse::Error(ManagerError::SpawnFailed(e)) => Err(e.into()),
+                ProcessActionResponse::Error(e) => Err(e.into()),
                 _ => Err(TogetherInternalError::UnexpectedResponse.into()),
             })
     }
@@ -341,25 +1968,124 @@ impl ProcessManagerHandle {
         ))
         .and_then(|r| match r {
             ProcessActionResponse::Created(id) => Ok(id),
+            ProcessActionResponse::Error(ManagerError::SpawnFailed(e)) => Err(e.into()),
+            ProcessActionResponse::Error(e) => Err(e.into()),
             _ => Err(TogetherInternalError::UnexpectedResponse.into()),
         })
     }
     pub fn kill(&self, id: ProcessId) -> TogetherResult<Option<()>> {
         self.send(ProcessAction::Kill(id)).and_then(|r| match r {
             ProcessActionResponse::Killed => Ok(Some(())),
-            ProcessActionResponse::Error(ProcessManagerError::NoSuchProcess) => Ok(None),
+            ProcessActionResponse::Error(ManagerError::NoSuchProcess) => Ok(None),
             _ => Err(TogetherInternalError::UnexpectedResponse.into()),
         })
     }
-    pub fn restart(&self, id: ProcessId, command: &str) -> TogetherResult<Option<ProcessId>> {
-        match self.kill(id)? {
-            Some(()) => Ok(Some(self.spawn(command)?)),
-            None => Ok(None),
+    pub fn kill_by_recipe(
+        &self,
+        start_options: &ConfigFileStartOptions,
+        recipe: &str,
+    ) -> TogetherResult<Vec<ProcessId>> {
+        let recipe_commands = config::collect_commands_by_recipes(start_options, &[recipe]);
+        let mut killed = vec![];
+        for id in self.list()? {
+            if !recipe_commands.iter().any(|c| c == id.command()) {
+                continue;
+            }
+            if self.kill(id.clone())?.is_some() {
+                killed.push(id);
+            }
         }
+        Ok(killed)
+    }
+    pub fn restart(&self, id: ProcessId) -> TogetherResult<Option<ProcessId>> {
+        self.send(ProcessAction::Restart(id)).and_then(|r| match r {
+            ProcessActionResponse::Created(id) => Ok(Some(id)),
+            ProcessActionResponse::Error(ManagerError::NoSuchProcess) => Ok(None),
+            _ => Err(TogetherInternalError::UnexpectedResponse.into()),
+        })
+    }
+    pub fn restart_all(&self) -> TogetherResult<Vec<ProcessId>> {
+        self.send(ProcessAction::RestartAll).and_then(|r| match r {
+            ProcessActionResponse::RestartedAll(list) => Ok(list),
+            _ => Err(TogetherInternalError::UnexpectedResponse.into()),
+        })
+    }
+    pub fn focus(&self, ids: Vec<ProcessId>) -> TogetherResult<()> {
+        self.send(ProcessAction::Focus(ids)).and_then(|r| match r {
+            ProcessActionResponse::Focused => Ok(()),
+            _ => Err(TogetherInternalError::UnexpectedResponse.into()),
+        })
+    }
+    pub fn unfocus(&self) -> TogetherResult<Vec<(ProcessId, usize)>> {
+        self.send(ProcessAction::Unfocus).and_then(|r| match r {
+            ProcessActionResponse::Unfocused(hidden) => Ok(hidden),
+            _ => Err(TogetherInternalError::UnexpectedResponse.into()),
+        })
+    }
+    pub fn filter(&self, filter: Option<OutputFilter>) -> TogetherResult<()> {
+        self.send(ProcessAction::Filter(filter))
+            .and_then(|r| match r {
+                ProcessActionResponse::Filtered => Ok(()),
+                _ => Err(TogetherInternalError::UnexpectedResponse.into()),
+            })
+    }
+    pub fn attach(&self, id: ProcessId) -> TogetherResult<Option<()>> {
+        self.send(ProcessAction::Attach(id)).and_then(|r| match r {
+            ProcessActionResponse::Attached => Ok(Some(())),
+            ProcessActionResponse::Error(ManagerError::NoSuchProcess) => Ok(None),
+            _ => Err(TogetherInternalError::UnexpectedResponse.into()),
+        })
+    }
+    pub fn detach(&self) -> TogetherResult<Vec<(ProcessId, usize)>> {
+        self.send(ProcessAction::Detach).and_then(|r| match r {
+            ProcessActionResponse::Detached(hidden) => Ok(hidden),
+            _ => Err(TogetherInternalError::UnexpectedResponse.into()),
+        })
+    }
+    pub fn input(&self, id: ProcessId, input: Vec<u8>) -> TogetherResult<Option<()>> {
+        self.send(ProcessAction::Input(id, input))
+            .and_then(|r| match r {
+                ProcessActionResponse::InputSent => Ok(Some(())),
+                ProcessActionResponse::Error(ManagerError::NoSuchProcess) => Ok(None),
+                _ => Err(TogetherInternalError::UnexpectedResponse.into()),
+            })
+    }
+    pub fn report(&self, format: ReportFormat) -> TogetherResult<String> {
+        self.send(ProcessAction::Report(format))
+            .and_then(|r| match r {
+                ProcessActionResponse::Report(report) => Ok(report),
+                _ => Err(TogetherInternalError::UnexpectedResponse.into()),
+            })
+    }
+    pub fn tail(&self, id: ProcessId, lines: usize) -> TogetherResult<Option<Vec<String>>> {
+        self.send(ProcessAction::Tail(id, lines))
+            .and_then(|r| match r {
+                ProcessActionResponse::Tail(lines) => Ok(Some(lines)),
+                ProcessActionResponse::Error(ManagerError::NoSuchProcess) => Ok(None),
+                _ => Err(TogetherInternalError::UnexpectedResponse.into()),
+            })
+    }
+    /// Start commands one after another, each after its delay from the previous one starting
+    pub fn schedule(&self, commands: Vec<(String, Duration)>) -> TogetherResult<()> {
+        self.send(ProcessAction::Schedule(commands))
+            .and_then(|r| match r {
+                ProcessActionResponse::Scheduled => Ok(()),
+                _ => Err(TogetherInternalError::UnexpectedResponse.into()),
+            })
+    }
+    pub fn status(&self) -> TogetherResult<Vec<ProcessStatus>> {
+        self.send(ProcessAction::Status).and_then(|r| match r {
+            ProcessActionResponse::Status(statuses) => Ok(statuses),
+            _ => Err(TogetherInternalError::UnexpectedResponse.into()),
+        })
+    }
+    pub fn wait(&self, id: ProcessId) -> TogetherResult<i32> {
+        self.exit_receiver(id)?.recv().map_err(|e| e.into())
     }
-    pub fn wait(&self, id: ProcessId) -> TogetherResult<()> {
+    /// A receiver for the exit status of a process, to wait on several processes at once
+    pub fn exit_receiver(&self, id: ProcessId) -> TogetherResult<mpsc::Receiver<i32>> {
         self.send(ProcessAction::Wait(id)).and_then(|r| match r {
-            ProcessActionResponse::Waited(done) => done.recv().map_err(|e| e.into()),
+            ProcessActionResponse::Waited(done) => Ok(done),
             _ => Err(TogetherInternalError::UnexpectedResponse.into()),
         })
     }
@@ -372,7 +2098,14 @@ impl Drop for ProcessManagerHandle {
         };
         let (sender, receiver) = mpsc::channel();
 
-        if let Err(_) = self.sender.send(Message(ProcessAction::KillAll, sender)) {
+        if self
+            .sender
+            .send(ManagerEvent::Message(Message(
+                ProcessAction::KillAll,
+                sender,
+            )))
+            .is_err()
+        {
             // the process manager has already exited, nothing to do
             return;
         };
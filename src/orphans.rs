@@ -0,0 +1,348 @@
+use std::{
+    path::{Path, PathBuf},
+    time::{Duration, Instant, SystemTime},
+};
+
+use crate::{
+    errors::{ConfigError, TogetherResult},
+    log, log_err,
+    process::{self, ProcessId},
+    terminal::{CleanupCommand, Terminal},
+};
+
+/// How long orphaned process groups have to exit after SIGTERM before they are sent SIGKILL
+const CLEANUP_KILL_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The process groups a together instance has started, so they can be found and killed by
+/// `together cleanup` if the instance dies without stopping them
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+struct SessionGroups {
+    pid: u32,
+    cwd: String,
+    started_at: String,
+    groups: Vec<Group>,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+struct Group {
+    /// Id of the process in the together session
+    id: u32,
+    pgid: u32,
+    command: String,
+    /// When the group leader started, to tell it apart from a later process given the same id
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    started: Option<String>,
+}
+
+/// A process as it is running now, rather than as recorded in a session file
+struct LiveProcess {
+    started: String,
+    command: String,
+}
+
+/// The session file of this instance, kept up to date as processes start and exit, and removed
+/// once they have all been stopped
+pub struct ProcessGroups {
+    path: PathBuf,
+    session: SessionGroups,
+}
+
+impl ProcessGroups {
+    /// Creates the session file, or nothing where processes are stopped with their job object
+    /// instead of a process group
+    pub fn create() -> Option<Self> {
+        if cfg!(windows) {
+            return None;
+        }
+        let dir = sessions_dir()?;
+        if let Err(e) = create_private_dir(&dir) {
+            log_err!("Failed to create {}: {}", dir.display(), e);
+            return None;
+        }
+        let pid = std::process::id();
+        let groups = Self {
+            path: dir.join(format!("{}.json", pid)),
+            session: SessionGroups {
+                pid,
+                cwd: std::env::current_dir()
+                    .map(|dir| dir.display().to_string())
+                    .unwrap_or_default(),
+                started_at: chrono::Local::now()
+                    .to_rfc3339_opts(chrono::SecondsFormat::Secs, false),
+                groups: vec![],
+            },
+        };
+        groups.save();
+        Some(groups)
+    }
+
+    pub fn add(&mut self, id: &ProcessId, pgid: u32) {
+        self.session.groups.push(Group {
+            id: id.index(),
+            pgid,
+            command: id.command().to_string(),
+            started: live_process(pgid).map(|p| p.started),
+        });
+        self.save();
+    }
+
+    pub fn remove(&mut self, id: &ProcessId) {
+        self.session.groups.retain(|g| g.id != id.index());
+        self.save();
+    }
+
+    /// Removes the session file, unless some processes are still running
+    pub fn close(&self) {
+        if self.session.groups.is_empty() {
+            _ = std::fs::remove_file(&self.path);
+        }
+    }
+
+    fn save(&self) {
+        let result = serde_json::to_string(&self.session)
+            .map_err(std::io::Error::from)
+            .and_then(|json| std::fs::write(&self.path, json));
+        if let Err(e) = result {
+            crate::debug!("failed to save {}: {}", self.path.display(), e);
+        }
+    }
+}
+
+/// Finds process groups left behind by together instances that died without stopping them,
+/// and kills them after asking, for `together cleanup`
+pub fn cleanup(command: &CleanupCommand) -> TogetherResult<()> {
+    if cfg!(windows) {
+        return Err(ConfigError::new("together cleanup is not supported on windows").into());
+    }
+    let orphaned = orphaned_sessions();
+    let groups: Vec<_> = orphaned
+        .iter()
+        .flat_map(|(_, session)| &session.groups)
+        .collect();
+    if groups.is_empty() {
+        for (path, _) in &orphaned {
+            _ = std::fs::remove_file(path);
+        }
+        log!("No orphaned processes found");
+        return Ok(());
+    }
+
+    for (_, session) in orphaned.iter().filter(|(_, s)| !s.groups.is_empty()) {
+        log!(
+            "together (pid {}) started at {} in {} left behind:",
+            session.pid,
+            session.started_at,
+            session.cwd
+        );
+        for group in &session.groups {
+            // the leader may have exited while the rest of its group still runs
+            let command = match live_process(group.pgid) {
+                Some(process) => process.command,
+                None => format!(
+                    "{} (exited, other processes of its group remain)",
+                    group.command
+                ),
+            };
+            log!("  - process group {}: {}", group.pgid, command);
+        }
+    }
+    if command.dry_run {
+        return Ok(());
+    }
+    let prompt = format!("Kill {} orphaned process group(s)?", groups.len());
+    if !command.yes && !Terminal::confirm(&prompt)? {
+        return Ok(());
+    }
+
+    for group in &groups {
+        signal_group(group.pgid, Signal::Terminate);
+    }
+    let deadline = Instant::now() + CLEANUP_KILL_TIMEOUT;
+    while groups.iter().any(|g| group_alive(g.pgid)) && Instant::now() < deadline {
+        std::thread::sleep(Duration::from_millis(100));
+    }
+    for group in groups.iter().filter(|g| group_alive(g.pgid)) {
+        log_err!(
+            "Process group {} is still running, force killing it",
+            group.pgid
+        );
+        signal_group(group.pgid, Signal::Kill);
+    }
+    for (path, _) in &orphaned {
+        _ = std::fs::remove_file(path);
+    }
+    log!("Killed {} orphaned process group(s)", groups.len());
+    Ok(())
+}
+
+/// Sessions of instances that are no longer running, with only the groups still alive
+fn orphaned_sessions() -> Vec<(PathBuf, SessionGroups)> {
+    let Some(dir) = sessions_dir() else {
+        return vec![];
+    };
+    if let Err(e) = create_private_dir(&dir) {
+        log_err!("Not reading sessions from {}: {}", dir.display(), e);
+        return vec![];
+    }
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return vec![];
+    };
+    let mut orphaned: Vec<_> = entries
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().and_then(|e| e.to_str()) == Some("json"))
+        .filter_map(|path| {
+            let session = read_session(&path)?;
+            (!process::is_alive(session.pid)).then_some((path, session))
+        })
+        .collect();
+    for (_, session) in &mut orphaned {
+        session.groups.retain(is_same_group);
+    }
+    orphaned.sort_by_key(|(path, _)| modified(path));
+    orphaned
+}
+
+/// Whether the group is still the one the session started. Ids of exited processes are reused,
+/// so a process with the id of the leader has to have started when the leader did. Without a
+/// leader the id can't have been reused, as it's taken until the rest of the group exits.
+fn is_same_group(group: &Group) -> bool {
+    match live_process(group.pgid) {
+        Some(process) => group.started.as_deref() == Some(process.started.as_str()),
+        None => group_alive(group.pgid),
+    }
+}
+
+fn read_session(path: &Path) -> Option<SessionGroups> {
+    let contents = std::fs::read_to_string(path).ok()?;
+    serde_json::from_str(&contents).ok()
+}
+
+fn modified(path: &Path) -> SystemTime {
+    let modified = std::fs::metadata(path).and_then(|m| m.modified());
+    modified.unwrap_or(SystemTime::UNIX_EPOCH)
+}
+
+/// `together/sessions` in the user's runtime directory, or a directory of the user in the temp
+/// directory where there is none, so sessions from before a reboot don't outlive it
+fn sessions_dir() -> Option<PathBuf> {
+    let dir = match dirs::runtime_dir() {
+        Some(dir) => dir.join("together"),
+        None => std::env::temp_dir().join(format!("together-{}", user_id())),
+    };
+    Some(dir.join("sessions"))
+}
+
+/// Creates the directory accessible only by the current user, failing if it already exists
+/// owned by someone else, who could otherwise list process groups for it to kill
+#[cfg(unix)]
+fn create_private_dir(dir: &Path) -> std::io::Result<()> {
+    use std::os::unix::fs::{DirBuilderExt, MetadataExt};
+
+    std::fs::DirBuilder::new()
+        .recursive(true)
+        .mode(0o700)
+        .create(dir)?;
+    let parent = dir.parent().unwrap_or(dir);
+    for dir in [dir, parent] {
+        if std::fs::metadata(dir)?.uid() != user_id() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::PermissionDenied,
+                format!("{} is owned by another user", dir.display()),
+            ));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(windows)]
+fn create_private_dir(dir: &Path) -> std::io::Result<()> {
+    std::fs::create_dir_all(dir)
+}
+
+#[cfg(unix)]
+fn user_id() -> u32 {
+    unsafe { libc::geteuid() }
+}
+
+#[cfg(windows)]
+fn user_id() -> u32 {
+    0
+}
+
+enum Signal {
+    Terminate,
+    Kill,
+}
+
+#[cfg(unix)]
+fn signal_group(pgid: u32, signal: Signal) {
+    let signal = match signal {
+        Signal::Terminate => libc::SIGTERM,
+        Signal::Kill => libc::SIGKILL,
+    };
+    if unsafe { libc::kill(-(pgid as i32), signal) } != 0 {
+        crate::debug!(
+            "failed to signal process group {}: {}",
+            pgid,
+            std::io::Error::last_os_error()
+        );
+    }
+}
+
+#[cfg(unix)]
+fn group_alive(pgid: u32) -> bool {
+    let result = unsafe { libc::kill(-(pgid as i32), 0) };
+    // signal 0 fails with EPERM for groups of other users, which still exist
+    result == 0 || std::io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+/// The start time and command line of a running process, from `/proc`
+#[cfg(target_os = "linux")]
+fn live_process(pid: u32) -> Option<LiveProcess> {
+    let stat = std::fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+    // the command name in parentheses can contain spaces, so fields are counted after it
+    let (_, fields) = stat.rsplit_once(')')?;
+    let started = fields.split_whitespace().nth(19)?.to_string();
+    let cmdline = std::fs::read(format!("/proc/{}/cmdline", pid)).ok()?;
+    let args: Vec<_> = cmdline
+        .split(|b| *b == 0)
+        .filter(|arg| !arg.is_empty())
+        .map(String::from_utf8_lossy)
+        .collect();
+    Some(LiveProcess {
+        started,
+        command: args.join(" "),
+    })
+}
+
+/// The start time and command line of a running process, as listed by `ps`
+#[cfg(all(unix, not(target_os = "linux")))]
+fn live_process(pid: u32) -> Option<LiveProcess> {
+    let ps = |field: &str| {
+        let output = std::process::Command::new("ps")
+            .args(["-o", field, "-p", &pid.to_string()])
+            .stderr(std::process::Stdio::null())
+            .output()
+            .ok()?;
+        let value = String::from_utf8_lossy(&output.stdout).trim().to_string();
+        (output.status.success() && !value.is_empty()).then_some(value)
+    };
+    Some(LiveProcess {
+        started: ps("lstart=")?,
+        command: ps("command=")?,
+    })
+}
+
+#[cfg(windows)]
+fn live_process(_pid: u32) -> Option<LiveProcess> {
+    None
+}
+
+#[cfg(windows)]
+fn signal_group(_pgid: u32, _signal: Signal) {}
+
+#[cfg(windows)]
+fn group_alive(_pgid: u32) -> bool {
+    false
+}
@@ -0,0 +1,557 @@
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    path::PathBuf,
+    sync::{mpsc, Arc, Mutex, OnceLock},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    config::commands::{CommandConfig, OutputAction},
+    links::Linker,
+    log, log_err,
+    log_sink::{LogForwarder, LogSink, Priority},
+    process::ProcessId,
+    t_println,
+    terminal::stdout::strip_ansi,
+    theme, timestamp,
+};
+
+/// How long the sliced output scheduler buffers output before flushing
+const SLICE_DURATION: Duration = Duration::from_millis(200);
+
+/// How long a process's output can pause before its block is printed in the blocks mode
+const BLOCK_WINDOW: Duration = Duration::from_millis(50);
+
+/// Longest a block is held back while output keeps arriving, so busy processes still show up
+const MAX_BLOCK_DURATION: Duration = Duration::from_millis(500);
+
+/// Number of recent output lines kept per process
+const BUFFERED_LINES: usize = 1000;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum OutputMode {
+    /// Print each line as soon as it is read
+    #[default]
+    Interleaved,
+    /// Batch each process's output into time slices, printed round-robin with separators
+    Sliced,
+    /// Print each process's output in blocks, ended by a blank line or a short pause, so
+    /// multi-line output like stack traces isn't interleaved with other processes
+    Blocks,
+}
+
+impl std::str::FromStr for OutputMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "interleaved" => Ok(Self::Interleaved),
+            "sliced" => Ok(Self::Sliced),
+            "blocks" => Ok(Self::Blocks),
+            _ => Err(format!("unknown output mode '{}'", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum OutputFilter {
+    /// Only print lines matching the pattern
+    Grep(regex::Regex),
+    /// Print all lines, highlighting matches of the pattern
+    Highlight(regex::Regex),
+}
+
+impl OutputFilter {
+    fn apply<'a>(&self, line: &'a str) -> Option<std::borrow::Cow<'a, str>> {
+        match self {
+            OutputFilter::Grep(regex) => regex.is_match(line).then_some(line.into()),
+            OutputFilter::Highlight(_) if !crate::terminal::stdout::color() => Some(line.into()),
+            OutputFilter::Highlight(regex) => {
+                Some(regex.replace_all(line, "\x1b[1;33m${0}\x1b[0m"))
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for OutputFilter {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            OutputFilter::Grep(regex) => write!(f, "grep '{}'", regex),
+            OutputFilter::Highlight(regex) => write!(f, "highlight '{}'", regex),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputStream {
+    Stdout,
+    Stderr,
+}
+
+struct OutputLine {
+    id: ProcessId,
+    stream: OutputStream,
+    line: String,
+}
+
+#[derive(Default)]
+struct OutputState {
+    attached: Option<ProcessId>,
+    focus: Option<HashSet<ProcessId>>,
+    filter: Option<OutputFilter>,
+    buffers: HashMap<ProcessId, ProcessOutput>,
+    /// Commands that haven't printed a local URL to open yet
+    open_urls: HashSet<String>,
+    /// Directories processes run in, that relative paths in their output are linked from
+    cwds: HashMap<ProcessId, PathBuf>,
+}
+
+#[derive(Default)]
+struct ProcessOutput {
+    lines: VecDeque<String>,
+    hidden: usize,
+    /// When the process last printed a line
+    last_line_at: Option<Instant>,
+}
+
+impl ProcessOutput {
+    fn push(&mut self, line: &str) {
+        if self.lines.len() == BUFFERED_LINES {
+            self.lines.pop_front();
+        }
+        self.lines.push_back(line.to_string());
+        self.last_line_at = Some(Instant::now());
+    }
+}
+
+#[derive(Clone)]
+pub struct OutputHandle {
+    sender: Option<mpsc::Sender<OutputLine>>,
+    timestamps: bool,
+    state: Arc<Mutex<OutputState>>,
+    sink: Option<Arc<LogSink>>,
+    forwarder: Option<LogForwarder>,
+    linker: Option<Linker>,
+    triggers: Option<Arc<Triggers>>,
+}
+
+/// Called with a process and the action of an `on_output` rule its output matched
+pub type TriggerFn = dyn Fn(&ProcessId, &OutputAction) + Send + Sync;
+
+/// The `on_output` rules of each command
+struct Triggers {
+    rules: HashMap<String, Vec<(regex::Regex, OutputAction)>>,
+    on_match: Box<TriggerFn>,
+}
+
+impl OutputHandle {
+    pub fn new(mode: OutputMode, timestamps: bool) -> Self {
+        let sender = match mode {
+            OutputMode::Interleaved => None,
+            OutputMode::Sliced => {
+                let (sender, receiver) = mpsc::channel();
+                std::thread::spawn(move || sliced_output_loop(receiver));
+                Some(sender)
+            }
+            OutputMode::Blocks => {
+                let (sender, receiver) = mpsc::channel();
+                std::thread::spawn(move || block_output_loop(receiver));
+                Some(sender)
+            }
+        };
+        Self {
+            sender,
+            timestamps,
+            state: Default::default(),
+            sink: None,
+            forwarder: None,
+            linker: None,
+            triggers: None,
+        }
+    }
+
+    /// Also forward every output line to a system logger
+    pub fn with_log_sink(mut self, sink: Option<Arc<LogSink>>) -> Self {
+        self.sink = sink;
+        self
+    }
+
+    /// Underline URLs and file references in output, as hyperlinks if the terminal supports them
+    pub fn with_linker(mut self, linker: Option<Linker>) -> Self {
+        self.linker = linker;
+        self
+    }
+
+    /// Check the output of commands against their `on_output` rules, calling `on_match` with
+    /// the action of each rule a line matches
+    pub fn with_triggers(mut self, commands: &[CommandConfig], on_match: Box<TriggerFn>) -> Self {
+        let rules: HashMap<_, _> = commands
+            .iter()
+            .filter(|c| !c.output_rules().is_empty())
+            .map(|c| {
+                let rules = c.output_rules().iter().filter_map(|rule| {
+                    let pattern = regex::Regex::new(&rule.pattern).ok()?;
+                    Some((pattern, rule.action.clone()))
+                });
+                (c.as_str().to_string(), rules.collect())
+            })
+            .collect();
+        if !rules.is_empty() {
+            self.triggers = Some(Arc::new(Triggers { rules, on_match }));
+        }
+        self
+    }
+
+    /// Also stream every output line to a remote collector
+    pub fn with_log_forwarder(mut self, forwarder: Option<LogForwarder>) -> Self {
+        self.forwarder = forwarder;
+        self
+    }
+
+    /// Only print output from the given processes, other output is still buffered
+    pub fn focus(&self, ids: Vec<ProcessId>) {
+        let mut state = self.state.lock().unwrap();
+        state.focus = Some(ids.into_iter().collect());
+    }
+
+    /// Print output from all processes again, returning how many lines were hidden per process
+    pub fn unfocus(&self) -> Vec<(ProcessId, usize)> {
+        let mut state = self.state.lock().unwrap();
+        state.focus = None;
+        state
+            .buffers
+            .iter_mut()
+            .filter(|(_, output)| output.hidden > 0)
+            .map(|(id, output)| (id.clone(), std::mem::take(&mut output.hidden)))
+            .collect()
+    }
+
+    /// Print only the unprefixed output of the given process, other output is still buffered
+    pub fn attach(&self, id: ProcessId) {
+        self.state.lock().unwrap().attached = Some(id);
+    }
+
+    /// Return to multiplexed output, returning how many lines were hidden per process
+    pub fn detach(&self) -> Vec<(ProcessId, usize)> {
+        let mut state = self.state.lock().unwrap();
+        state.attached = None;
+        let focus = state.focus.clone();
+        state
+            .buffers
+            .iter_mut()
+            // lines from unfocused processes stay hidden until the focus is cleared
+            .filter(|(id, _)| match &focus {
+                Some(focus) => focus.contains(id),
+                None => true,
+            })
+            .filter(|(_, output)| output.hidden > 0)
+            .map(|(id, output)| (id.clone(), std::mem::take(&mut output.hidden)))
+            .collect()
+    }
+
+    /// Open the first local URL each of the commands prints in the browser
+    pub fn open_urls(&self, commands: impl IntoIterator<Item = String>) {
+        self.state.lock().unwrap().open_urls = commands.into_iter().collect();
+    }
+
+    pub fn filter(&self, filter: Option<OutputFilter>) {
+        self.state.lock().unwrap().filter = filter;
+    }
+
+    /// The last `lines` buffered output lines of a process, including hidden and muted output
+    pub fn tail(&self, id: &ProcessId, lines: usize) -> Option<Vec<String>> {
+        let state = self.state.lock().unwrap();
+        let output = state.buffers.get(id)?;
+        let skip = output.lines.len().saturating_sub(lines);
+        Some(output.lines.iter().skip(skip).cloned().collect())
+    }
+
+    /// When a process last printed a line, including hidden and muted output
+    pub fn last_output(&self, id: &ProcessId) -> Option<Instant> {
+        let state = self.state.lock().unwrap();
+        state.buffers.get(id)?.last_line_at
+    }
+
+    /// Buffer a line without printing it, so it is still available to `tail`
+    pub fn write_muted(&self, id: &ProcessId, line: &str) {
+        self.forward(id, OutputStream::Stdout, line);
+        self.trigger(id, line);
+        let mut state = self.state.lock().unwrap();
+        let output = state.buffers.entry(id.clone()).or_default();
+        output.push(line);
+        output.hidden += 1;
+    }
+
+    fn trigger(&self, id: &ProcessId, line: &str) {
+        let Some(triggers) = &self.triggers else {
+            return;
+        };
+        let Some(rules) = triggers.rules.get(id.command()) else {
+            return;
+        };
+        let line = strip_ansi(line);
+        for (pattern, action) in rules {
+            if pattern.is_match(&line) {
+                (triggers.on_match)(id, action);
+            }
+        }
+    }
+
+    fn forward(&self, id: &ProcessId, stream: OutputStream, line: &str) {
+        if let Some(sink) = &self.sink {
+            let priority = match stream {
+                OutputStream::Stdout => Priority::Info,
+                OutputStream::Stderr => Priority::Error,
+            };
+            sink.send(id, priority, line);
+        }
+        if let Some(forwarder) = &self.forwarder {
+            let stream = match stream {
+                OutputStream::Stdout => "stdout",
+                OutputStream::Stderr => "stderr",
+            };
+            forwarder.send(id, stream, line);
+        }
+    }
+
+    /// Drop the buffered output of a process that is no longer tracked
+    pub fn remove(&self, id: &ProcessId) {
+        let mut state = self.state.lock().unwrap();
+        state.buffers.remove(id);
+        state.cwds.remove(id);
+    }
+
+    /// The directory a process runs in, when it isn't the current directory
+    pub fn set_cwd(&self, id: &ProcessId, cwd: &str) {
+        self.state
+            .lock()
+            .unwrap()
+            .cwds
+            .insert(id.clone(), cwd.into());
+    }
+
+    pub fn write(&self, id: &ProcessId, stream: OutputStream, line: &str) {
+        self.forward(id, stream, line);
+        self.trigger(id, line);
+        let line = {
+            let mut state = self.state.lock().unwrap();
+            if state.open_urls.contains(id.command()) {
+                if let Some(url) = local_url(line) {
+                    state.open_urls.remove(id.command());
+                    log!("Opening {} from {}", url, id);
+                    if let Err(e) = crate::process::open_url(&url) {
+                        log_err!("Failed to open {}: {}", url, e);
+                    }
+                }
+            }
+            let hidden = state.focus.as_ref().is_some_and(|f| !f.contains(id));
+            let attached = state.attached.as_ref().map(|attached| attached == id);
+            let output = state.buffers.entry(id.clone()).or_default();
+            output.push(line);
+            if let Some(attached) = attached {
+                if attached {
+                    // the terminal is in raw mode while attached
+                    print!("{}", line.trim_end_matches(['\r', '\n']));
+                    print!("\r\n");
+                } else {
+                    output.hidden += 1;
+                }
+                return;
+            }
+            if hidden {
+                output.hidden += 1;
+                return;
+            }
+            let line = match &state.filter {
+                Some(filter) => match filter.apply(line) {
+                    Some(line) => line.into_owned(),
+                    None => return,
+                },
+                None => line.to_string(),
+            };
+            match &self.linker {
+                Some(linker) => {
+                    let cwd = state.cwds.get(id).map(PathBuf::as_path);
+                    linker.apply(&line, cwd).into_owned()
+                }
+                None => line,
+            }
+        };
+        let line = match self.timestamps {
+            true => {
+                let now = timestamp::format(std::time::SystemTime::now());
+                match crate::terminal::stdout::color() {
+                    true => format!("\x1b[90m{}\x1b[0m {}", now, line),
+                    false => format!("{} {}", now, line),
+                }
+            }
+            false => line,
+        };
+
+        let Some(sender) = &self.sender else {
+            print_line(id, stream, &line);
+            return;
+        };
+        let line = OutputLine {
+            id: id.clone(),
+            stream,
+            line,
+        };
+        if let Err(mpsc::SendError(line)) = sender.send(line) {
+            // the scheduler has stopped, fall back to printing directly
+            print_line(&line.id, line.stream, &line.line);
+        }
+    }
+}
+
+/// The first URL of a local server in a line, e.g. `http://localhost:5173/`
+fn local_url(line: &str) -> Option<String> {
+    static LOCAL_URL: OnceLock<regex::Regex> = OnceLock::new();
+    let regex = LOCAL_URL.get_or_init(|| {
+        let host = r"(localhost|127\.0\.0\.1|0\.0\.0\.0|\[::1?\])";
+        regex::Regex::new(&format!(r#"https?://{}(:\d+)?[^\s'"<>]*"#, host)).unwrap()
+    });
+    let url = regex.find(&strip_ansi(line))?.as_str().to_string();
+    // servers listening on all interfaces are opened through localhost
+    Some(
+        url.replacen("0.0.0.0", "localhost", 1)
+            .replacen("[::]", "localhost", 1),
+    )
+}
+
+fn print_line(id: &ProcessId, stream: OutputStream, line: &str) {
+    match stream {
+        OutputStream::Stdout => print!("{} {}", theme::prefix(id), line),
+        OutputStream::Stderr => eprint!("{} {}", theme::prefix(id), line),
+    }
+}
+
+fn sliced_output_loop(receiver: mpsc::Receiver<OutputLine>) {
+    let mut batches: VecDeque<(ProcessId, Vec<OutputLine>)> = VecDeque::new();
+    let mut last_printed: Option<ProcessId> = None;
+
+    loop {
+        let deadline = Instant::now() + SLICE_DURATION;
+        loop {
+            let timeout = deadline.saturating_duration_since(Instant::now());
+            match receiver.recv_timeout(timeout) {
+                Ok(line) => match batches.iter_mut().find(|(id, _)| id == &line.id) {
+                    Some((_, lines)) => lines.push(line),
+                    None => batches.push_back((line.id.clone(), vec![line])),
+                },
+                Err(mpsc::RecvTimeoutError::Timeout) => break,
+                Err(mpsc::RecvTimeoutError::Disconnected) => {
+                    flush_batches(&mut batches, &mut last_printed);
+                    return;
+                }
+            }
+        }
+        flush_batches(&mut batches, &mut last_printed);
+    }
+}
+
+fn flush_batches(
+    batches: &mut VecDeque<(ProcessId, Vec<OutputLine>)>,
+    last_printed: &mut Option<ProcessId>,
+) {
+    // start after the last printed process, so a chatty process can't always go first
+    if let Some(position) = last_printed
+        .as_ref()
+        .and_then(|last| batches.iter().position(|(id, _)| id == last))
+    {
+        batches.rotate_left(position + 1);
+    }
+
+    for (id, lines) in batches.drain(..) {
+        if last_printed.as_ref() != Some(&id) {
+            t_println!("\x1b[90m--- {} ---\x1b[0m", id);
+        }
+        for line in lines {
+            print_line(&line.id, line.stream, &line.line);
+        }
+        *last_printed = Some(id);
+    }
+}
+
+/// Lines of one output stream of a process, printed together
+struct OutputBlock {
+    lines: Vec<OutputLine>,
+    started: Instant,
+    last: Instant,
+}
+
+impl OutputBlock {
+    fn due(&self) -> Instant {
+        (self.last + BLOCK_WINDOW).min(self.started + MAX_BLOCK_DURATION)
+    }
+
+    fn print(&self) {
+        let Some(first) = self.lines.first() else {
+            return;
+        };
+        let block: String = self
+            .lines
+            .iter()
+            .map(|line| format!("{} {}", theme::prefix(&line.id), line.line))
+            .collect();
+        // a single print, so no other output is printed in the middle of the block
+        match first.stream {
+            OutputStream::Stdout => print!("{}", block),
+            OutputStream::Stderr => eprint!("{}", block),
+        }
+    }
+}
+
+fn block_output_loop(receiver: mpsc::Receiver<OutputLine>) {
+    let mut blocks: Vec<OutputBlock> = vec![];
+
+    loop {
+        let next_due = blocks.iter().map(|b| b.due()).min();
+        let received = match next_due {
+            Some(due) => receiver.recv_timeout(due.saturating_duration_since(Instant::now())),
+            None => receiver
+                .recv()
+                .map_err(|_| mpsc::RecvTimeoutError::Disconnected),
+        };
+        match received {
+            Ok(line) => {
+                let now = Instant::now();
+                let blank = line.line.trim().is_empty();
+                let position = blocks.iter().position(|b| {
+                    b.lines
+                        .first()
+                        .is_some_and(|l| l.id == line.id && l.stream == line.stream)
+                });
+                let position = position.unwrap_or_else(|| {
+                    blocks.push(OutputBlock {
+                        lines: vec![],
+                        started: now,
+                        last: now,
+                    });
+                    blocks.len() - 1
+                });
+                let block = &mut blocks[position];
+                block.lines.push(line);
+                block.last = now;
+                if blank {
+                    blocks.remove(position).print();
+                }
+            }
+            Err(mpsc::RecvTimeoutError::Timeout) => {}
+            Err(mpsc::RecvTimeoutError::Disconnected) => {
+                blocks.iter().for_each(OutputBlock::print);
+                return;
+            }
+        }
+
+        let now = Instant::now();
+        blocks.retain(|block| {
+            let due = block.due() <= now;
+            if due {
+                block.print();
+            }
+            !due
+        });
+    }
+}
@@ -0,0 +1,76 @@
+use std::net::TcpListener;
+
+use crate::{config::commands::CommandConfig, log};
+
+/// How ports are given to commands
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PortsMode {
+    /// Pick a free port for each command when starting
+    Auto,
+}
+
+/// Ports picked for each configured command, passed to all commands through the environment
+/// so they can find each other
+#[derive(Debug, Clone, Default)]
+pub struct PortAssignments {
+    /// Each command with its alias and port
+    ports: Vec<(String, Option<String>, u16)>,
+}
+
+impl PortAssignments {
+    /// Picks a distinct free port for each command
+    pub fn assign(commands: &[CommandConfig]) -> std::io::Result<Self> {
+        // the listeners are held until all ports are picked, so no port is picked twice
+        let mut listeners = vec![];
+        let mut ports = vec![];
+        for command in commands {
+            let listener = TcpListener::bind(("127.0.0.1", 0))?;
+            let port = listener.local_addr()?.port();
+            listeners.push(listener);
+            ports.push((
+                command.as_str().to_string(),
+                command.alias().map(str::to_string),
+                port,
+            ));
+        }
+        Ok(Self { ports })
+    }
+
+    pub fn log(&self) {
+        log!("Assigned ports:");
+        for (command, alias, port) in &self.ports {
+            match alias {
+                Some(alias) => {
+                    log!("  {:>5} {} (${})", port, alias, env_name(alias));
+                }
+                None => {
+                    log!("  {:>5} {}", port, command);
+                }
+            }
+        }
+    }
+
+    /// `PORT` for the command's own port, and `TOGETHER_PORT_<ALIAS>` for every aliased command
+    pub fn env(&self, command: &str) -> Vec<(String, String)> {
+        let own = self.ports.iter().find(|(c, _, _)| c == command);
+        let own = own.map(|(_, _, port)| ("PORT".to_string(), port.to_string()));
+        let shared = self.ports.iter().filter_map(|(_, alias, port)| {
+            let alias = alias.as_deref()?;
+            Some((env_name(alias), port.to_string()))
+        });
+        own.into_iter().chain(shared).collect()
+    }
+}
+
+/// The variable a command's port is shared as, e.g. `TOGETHER_PORT_WEB_API` for `web-api`
+fn env_name(alias: &str) -> String {
+    let alias: String = alias
+        .chars()
+        .map(|c| match c.is_ascii_alphanumeric() {
+            true => c.to_ascii_uppercase(),
+            false => '_',
+        })
+        .collect();
+    format!("TOGETHER_PORT_{}", alias)
+}
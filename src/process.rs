@@ -1,5 +1,8 @@
-use std::sync::Arc;
+use std::{sync::Arc, time::Duration};
 
+use crate::errors::TogetherResult;
+
+pub use subprocess_impl::exits::watch_exits;
 pub use subprocess_impl::SbProcess::{self as Process};
 
 #[derive(Debug, Clone, Hash, Eq, PartialEq)]
@@ -15,6 +18,9 @@ impl ProcessId {
             command: command.into_boxed_str().into(),
         }
     }
+    pub fn index(&self) -> u32 {
+        self.id
+    }
     pub fn command(&self) -> &str {
         &self.command
     }
@@ -31,13 +37,28 @@ pub enum ProcessSignal {
     SIGINT,
     SIGTERM,
     SIGKILL,
+    /// CTRL_BREAK_EVENT on windows, delivered as SIGINT on unix
+    SIGBREAK,
 }
 
-#[derive(Clone, Copy)]
+/// Resources used by a process, including the processes it started
+#[derive(Debug, Clone, Copy)]
+pub struct ResourceUsage {
+    /// Total CPU time used since the process started
+    pub cpu_time: Duration,
+    /// Resident memory in bytes
+    pub memory: u64,
+}
+
+#[derive(Debug, Clone, Copy)]
 pub enum ProcessStdio {
     Inherit,
     Raw,
     StderrOnly,
+    /// Discard all output
+    Null,
+    /// Run in a pseudo-terminal, its output is forwarded like `Inherit` (unix only)
+    Pty,
 }
 
 impl From<bool> for ProcessStdio {
@@ -50,59 +71,369 @@ impl From<bool> for ProcessStdio {
     }
 }
 
-mod subprocess_impl {
-    use std::{
-        io::BufRead,
-        sync::{Arc, RwLock},
+/// Which variables of together's own environment a process inherits, all of them by default
+#[derive(Debug, Clone, Default)]
+pub struct EnvFilter {
+    /// Only inherit these, along with the variables a shell needs to work such as `PATH`
+    allow: Option<Vec<String>>,
+    block: Vec<String>,
+}
+
+impl EnvFilter {
+    /// Names can end in `*` to match every variable starting with the rest, e.g. `AWS_*`
+    pub fn new(inherit: bool, allow: Option<&[String]>, block: Option<&[String]>) -> Self {
+        let allow = match (inherit, allow) {
+            (_, Some(allow)) => Some(allow.to_vec()),
+            (false, None) => Some(vec![]),
+            (true, None) => None,
+        };
+        Self {
+            allow,
+            block: block.map(<[String]>::to_vec).unwrap_or_default(),
+        }
+    }
+
+    /// Every variable is inherited
+    pub fn inherits_all(&self) -> bool {
+        self.allow.is_none() && self.block.is_empty()
+    }
+
+    pub fn inherits(&self, name: &str) -> bool {
+        let matches = |pattern: &String| match pattern.strip_suffix('*') {
+            Some(prefix) => name.starts_with(prefix),
+            None => name == pattern,
+        };
+        let allowed = match &self.allow {
+            Some(allow) => {
+                subprocess_impl::os::BASE_ENV
+                    .iter()
+                    .any(|base| base_env_matches(base, name))
+                    || allow.iter().any(matches)
+            }
+            None => true,
+        };
+        allowed && !self.block.iter().any(matches)
+    }
+
+    /// The environment of together filtered down to the variables to inherit
+    pub fn apply(
+        &self,
+        vars: impl IntoIterator<Item = (std::ffi::OsString, std::ffi::OsString)>,
+    ) -> Vec<(std::ffi::OsString, std::ffi::OsString)> {
+        vars.into_iter()
+            .filter(|(key, _)| key.to_str().is_some_and(|key| self.inherits(key)))
+            .collect()
+    }
+}
+
+/// Variable names are case insensitive on windows
+fn base_env_matches(base: &str, name: &str) -> bool {
+    match cfg!(windows) {
+        true => base.eq_ignore_ascii_case(name),
+        false => base == name,
+    }
+}
+
+pub fn open_url(url: &str) -> TogetherResult<()> {
+    let mut argv = subprocess_impl::os::OPEN_URL.to_vec();
+    argv.push(url);
+    std::process::Command::new(argv[0])
+        .args(&argv[1..])
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .spawn()?;
+    Ok(())
+}
+
+/// Whether a process with the id is running, including those of other users
+#[cfg(unix)]
+pub fn is_alive(pid: u32) -> bool {
+    let result = unsafe { libc::kill(pid as i32, 0) };
+    // signal 0 fails with EPERM for processes of other users, which still exist
+    result == 0 || std::io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+/// Whether a process with the id is running, including those of other users
+#[cfg(windows)]
+pub fn is_alive(pid: u32) -> bool {
+    use windows_sys::Win32::{
+        Foundation::{CloseHandle, ERROR_ACCESS_DENIED, STILL_ACTIVE},
+        System::Threading::{GetExitCodeProcess, OpenProcess, PROCESS_QUERY_LIMITED_INFORMATION},
+    };
+    let process = unsafe { OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, 0, pid) };
+    if process == 0 {
+        let error = std::io::Error::last_os_error().raw_os_error();
+        return error == Some(ERROR_ACCESS_DENIED as i32);
+    }
+    let mut status = 0;
+    let queried = unsafe { GetExitCodeProcess(process, &mut status) };
+    unsafe { CloseHandle(process) };
+    queried == 0 || status == STILL_ACTIVE as u32
+}
+
+/// Shows a desktop notification, logging rather than failing if it can't be shown
+pub fn notify(title: &str, message: &str) {
+    #[cfg(target_os = "macos")]
+    let argv = [
+        "osascript".to_string(),
+        "-e".to_string(),
+        format!("display notification {:?} with title {:?}", message, title),
+    ];
+    #[cfg(all(unix, not(target_os = "macos")))]
+    let argv = [
+        "notify-send".to_string(),
+        title.to_string(),
+        message.to_string(),
+    ];
+    #[cfg(windows)]
+    let argv: [String; 0] = {
+        _ = (title, message);
+        []
+    };
+
+    let Some((program, args)) = argv.split_first() else {
+        return;
     };
+    let result = std::process::Command::new(program)
+        .args(args)
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .spawn();
+    if let Err(e) = result {
+        crate::log_err!("Failed to show notification: {}", e);
+    }
+}
 
-    use subprocess::{ExitStatus, Popen, PopenConfig};
+mod subprocess_impl {
+    use std::sync::{Arc, RwLock};
 
+    #[cfg(windows)]
+    use crate::log_err;
     use crate::{
         errors::{TogetherInternalError, TogetherResult},
-        log, log_err,
+        output::{OutputHandle, OutputStream},
     };
 
-    use super::{ProcessId, ProcessSignal, ProcessStdio};
+    use super::{EnvFilter, ProcessId, ProcessSignal, ProcessStdio, ResourceUsage};
+    use forwarder::Stream;
 
     pub struct SbProcess {
-        popen: subprocess::Popen,
+        /// Declared first so the wait is unregistered before the process handle is closed
+        #[cfg(windows)]
+        _exit_wait: Option<exits::ExitWait>,
+        child: std::process::Child,
+        /// Controlling side of the pseudo-terminal the process runs in, if any
+        #[cfg(unix)]
+        pty: Option<std::fs::File>,
+        stdin: Option<std::fs::File>,
         mute: Option<Arc<RwLock<bool>>>,
+        #[cfg(windows)]
+        job: Option<job::JobObject>,
     }
 
     impl SbProcess {
+        /// Spawns the command in a shell, with `env` set on top of the environment it inherits
+        /// through `inherit`
+        #[cfg(unix)]
         pub fn spawn(
             command: &str,
             cwd: Option<&str>,
             stdio: ProcessStdio,
+            user: Option<&str>,
+            env: &[(String, String)],
+            inherit: &EnvFilter,
         ) -> TogetherResult<Self> {
-            let mut config = PopenConfig::default();
-            config.stdout = match stdio {
-                ProcessStdio::Raw => subprocess::Redirection::None,
-                _ => subprocess::Redirection::Pipe,
+            use std::os::unix::process::CommandExt;
+            use std::process::{Command, Stdio};
+
+            let user = user.map(users::RunAs::resolve).transpose()?;
+            let pty = match stdio {
+                ProcessStdio::Pty => Some(pty::open()?),
+                _ => None,
             };
-            config.stderr = match stdio {
-                ProcessStdio::Raw | ProcessStdio::StderrOnly => subprocess::Redirection::None,
-                _ => subprocess::Redirection::Pipe,
+
+            let mut child = Command::new(os::SHELL[0]);
+            child.arg(os::SHELL[1]).arg(command).process_group(0);
+            match &pty {
+                Some((_, terminal)) => {
+                    child
+                        .stdin(terminal.try_clone()?)
+                        .stdout(terminal.try_clone()?)
+                        .stderr(terminal.try_clone()?);
+                }
+                None => {
+                    child
+                        .stdin(Stdio::piped())
+                        .stdout(match stdio {
+                            ProcessStdio::Raw => Stdio::inherit(),
+                            // discarded rather than piped, as nothing would read the pipe
+                            ProcessStdio::StderrOnly | ProcessStdio::Null => Stdio::null(),
+                            _ => Stdio::piped(),
+                        })
+                        .stderr(match stdio {
+                            ProcessStdio::Raw | ProcessStdio::StderrOnly => Stdio::inherit(),
+                            ProcessStdio::Null => Stdio::null(),
+                            _ => Stdio::piped(),
+                        });
+                }
+            }
+            if let Some(cwd) = cwd {
+                child.current_dir(cwd);
+            }
+            if user.is_some() || !inherit.inherits_all() {
+                let vars = std::env::vars_os();
+                let vars = match &user {
+                    Some(user) => user.env(vars),
+                    None => vars.collect(),
+                };
+                child.env_clear();
+                child.envs(inherit.apply(vars));
+            }
+            child.envs(env.iter().map(|(k, v)| (k, v)));
+            if let Some(user) = &user {
+                user.configure(&mut child);
+            }
+
+            let mut child = child.spawn()?;
+            // the terminal side is only held by the child from here on, so reads from the
+            // controlling side end once it exits
+            let (stdin, pty) = match pty {
+                Some((pty, _)) => (Some(pty.try_clone()?), Some(pty)),
+                None => (child.stdin.take().map(into_file), None),
             };
-            config.cwd = cwd.map(|s| s.into());
+            let mute = Arc::new(RwLock::new(false));
 
-            #[cfg(unix)]
-            {
-                config.setpgid = true;
+            Ok(Self {
+                child,
+                pty,
+                stdin,
+                mute: Some(mute),
+            })
+        }
+
+        /// Spawned in a new process group, which is required to send it CTRL_BREAK_EVENT
+        #[cfg(windows)]
+        pub fn spawn(
+            command: &str,
+            cwd: Option<&str>,
+            stdio: ProcessStdio,
+            user: Option<&str>,
+            env: &[(String, String)],
+            inherit: &EnvFilter,
+        ) -> TogetherResult<Self> {
+            use std::os::windows::process::CommandExt;
+            use std::process::{Command, Stdio};
+            use windows_sys::Win32::System::Threading::CREATE_NEW_PROCESS_GROUP;
+
+            if let ProcessStdio::Pty = stdio {
+                static WARN_PTY: std::sync::Once = std::sync::Once::new();
+                WARN_PTY.call_once(|| {
+                    log_err!("Pseudo-terminals are not supported on windows, using pipes instead");
+                });
+            }
+            if user.is_some() {
+                static WARN_USER: std::sync::Once = std::sync::Once::new();
+                WARN_USER.call_once(|| {
+                    log_err!("Running commands as another user is not supported on windows");
+                });
             }
 
-            let mut argv = os::SHELL.to_vec();
-            argv.push(command);
-            let popen = Popen::create(&argv, config)?;
+            let mut child = Command::new(os::SHELL[0]);
+            child
+                .arg(os::SHELL[1])
+                .raw_arg(command)
+                .creation_flags(CREATE_NEW_PROCESS_GROUP)
+                .stdin(Stdio::piped())
+                .stdout(match stdio {
+                    ProcessStdio::Raw => Stdio::inherit(),
+                    ProcessStdio::StderrOnly | ProcessStdio::Null => Stdio::null(),
+                    _ => Stdio::piped(),
+                })
+                .stderr(match stdio {
+                    ProcessStdio::Raw | ProcessStdio::StderrOnly => Stdio::inherit(),
+                    ProcessStdio::Null => Stdio::null(),
+                    _ => Stdio::piped(),
+                });
+            if let Some(cwd) = cwd {
+                child.current_dir(cwd);
+            }
+            if !inherit.inherits_all() {
+                child.env_clear();
+                child.envs(inherit.apply(std::env::vars_os()));
+            }
+            child.envs(env.iter().map(|(k, v)| (k, v)));
+            let mut child = child.spawn()?;
+            let stdin = child.stdin.take().map(into_file);
             let mute = Arc::new(RwLock::new(false));
 
+            let job = match job::JobObject::assign(child.id()) {
+                Ok(job) => Some(job),
+                Err(e) => {
+                    log_err!(
+                        "Failed to create job object, child processes may outlive kill: {}",
+                        e
+                    );
+                    None
+                }
+            };
+
+            let exit_wait = match exits::ExitWait::register(&child) {
+                Ok(exit_wait) => Some(exit_wait),
+                Err(e) => {
+                    log_err!("Failed to watch for process exit: {}", e);
+                    None
+                }
+            };
+
             Ok(Self {
-                popen,
+                _exit_wait: exit_wait,
+                child,
+                stdin,
                 mute: Some(mute),
+                job,
             })
         }
 
+        pub fn write_stdin(&mut self, input: &[u8]) -> TogetherResult<()> {
+            use std::io::Write;
+            let Some(stdin) = &mut self.stdin else {
+                return Ok(());
+            };
+            stdin.write_all(input)?;
+            stdin.flush()?;
+            Ok(())
+        }
+
+        #[cfg(windows)]
+        pub fn kill(&mut self, signal: Option<&ProcessSignal>) -> TogetherResult<()> {
+            use windows_sys::Win32::System::Console::{GenerateConsoleCtrlEvent, CTRL_BREAK_EVENT};
+
+            if self.child.try_wait()?.is_some() {
+                return Ok(());
+            }
+            match signal {
+                // CTRL_C_EVENT can't be sent to a single process group, so all graceful signals
+                // are delivered as CTRL_BREAK_EVENT to the group the child was started in
+                Some(ProcessSignal::SIGINT | ProcessSignal::SIGTERM | ProcessSignal::SIGBREAK)
+                | None => {
+                    let pgid = self.child.id();
+                    crate::debug!("sending CTRL_BREAK_EVENT to process group {}", pgid);
+                    if unsafe { GenerateConsoleCtrlEvent(CTRL_BREAK_EVENT, pgid) } == 0 {
+                        return Err(std::io::Error::last_os_error().into());
+                    }
+                    Ok(())
+                }
+                Some(ProcessSignal::SIGKILL) => match &self.job {
+                    Some(job) => {
+                        crate::debug!("terminating job of process {}", self.child.id());
+                        Ok(job.terminate(1)?)
+                    }
+                    None => Ok(self.child.kill()?),
+                },
+            }
+        }
+
+        #[cfg(unix)]
         pub fn kill(&mut self, signal: Option<&ProcessSignal>) -> TogetherResult<()> {
             fn check_err<T: Ord + Default>(num: T) -> std::io::Result<T> {
                 if num < T::default() {
@@ -111,120 +442,913 @@ mod subprocess_impl {
                 Ok(num)
             }
 
-            #[cfg(windows)]
-            {
-                Ok(self.popen.terminate()?)
+            // the process group may have been reused by now once the child is reaped
+            if self.child.try_wait()?.is_some() {
+                return Ok(());
             }
-            #[cfg(unix)]
-            {
-                self.popen.poll();
-                let pid = match self.popen.pid() {
-                    Some(pid) => pid as i32,
-                    _ => return Ok(()),
-                };
-                let signal = match signal {
-                    Some(ProcessSignal::SIGINT) => libc::SIGINT,
-                    Some(ProcessSignal::SIGTERM) => libc::SIGTERM,
-                    Some(ProcessSignal::SIGKILL) => libc::SIGKILL,
-                    None => libc::SIGTERM,
-                };
-                let _code = check_err(unsafe { libc::kill(-pid, signal) })?;
-                Ok(())
+            let pid = self.child.id() as i32;
+            let signal = match signal {
+                Some(ProcessSignal::SIGINT) => libc::SIGINT,
+                Some(ProcessSignal::SIGTERM) => libc::SIGTERM,
+                Some(ProcessSignal::SIGKILL) => libc::SIGKILL,
+                // closest unix equivalent of a console break
+                Some(ProcessSignal::SIGBREAK) => libc::SIGINT,
+                None => libc::SIGTERM,
+            };
+            crate::debug!("sending signal {} to process group {}", signal, pid);
+            let _code = check_err(unsafe { libc::kill(-pid, signal) })?;
+            Ok(())
+        }
+
+        #[cfg(unix)]
+        pub fn try_wait(&mut self) -> TogetherResult<Option<i32>> {
+            use std::os::unix::process::ExitStatusExt;
+
+            match self.child.try_wait()? {
+                Some(status) => match (status.code(), status.signal()) {
+                    (Some(code), _) => Ok(Some(code)),
+                    (None, Some(_)) => Ok(Some(1)),
+                    (None, None) => Err(TogetherInternalError::ProcessFailedToExit.into()),
+                },
+                None => Ok(None),
             }
         }
 
+        #[cfg(windows)]
         pub fn try_wait(&mut self) -> TogetherResult<Option<i32>> {
-            match self.popen.poll() {
-                Some(ExitStatus::Exited(code)) => Ok(Some(code as i32)),
-                Some(ExitStatus::Signaled(_)) => Ok(Some(1)),
-                Some(ExitStatus::Other(_)) | Some(ExitStatus::Undetermined) => {
-                    Err(TogetherInternalError::ProcessFailedToExit.into())
-                }
+            match self.child.try_wait()? {
+                Some(status) => match status.code() {
+                    Some(code) => Ok(Some(code)),
+                    None => Err(TogetherInternalError::ProcessFailedToExit.into()),
+                },
                 None => Ok(None),
             }
         }
 
-        pub fn forward_stdio(&mut self, id: &ProcessId) {
-            let stdout = self.popen.stdout.take().unwrap();
-            let stderr = self.popen.stderr.take().unwrap();
-            let id = id.clone();
-            let mute = self.mute.clone();
+        /// Id of the child, which leads the process group it was started in
+        pub fn pid(&self) -> Option<u32> {
+            Some(self.child.id())
+        }
+
+        /// Resources used by every process in the process group of the child
+        #[cfg(unix)]
+        pub fn usage(&self) -> Option<ResourceUsage> {
+            usage::process_group(self.child.id())
+        }
+
+        /// Resources used by every process in the job object of the child
+        #[cfg(windows)]
+        pub fn usage(&self) -> Option<ResourceUsage> {
+            match self.job.as_ref()?.usage() {
+                Ok(usage) => Some(usage),
+                Err(e) => {
+                    log_err!("Failed to query process resources: {}", e);
+                    None
+                }
+            }
+        }
+
+        pub fn forward_stdio(&mut self, id: &ProcessId, output: &OutputHandle) {
+            #[cfg(unix)]
+            let (stdout, stderr) = match self.pty.take() {
+                Some(pty) => (pty, None),
+                None => (
+                    into_file(self.child.stdout.take().unwrap()),
+                    self.child.stderr.take().map(into_file),
+                ),
+            };
+            #[cfg(windows)]
+            let (stdout, stderr) = (
+                into_file(self.child.stdout.take().unwrap()),
+                self.child.stderr.take().map(into_file),
+            );
+            let stdout = Stream::new(stdout, id, OutputStream::Stdout, self.mute.clone(), output);
+            forwarder::forward(stdout);
+            if let Some(stderr) = stderr {
+                forwarder::forward(Stream::new(stderr, id, OutputStream::Stderr, None, output));
+            }
+        }
+    }
+
+    #[cfg(unix)]
+    fn into_file(stdio: impl Into<std::os::fd::OwnedFd>) -> std::fs::File {
+        std::fs::File::from(stdio.into())
+    }
+
+    #[cfg(windows)]
+    fn into_file(stdio: impl Into<std::os::windows::io::OwnedHandle>) -> std::fs::File {
+        std::fs::File::from(stdio.into())
+    }
+
+    /// Forwards child process output to the `OutputHandle` line by line. On unix a single thread
+    /// polls every pipe, so a stream is read as soon as it has data regardless of the others
+    mod forwarder {
+        use std::{
+            fs::File,
+            io::Read,
+            sync::{Arc, RwLock},
+        };
+
+        use crate::{
+            log_err,
+            output::{OutputHandle, OutputStream},
+            process::ProcessId,
+        };
+
+        const READ_BUFFER_SIZE: usize = 8192;
+
+        pub struct Stream {
+            file: File,
+            id: ProcessId,
+            stream: OutputStream,
+            mute: Option<Arc<RwLock<bool>>>,
+            output: OutputHandle,
+            partial: Vec<u8>,
+        }
+
+        impl Stream {
+            pub fn new(
+                file: File,
+                id: &ProcessId,
+                stream: OutputStream,
+                mute: Option<Arc<RwLock<bool>>>,
+                output: &OutputHandle,
+            ) -> Self {
+                Self {
+                    file,
+                    id: id.clone(),
+                    stream,
+                    mute,
+                    output: output.clone(),
+                    partial: vec![],
+                }
+            }
+
+            /// Reads the available output, returning false once the stream has closed
+            fn read(&mut self, buf: &mut [u8]) -> bool {
+                match self.file.read(buf) {
+                    Ok(0) => false,
+                    Ok(n) => {
+                        self.push(&buf[..n]);
+                        true
+                    }
+                    Err(e) if e.kind() == std::io::ErrorKind::Interrupted => true,
+                    // a pseudo-terminal reports EIO rather than EOF once the process exits
+                    #[cfg(unix)]
+                    Err(e) if e.raw_os_error() == Some(libc::EIO) => false,
+                    Err(e) => {
+                        log_err!("Failed to read {:?} of {}: {}", self.stream, self.id, e);
+                        false
+                    }
+                }
+            }
+
+            fn push(&mut self, bytes: &[u8]) {
+                self.partial.extend_from_slice(bytes);
+                while let Some(end) = self.partial.iter().position(|b| *b == b'\n') {
+                    let line: Vec<u8> = self.partial.drain(..=end).collect();
+                    self.write(&line);
+                }
+            }
+
+            /// Writes out any trailing output that didn't end with a newline
+            fn finish(self) {
+                if !self.partial.is_empty() {
+                    self.write(&self.partial);
+                }
+            }
+
+            fn write(&self, line: &[u8]) {
+                let line = String::from_utf8_lossy(line);
+                if self.mute.as_ref().is_some_and(|m| *m.read().unwrap()) {
+                    self.output.write_muted(&self.id, &line);
+                } else {
+                    self.output.write(&self.id, self.stream, &line);
+                }
+            }
+        }
+
+        #[cfg(unix)]
+        pub fn forward(stream: Stream) {
+            static FORWARDER: std::sync::OnceLock<Option<poll::Forwarder>> =
+                std::sync::OnceLock::new();
+            let forwarder = FORWARDER.get_or_init(|| match poll::Forwarder::start() {
+                Ok(forwarder) => Some(forwarder),
+                Err(e) => {
+                    log_err!(
+                        "Failed to start output forwarder, using a thread per stream: {}",
+                        e
+                    );
+                    None
+                }
+            });
+            match forwarder {
+                Some(forwarder) => forwarder.add(stream),
+                None => forward_threaded(stream),
+            }
+        }
+
+        #[cfg(windows)]
+        pub fn forward(stream: Stream) {
+            // anonymous pipes can't be polled on windows
+            forward_threaded(stream)
+        }
+
+        fn forward_threaded(mut stream: Stream) {
             std::thread::spawn(move || {
-                let id = id.clone();
-                Self::forward_stdio_blocking(&id, stdout, stderr, mute)
+                let mut buf = [0; READ_BUFFER_SIZE];
+                while stream.read(&mut buf) {}
+                stream.finish();
             });
         }
 
-        fn forward_stdio_blocking(
-            id: &ProcessId,
-            stdout: std::fs::File,
-            stderr: std::fs::File,
-            mute: Option<Arc<RwLock<bool>>>,
-        ) {
-            let mut stdout = std::io::BufReader::new(stdout);
-            let mut stderr = std::io::BufReader::new(stderr);
-            let mut stdout_line = String::new();
-            let mut stderr_line = String::new();
-            loop {
-                let mut stdout_done = false;
-                let mut stderr_done = false;
-                let mut stdout_bytes = vec![];
-                let mut stderr_bytes = vec![];
-                let stdout_read = stdout.read_line(&mut stdout_line);
-                let stderr_read = stderr.read_line(&mut stderr_line);
-                match (stdout_read, stderr_read) {
-                    (Ok(0), Ok(0)) => {
-                        stdout_done = true;
-                        stderr_done = true;
+        #[cfg(unix)]
+        mod poll {
+            use std::{
+                io::{Read, Write},
+                os::{fd::AsRawFd, unix::net::UnixStream},
+                sync::{mpsc, Mutex},
+            };
+
+            use super::{Stream, READ_BUFFER_SIZE};
+            use crate::log_err;
+
+            pub struct Forwarder {
+                sender: Mutex<mpsc::Sender<Stream>>,
+                /// Interrupts `poll` so newly added streams are picked up
+                wake: UnixStream,
+            }
+
+            impl Forwarder {
+                pub fn start() -> std::io::Result<Self> {
+                    let (wake, wake_receiver) = UnixStream::pair()?;
+                    wake.set_nonblocking(true)?;
+                    wake_receiver.set_nonblocking(true)?;
+                    let (sender, receiver) = mpsc::channel();
+                    std::thread::spawn(move || poll_loop(receiver, wake_receiver));
+                    Ok(Self {
+                        sender: Mutex::new(sender),
+                        wake,
+                    })
+                }
+
+                pub fn add(&self, stream: Stream) {
+                    if let Err(mpsc::SendError(stream)) = self.sender.lock().unwrap().send(stream) {
+                        super::forward_threaded(stream);
+                        return;
+                    }
+                    // a full socket means a wake up is already pending
+                    _ = (&self.wake).write(&[0]);
+                }
+            }
+
+            fn poll_loop(receiver: mpsc::Receiver<Stream>, wake: UnixStream) {
+                let mut streams: Vec<Stream> = vec![];
+                let mut buf = [0; READ_BUFFER_SIZE];
+                loop {
+                    let mut fds: Vec<_> = std::iter::once(wake.as_raw_fd())
+                        .chain(streams.iter().map(|s| s.file.as_raw_fd()))
+                        .map(|fd| libc::pollfd {
+                            fd,
+                            events: libc::POLLIN,
+                            revents: 0,
+                        })
+                        .collect();
+                    let result =
+                        unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, -1) };
+                    if result < 0 {
+                        let e = std::io::Error::last_os_error();
+                        if e.kind() != std::io::ErrorKind::Interrupted {
+                            log_err!("Failed to poll process output: {}", e);
+                            std::thread::sleep(std::time::Duration::from_millis(100));
+                        }
+                        continue;
                     }
-                    (Ok(0), _) => {
-                        stdout_done = true;
+
+                    if fds[0].revents != 0 {
+                        while (&wake).read(&mut buf).is_ok_and(|n| n > 0) {}
                     }
-                    (_, Ok(0)) => {
-                        stderr_done = true;
+                    let mut open = Vec::with_capacity(streams.len());
+                    for (mut stream, fd) in streams.into_iter().zip(&fds[1..]) {
+                        if fd.revents == 0 || stream.read(&mut buf) {
+                            open.push(stream);
+                        } else {
+                            stream.finish();
+                        }
                     }
-                    (Ok(_), Ok(_)) => {}
-                    (Err(e), _) => {
-                        log_err!("Failed to read stdout: {}", e);
-                        stdout_done = true;
+                    streams = open;
+                    streams.extend(receiver.try_iter());
+                }
+            }
+        }
+    }
+
+    #[cfg(unix)]
+    mod pty {
+        use std::{
+            fs::File,
+            os::{
+                fd::FromRawFd,
+                unix::{ffi::OsStrExt, fs::OpenOptionsExt},
+            },
+        };
+
+        /// Opens a pseudo-terminal sized like the current terminal, returning its controlling
+        /// side and the terminal side to hand to the child
+        pub fn open() -> std::io::Result<(File, File)> {
+            let fd = unsafe { libc::posix_openpt(libc::O_RDWR | libc::O_NOCTTY) };
+            if fd < 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+            let pty = unsafe { File::from_raw_fd(fd) };
+            unsafe {
+                if libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) < 0
+                    || libc::grantpt(fd) < 0
+                    || libc::unlockpt(fd) < 0
+                {
+                    return Err(std::io::Error::last_os_error());
+                }
+            }
+
+            // ptsname isn't thread safe, but processes are only spawned from the manager thread
+            let name = unsafe { libc::ptsname(fd) };
+            if name.is_null() {
+                return Err(std::io::Error::last_os_error());
+            }
+            let name = unsafe { std::ffi::CStr::from_ptr(name) };
+            let name = std::ffi::OsStr::from_bytes(name.to_bytes());
+            let terminal = std::fs::OpenOptions::new()
+                .read(true)
+                .write(true)
+                .custom_flags(libc::O_NOCTTY)
+                .open(name)?;
+
+            let mut size: libc::winsize = unsafe { std::mem::zeroed() };
+            unsafe {
+                if libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) == 0 {
+                    libc::ioctl(fd, libc::TIOCSWINSZ, &size);
+                }
+            }
+            Ok((pty, terminal))
+        }
+    }
+
+    #[cfg(unix)]
+    mod users {
+        use std::{
+            ffi::{CStr, CString, OsString},
+            os::unix::{ffi::OsStrExt, process::CommandExt},
+            process::Command,
+        };
+
+        /// A user to run a command as
+        pub struct RunAs {
+            uid: u32,
+            gid: u32,
+            /// Name and home directory of the user, if it has a passwd entry
+            entry: Option<(OsString, OsString)>,
+            /// Supplementary groups to give the command when together runs as root, the groups
+            /// of the user or none when it has no passwd entry
+            groups: Option<Vec<libc::gid_t>>,
+        }
+
+        impl RunAs {
+            /// Looks up a user by name, or by `uid[:gid]`. The gid defaults to the primary group
+            /// of the user.
+            pub fn resolve(user: &str) -> std::io::Result<Self> {
+                let (name, gid) = match user.split_once(':') {
+                    Some((name, gid)) => (name, Some(gid)),
+                    None => (user, None),
+                };
+                let passwd = Passwd::lookup(name)?;
+                let uid = match (&passwd, name.parse::<u32>()) {
+                    (Some(passwd), _) => passwd.uid,
+                    (None, Ok(uid)) => uid,
+                    (None, Err(_)) => {
+                        return Err(std::io::Error::new(
+                            std::io::ErrorKind::NotFound,
+                            format!("unknown user '{}'", name),
+                        ))
                     }
-                    (_, Err(e)) => {
-                        log_err!("Failed to read stderr: {}", e);
-                        stderr_done = true;
+                };
+                let gid = match gid {
+                    Some(gid) => gid.parse::<u32>().map_err(|_| {
+                        std::io::Error::new(
+                            std::io::ErrorKind::InvalidInput,
+                            format!("invalid group id '{}'", gid),
+                        )
+                    })?,
+                    None => passwd.as_ref().map_or(uid, |p| p.gid),
+                };
+                let groups = match unsafe { libc::geteuid() } {
+                    0 => Some(match &passwd {
+                        Some(passwd) => group_list(&passwd.name, gid)?,
+                        None => vec![],
+                    }),
+                    _ => None,
+                };
+                Ok(Self {
+                    uid,
+                    gid,
+                    entry: passwd.map(|p| (p.name, p.home)),
+                    groups,
+                })
+            }
+
+            /// The environment with `USER` and `HOME` pointing at the user
+            pub fn env(
+                &self,
+                vars: impl Iterator<Item = (OsString, OsString)>,
+            ) -> Vec<(OsString, OsString)> {
+                let Some((name, home)) = &self.entry else {
+                    return vars.collect();
+                };
+                vars.filter(|(key, _)| !matches!(key.to_str(), Some("USER" | "HOME")))
+                    .chain([("USER".into(), name.clone()), ("HOME".into(), home.clone())])
+                    .collect()
+            }
+
+            /// Drops to the user in the child before it runs the command, leaving the
+            /// credentials of together itself untouched
+            pub fn configure(&self, command: &mut Command) {
+                let (uid, gid, groups) = (self.uid, self.gid, self.groups.clone());
+                // only async-signal-safe calls are allowed between fork and exec, so the groups
+                // are looked up beforehand
+                let drop_privileges = move || {
+                    let check = |code: libc::c_int| match code {
+                        0 => Ok(()),
+                        _ => Err(std::io::Error::last_os_error()),
+                    };
+                    if let Some(groups) = &groups {
+                        check(unsafe { libc::setgroups(groups.len() as _, groups.as_ptr()) })?;
                     }
+                    // the group is set first, as setuid gives up the permission to change it
+                    check(unsafe { libc::setgid(gid) })?;
+                    check(unsafe { libc::setuid(uid) })
+                };
+                unsafe { command.pre_exec(drop_privileges) };
+            }
+        }
+
+        /// The groups of a user, including its primary group `gid`
+        fn group_list(name: &OsString, gid: u32) -> std::io::Result<Vec<libc::gid_t>> {
+            let name = CString::new(name.as_bytes())?;
+            let mut len = 32;
+            loop {
+                let mut groups = vec![0; len];
+                let mut count = len as libc::c_int;
+                #[cfg(not(target_os = "macos"))]
+                let found = unsafe {
+                    libc::getgrouplist(name.as_ptr(), gid, groups.as_mut_ptr(), &mut count)
+                };
+                #[cfg(target_os = "macos")]
+                let found = unsafe {
+                    libc::getgrouplist(
+                        name.as_ptr(),
+                        gid as libc::c_int,
+                        groups.as_mut_ptr() as *mut libc::c_int,
+                        &mut count,
+                    )
+                };
+                if found >= 0 {
+                    groups.truncate(count as usize);
+                    return Ok(groups);
+                }
+                if len >= 65536 {
+                    return Err(std::io::Error::other("too many groups"));
+                }
+                len *= 2;
+            }
+        }
+
+        struct Passwd {
+            uid: u32,
+            gid: u32,
+            name: OsString,
+            home: OsString,
+        }
+
+        impl Passwd {
+            /// Looks up a user by name, or by uid if it's a number
+            fn lookup(user: &str) -> std::io::Result<Option<Self>> {
+                let mut passwd: libc::passwd = unsafe { std::mem::zeroed() };
+                let mut buf = vec![0; 16384];
+                let mut result = std::ptr::null_mut();
+                let code = match user.parse::<u32>() {
+                    Ok(uid) => unsafe {
+                        libc::getpwuid_r(uid, &mut passwd, buf.as_mut_ptr(), buf.len(), &mut result)
+                    },
+                    Err(_) => {
+                        let name = CString::new(user)?;
+                        unsafe {
+                            libc::getpwnam_r(
+                                name.as_ptr(),
+                                &mut passwd,
+                                buf.as_mut_ptr(),
+                                buf.len(),
+                                &mut result,
+                            )
+                        }
+                    }
+                };
+                if code != 0 {
+                    return Err(std::io::Error::from_raw_os_error(code));
+                }
+                if result.is_null() {
+                    return Ok(None);
+                }
+                let string = |s: *const libc::c_char| {
+                    let s = unsafe { CStr::from_ptr(s) };
+                    std::ffi::OsStr::from_bytes(s.to_bytes()).to_os_string()
+                };
+                Ok(Some(Self {
+                    uid: passwd.pw_uid,
+                    gid: passwd.pw_gid,
+                    name: string(passwd.pw_name),
+                    home: string(passwd.pw_dir),
+                }))
+            }
+        }
+    }
+
+    #[cfg(unix)]
+    mod usage {
+        use std::time::Duration;
+
+        use super::ResourceUsage;
+
+        /// Sums the usage of every process in the group, read from `/proc`
+        #[cfg(target_os = "linux")]
+        pub fn process_group(pgid: u32) -> Option<ResourceUsage> {
+            let ticks_per_sec = unsafe { libc::sysconf(libc::_SC_CLK_TCK) };
+            let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) };
+            if ticks_per_sec <= 0 || page_size <= 0 {
+                return None;
+            }
+
+            let mut found = false;
+            let (mut ticks, mut pages) = (0, 0);
+            for entry in std::fs::read_dir("/proc").ok()?.flatten() {
+                let name = entry.file_name();
+                if !name
+                    .to_str()
+                    .is_some_and(|n| n.bytes().all(|b| b.is_ascii_digit()))
+                {
+                    continue;
+                }
+                let Ok(stat) = std::fs::read_to_string(entry.path().join("stat")) else {
+                    continue;
+                };
+                // fields after the command name, which is in parentheses and may contain spaces
+                let Some((_, fields)) = stat.rsplit_once(')') else {
+                    continue;
+                };
+                let fields: Vec<_> = fields.split_whitespace().collect();
+                let field = |i: usize| fields.get(i).and_then(|f| f.parse::<u64>().ok());
+                if field(2) != Some(pgid as u64) {
+                    continue;
+                }
+                found = true;
+                ticks += field(11).unwrap_or(0) + field(12).unwrap_or(0);
+                pages += field(21).unwrap_or(0);
+            }
+
+            found.then(|| ResourceUsage {
+                cpu_time: Duration::from_secs_f64(ticks as f64 / ticks_per_sec as f64),
+                memory: pages * page_size as u64,
+            })
+        }
+
+        /// Sums the usage of every process in the group, as listed by `ps`
+        #[cfg(not(target_os = "linux"))]
+        pub fn process_group(pgid: u32) -> Option<ResourceUsage> {
+            let output = std::process::Command::new("ps")
+                .args(["-A", "-o", "pgid=,rss=,time="])
+                .stderr(std::process::Stdio::null())
+                .output()
+                .ok()?;
+            let pgid = pgid.to_string();
+
+            let mut usage: Option<ResourceUsage> = None;
+            for line in String::from_utf8_lossy(&output.stdout).lines() {
+                let mut fields = line.split_whitespace();
+                if fields.next() != Some(pgid.as_str()) {
+                    continue;
+                }
+                let kilobytes = fields.next().and_then(|f| f.parse::<u64>().ok());
+                let cpu_time = fields.next().and_then(parse_cpu_time);
+                let usage = usage.get_or_insert(ResourceUsage {
+                    cpu_time: Duration::ZERO,
+                    memory: 0,
+                });
+                usage.cpu_time += cpu_time.unwrap_or_default();
+                usage.memory += kilobytes.unwrap_or(0) * 1024;
+            }
+            usage
+        }
+
+        /// Parses a `ps` time, `[dd-]hh:mm:ss` or `mm:ss.cc`
+        #[cfg(not(target_os = "linux"))]
+        fn parse_cpu_time(time: &str) -> Option<Duration> {
+            let (days, time) = match time.split_once('-') {
+                Some((days, time)) => (days.parse::<u64>().ok()?, time),
+                None => (0, time),
+            };
+            let secs = time.split(':').try_fold(0.0, |acc, part| {
+                part.parse::<f64>().ok().map(|part| acc * 60.0 + part)
+            })?;
+            Some(Duration::from_secs_f64(days as f64 * 86400.0 + secs))
+        }
+    }
+
+    /// Windows counterpart of the unix process group: every process in the tree of a command
+    /// joins its job object, so terminating the job also stops grandchildren
+    #[cfg(windows)]
+    mod job {
+        use windows_sys::Win32::{
+            Foundation::{CloseHandle, HANDLE},
+            System::{
+                JobObjects::{
+                    AssignProcessToJobObject, CreateJobObjectW,
+                    JobObjectBasicAccountingInformation, JobObjectBasicProcessIdList,
+                    QueryInformationJobObject, TerminateJobObject,
+                    JOBOBJECT_BASIC_ACCOUNTING_INFORMATION,
+                },
+                ProcessStatus::{K32GetProcessMemoryInfo, PROCESS_MEMORY_COUNTERS},
+                Threading::{
+                    OpenProcess, PROCESS_QUERY_LIMITED_INFORMATION, PROCESS_SET_QUOTA,
+                    PROCESS_TERMINATE,
+                },
+            },
+        };
+
+        use super::ResourceUsage;
+
+        /// Most processes of a job whose memory is counted
+        const MAX_JOB_PROCESSES: usize = 256;
+
+        /// `JOBOBJECT_BASIC_PROCESS_ID_LIST` with room for more than one process
+        #[repr(C)]
+        struct ProcessIdList {
+            assigned: u32,
+            listed: u32,
+            ids: [usize; MAX_JOB_PROCESSES],
+        }
+
+        pub struct JobObject {
+            handle: HANDLE,
+        }
+
+        impl JobObject {
+            /// Creates a job object for the process. Grandchildren spawned before the process
+            /// was assigned are not part of the job.
+            pub fn assign(pid: u32) -> std::io::Result<Self> {
+                let handle = unsafe { CreateJobObjectW(std::ptr::null(), std::ptr::null()) };
+                if handle == 0 {
+                    return Err(std::io::Error::last_os_error());
                 }
-                if !stdout_done {
-                    stdout_bytes.extend(stdout_line.as_bytes());
-                    stdout_line.clear();
+                let job = Self { handle };
+
+                let process = unsafe { OpenProcess(PROCESS_SET_QUOTA | PROCESS_TERMINATE, 0, pid) };
+                if process == 0 {
+                    return Err(std::io::Error::last_os_error());
                 }
-                if !stderr_done {
-                    stderr_bytes.extend(stderr_line.as_bytes());
-                    stderr_line.clear();
+                let assigned = unsafe { AssignProcessToJobObject(job.handle, process) };
+                let error = std::io::Error::last_os_error();
+                unsafe { CloseHandle(process) };
+                if assigned == 0 {
+                    return Err(error);
                 }
-                if !stdout_bytes.is_empty() {
-                    while mute.as_ref().map_or(false, |m| *m.read().unwrap()) {
-                        log!("Skipping muted process {}", id.id);
-                        std::thread::sleep(std::time::Duration::from_millis(100));
+                Ok(job)
+            }
+
+            /// CPU time of every process that ran in the job, and the working set of those
+            /// still running
+            pub fn usage(&self) -> std::io::Result<ResourceUsage> {
+                let mut accounting: JOBOBJECT_BASIC_ACCOUNTING_INFORMATION =
+                    unsafe { std::mem::zeroed() };
+                self.query(JobObjectBasicAccountingInformation, &mut accounting)?;
+                let cpu_time = (accounting.TotalUserTime + accounting.TotalKernelTime) as u64;
+
+                let mut list: ProcessIdList = unsafe { std::mem::zeroed() };
+                self.query(JobObjectBasicProcessIdList, &mut list)?;
+                let listed = (list.listed as usize).min(MAX_JOB_PROCESSES);
+
+                let mut memory = 0;
+                for &pid in &list.ids[..listed] {
+                    let process =
+                        unsafe { OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, 0, pid as u32) };
+                    if process == 0 {
+                        continue;
+                    }
+                    let mut counters: PROCESS_MEMORY_COUNTERS = unsafe { std::mem::zeroed() };
+                    let size = std::mem::size_of::<PROCESS_MEMORY_COUNTERS>() as u32;
+                    counters.cb = size;
+                    if unsafe { K32GetProcessMemoryInfo(process, &mut counters, size) } != 0 {
+                        memory += counters.WorkingSetSize as u64;
                     }
-                    print!("{}: {}", id.id, String::from_utf8_lossy(&stdout_bytes));
+                    unsafe { CloseHandle(process) };
                 }
-                if !stderr_bytes.is_empty() {
-                    eprint!("{}: {}", id.id, String::from_utf8_lossy(&stderr_bytes));
+
+                Ok(ResourceUsage {
+                    // job times are in 100ns units
+                    cpu_time: std::time::Duration::from_nanos(cpu_time * 100),
+                    memory,
+                })
+            }
+
+            fn query<T>(&self, class: i32, info: &mut T) -> std::io::Result<()> {
+                let size = std::mem::size_of::<T>() as u32;
+                let info = info as *mut T as *mut std::ffi::c_void;
+                let queried = unsafe {
+                    QueryInformationJobObject(self.handle, class, info, size, std::ptr::null_mut())
+                };
+                if queried == 0 {
+                    return Err(std::io::Error::last_os_error());
                 }
-                if stdout_done && stderr_done {
-                    break;
+                Ok(())
+            }
+
+            pub fn terminate(&self, exit_code: u32) -> std::io::Result<()> {
+                if unsafe { TerminateJobObject(self.handle, exit_code) } == 0 {
+                    return Err(std::io::Error::last_os_error());
                 }
+                Ok(())
+            }
+        }
+
+        impl Drop for JobObject {
+            fn drop(&mut self) {
+                unsafe { CloseHandle(self.handle) };
+            }
+        }
+    }
+
+    pub mod exits {
+        use std::sync::Mutex;
+
+        use crate::errors::TogetherResult;
+
+        type Watcher = Box<dyn Fn() -> bool + Send>;
+
+        static WATCHERS: Mutex<Vec<Watcher>> = Mutex::new(Vec::new());
+
+        /// Calls `notify` whenever a child process may have exited, so exits can be handled as
+        /// they happen rather than by polling every process. `notify` returns false once it no
+        /// longer needs to be called.
+        pub fn watch_exits(notify: impl Fn() -> bool + Send + 'static) -> TogetherResult<()> {
+            #[cfg(unix)]
+            {
+                static INSTALLED: std::sync::OnceLock<Result<(), String>> =
+                    std::sync::OnceLock::new();
+                let installed = INSTALLED.get_or_init(|| install().map_err(|e| e.to_string()));
+                if let Err(e) = installed {
+                    return Err(std::io::Error::other(e.clone()).into());
+                }
+            }
+            WATCHERS.lock().unwrap().push(Box::new(notify));
+            Ok(())
+        }
+
+        fn notify_watchers() {
+            WATCHERS.lock().unwrap().retain(|notify| notify());
+        }
+
+        /// Installs a SIGCHLD handler, which wakes a thread that notifies the watchers
+        #[cfg(unix)]
+        fn install() -> std::io::Result<()> {
+            use std::{
+                io::Read,
+                os::{fd::IntoRawFd, unix::net::UnixStream},
+                sync::atomic::{AtomicI32, Ordering},
+            };
+
+            static WAKE: AtomicI32 = AtomicI32::new(-1);
+
+            extern "C" fn on_sigchld(_: libc::c_int) {
+                let fd = WAKE.load(Ordering::Relaxed);
+                if fd < 0 {
+                    return;
+                }
+                // only async-signal-safe calls are allowed here, and errno must be preserved
+                unsafe {
+                    let errno = *errno_location();
+                    libc::write(fd, [0u8].as_ptr().cast(), 1);
+                    *errno_location() = errno;
+                }
+            }
+
+            let (wake, mut receiver) = UnixStream::pair()?;
+            // a full socket means a notification is already pending
+            wake.set_nonblocking(true)?;
+            WAKE.store(wake.into_raw_fd(), Ordering::Relaxed);
+
+            unsafe {
+                let mut action: libc::sigaction = std::mem::zeroed();
+                action.sa_sigaction = on_sigchld as extern "C" fn(libc::c_int) as usize;
+                action.sa_flags = libc::SA_RESTART | libc::SA_NOCLDSTOP;
+                libc::sigemptyset(&mut action.sa_mask);
+                if libc::sigaction(libc::SIGCHLD, &action, std::ptr::null_mut()) != 0 {
+                    return Err(std::io::Error::last_os_error());
+                }
+            }
+
+            std::thread::spawn(move || {
+                let mut buf = [0; 64];
+                while receiver.read(&mut buf).is_ok_and(|n| n > 0) {
+                    notify_watchers();
+                }
+            });
+            Ok(())
+        }
+
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        unsafe fn errno_location() -> *mut libc::c_int {
+            libc::__errno_location()
+        }
+
+        #[cfg(all(unix, not(any(target_os = "linux", target_os = "android"))))]
+        unsafe fn errno_location() -> *mut libc::c_int {
+            libc::__error()
+        }
+
+        /// A wait registered with the thread pool, which notifies the `watch_exits` callbacks once
+        /// the process exits
+        #[cfg(windows)]
+        pub struct ExitWait {
+            handle: windows_sys::Win32::Foundation::HANDLE,
+        }
+
+        #[cfg(windows)]
+        impl ExitWait {
+            pub fn register(child: &std::process::Child) -> std::io::Result<Self> {
+                use std::os::windows::io::AsRawHandle;
+                use windows_sys::Win32::{
+                    Foundation::BOOLEAN,
+                    System::Threading::{
+                        RegisterWaitForSingleObject, INFINITE, WT_EXECUTEONLYONCE,
+                    },
+                };
+
+                unsafe extern "system" fn on_exit(_: *mut std::ffi::c_void, _: BOOLEAN) {
+                    notify_watchers();
+                }
+
+                let mut handle = 0;
+                let registered = unsafe {
+                    RegisterWaitForSingleObject(
+                        &mut handle,
+                        child.as_raw_handle() as _,
+                        Some(on_exit),
+                        std::ptr::null(),
+                        INFINITE,
+                        WT_EXECUTEONLYONCE,
+                    )
+                };
+                if registered == 0 {
+                    return Err(std::io::Error::last_os_error());
+                }
+                Ok(Self { handle })
+            }
+        }
+
+        #[cfg(windows)]
+        impl Drop for ExitWait {
+            fn drop(&mut self) {
+                use windows_sys::Win32::System::Threading::UnregisterWaitEx;
+                unsafe { UnregisterWaitEx(self.handle, 0) };
             }
         }
     }
 
     #[cfg(unix)]
-    mod os {
+    pub(super) mod os {
         pub const SHELL: [&str; 2] = ["sh", "-c"];
+        /// Inherited even when a command doesn't inherit the rest of the environment
+        pub const BASE_ENV: &[&str] = &[
+            "PATH", "HOME", "USER", "LOGNAME", "SHELL", "TERM", "LANG", "TMPDIR",
+        ];
+        #[cfg(target_os = "macos")]
+        pub const OPEN_URL: [&str; 1] = ["open"];
+        #[cfg(not(target_os = "macos"))]
+        pub const OPEN_URL: [&str; 1] = ["xdg-open"];
     }
 
     #[cfg(windows)]
-    mod os {
+    pub(super) mod os {
         pub const SHELL: [&str; 2] = ["cmd.exe", "/c"];
+        /// Inherited even when a command doesn't inherit the rest of the environment
+        pub const BASE_ENV: &[&str] = &[
+            "PATH",
+            "PATHEXT",
+            "SYSTEMROOT",
+            "SYSTEMDRIVE",
+            "WINDIR",
+            "COMSPEC",
+            "TEMP",
+            "TMP",
+            "USERPROFILE",
+        ];
+        pub const OPEN_URL: [&str; 4] = ["cmd.exe", "/c", "start", ""];
     }
 }
@@ -0,0 +1,287 @@
+use std::time::{Duration, Instant};
+
+use chrono::{Datelike, Local, NaiveDate, NaiveDateTime, TimeZone, Timelike};
+
+use crate::config::commands::CommandConfig;
+
+/// How often a recurring command runs again, from its `every` or `schedule` setting
+#[derive(Debug, Clone)]
+pub enum Recurrence {
+    Every(Duration),
+    Cron(CronSchedule),
+}
+
+impl Recurrence {
+    /// The recurrence configured for a command, if any
+    pub fn of(command: &CommandConfig) -> Option<Result<Self, String>> {
+        match (command.every(), command.schedule()) {
+            (Some(_), Some(_)) => Some(Err("set either `every` or `schedule`".to_string())),
+            (Some(every), None) => Some(parse_duration(every).map(Self::Every)),
+            (None, Some(schedule)) => Some(schedule.parse().map(Self::Cron)),
+            (None, None) => None,
+        }
+    }
+
+    /// When the command should run next, after running at `now`
+    pub fn next(&self, now: Instant) -> Option<Instant> {
+        match self {
+            Self::Every(interval) => Some(now + *interval),
+            Self::Cron(schedule) => {
+                let current = Local::now();
+                let next = schedule.next_after(current.naive_local())?;
+                let next = Local.from_local_datetime(&next).earliest()?;
+                Some(now + (next - current).to_std().unwrap_or_default())
+            }
+        }
+    }
+}
+
+/// Parses a duration such as `30s`, `5m`, `1h30m` or `500ms`
+pub fn parse_duration(duration: &str) -> Result<Duration, String> {
+    let invalid = || {
+        format!(
+            "invalid duration '{}', e.g. '30s', '5m' or '1h30m'",
+            duration
+        )
+    };
+    let mut rest = duration.trim();
+    let mut total = Duration::ZERO;
+    while !rest.is_empty() {
+        let digits = rest
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(rest.len());
+        let value: u64 = rest[..digits].parse().map_err(|_| invalid())?;
+        rest = &rest[digits..];
+        let unit = rest
+            .find(|c: char| c.is_ascii_digit())
+            .unwrap_or(rest.len());
+        total += match &rest[..unit] {
+            "ms" => Duration::from_millis(value),
+            "s" => Duration::from_secs(value),
+            "m" => Duration::from_secs(value * 60),
+            "h" => Duration::from_secs(value * 60 * 60),
+            "d" => Duration::from_secs(value * 60 * 60 * 24),
+            _ => return Err(invalid()),
+        };
+        rest = &rest[unit..];
+    }
+    match total.is_zero() {
+        true => Err(invalid()),
+        false => Ok(total),
+    }
+}
+
+/// A five field cron expression: minute, hour, day of month, month and day of week. Fields
+/// accept `*`, values, ranges, lists and steps, e.g. `*/5 9-17 * * 1-5`.
+#[derive(Debug, Clone)]
+pub struct CronSchedule {
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    /// When both the day of month and day of week are restricted, either one matching is enough
+    any_day: bool,
+    any_weekday: bool,
+}
+
+impl std::str::FromStr for CronSchedule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let fields: Vec<_> = s.split_whitespace().collect();
+        let [minute, hour, day, month, weekday] = fields[..] else {
+            return Err(format!(
+                "cron schedule '{}' should have five fields: minute hour day month weekday",
+                s
+            ));
+        };
+        let mut weekdays = parse_field(weekday, 0, 7)?;
+        // sunday is either 0 or 7
+        if weekdays & (1 << 7) != 0 {
+            weekdays |= 1;
+        }
+        let schedule = Self {
+            minutes: parse_field(minute, 0, 59)?,
+            hours: parse_field(hour, 0, 23)?,
+            days: parse_field(day, 1, 31)?,
+            months: parse_field(month, 1, 12)?,
+            weekdays,
+            any_day: day == "*",
+            any_weekday: weekday == "*",
+        };
+        if schedule.next_after(Local::now().naive_local()).is_none() {
+            return Err(format!("cron schedule '{}' never runs", s));
+        }
+        Ok(schedule)
+    }
+}
+
+impl CronSchedule {
+    /// The first whole minute after `time` matching the schedule
+    pub fn next_after(&self, time: NaiveDateTime) -> Option<NaiveDateTime> {
+        let mut time = time.with_second(0)?.with_nanosecond(0)? + chrono::Duration::minutes(1);
+        // matching days can be years apart, e.g. february 29th on a monday
+        let limit = time + chrono::Duration::days(366 * 8);
+        while time < limit {
+            let date = time.date();
+            if !has(self.months, date.month()) {
+                let (year, month) = match date.month() {
+                    12 => (date.year() + 1, 1),
+                    month => (date.year(), month + 1),
+                };
+                time = NaiveDate::from_ymd_opt(year, month, 1)?.and_hms_opt(0, 0, 0)?;
+            } else if !self.day_matches(date) {
+                time = date.succ_opt()?.and_hms_opt(0, 0, 0)?;
+            } else if !has(self.hours, time.hour()) {
+                time = time.with_minute(0)? + chrono::Duration::hours(1);
+            } else if !has(self.minutes, time.minute()) {
+                time += chrono::Duration::minutes(1);
+            } else {
+                return Some(time);
+            }
+        }
+        None
+    }
+
+    fn day_matches(&self, date: NaiveDate) -> bool {
+        let day = has(self.days, date.day());
+        let weekday = has(self.weekdays, date.weekday().num_days_from_sunday());
+        match (self.any_day, self.any_weekday) {
+            (false, false) => day || weekday,
+            _ => day && weekday,
+        }
+    }
+}
+
+fn has(set: u64, value: u32) -> bool {
+    set & (1 << value) != 0
+}
+
+/// Parses a cron field into the set of values it matches, as bits
+fn parse_field(field: &str, min: u32, max: u32) -> Result<u64, String> {
+    let invalid = || format!("invalid cron field '{}'", field);
+    let parse = |value: &str| value.parse::<u32>().map_err(|_| invalid());
+
+    let mut set = 0;
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (range, parse(step)?),
+            None => (part, 1),
+        };
+        let (start, end) = match range.split_once('-') {
+            _ if range == "*" => (min, max),
+            Some((start, end)) => (parse(start)?, parse(end)?),
+            // a single value with a step, e.g. `5/15`, runs from the value to the maximum
+            None if step > 1 => (parse(range)?, max),
+            None => (parse(range)?, parse(range)?),
+        };
+        if step == 0 || start < min || end > max || start > end {
+            return Err(invalid());
+        }
+        for value in (start..=end).step_by(step as usize) {
+            set |= 1 << value;
+        }
+    }
+    Ok(set)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(date: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(date, "%Y-%m-%d %H:%M").unwrap()
+    }
+
+    #[test]
+    fn parses_durations() {
+        let cases = [
+            ("500ms", Duration::from_millis(500)),
+            ("30s", Duration::from_secs(30)),
+            ("5m", Duration::from_secs(5 * 60)),
+            ("1h30m", Duration::from_secs(90 * 60)),
+            ("2d", Duration::from_secs(2 * 24 * 60 * 60)),
+            ("1m30s500ms", Duration::from_millis(90_500)),
+            (" 10s ", Duration::from_secs(10)),
+        ];
+        for (input, expected) in cases {
+            assert_eq!(parse_duration(input), Ok(expected), "{}", input);
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_durations() {
+        let cases = ["", "10", "s", "0s", "10x", "1.5h", "-5m", "5 m", "h30m"];
+        for input in cases {
+            assert!(parse_duration(input).is_err(), "{}", input);
+        }
+    }
+
+    #[test]
+    fn parses_cron_fields() {
+        let cases = [
+            ("*", 0, 5, 0b111111),
+            ("3", 0, 59, 1 << 3),
+            ("1-3", 0, 59, 0b1110),
+            ("1,4", 0, 59, 0b10010),
+            ("*/2", 0, 5, 0b010101),
+            ("1-5/2", 0, 59, 0b101010),
+            ("4/1", 0, 5, 1 << 4),
+            ("3/2", 0, 7, 0b10101000),
+        ];
+        for (field, min, max, expected) in cases {
+            assert_eq!(parse_field(field, min, max), Ok(expected), "{}", field);
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_cron_fields() {
+        let cases = ["", "x", "60", "*/0", "5-1", "1-", "1,,2", "-1"];
+        for field in cases {
+            assert!(parse_field(field, 0, 59).is_err(), "{}", field);
+        }
+        assert!(parse_field("0", 1, 31).is_err());
+    }
+
+    #[test]
+    fn finds_the_next_cron_run() {
+        // 2024-01-01 is a monday
+        let cases = [
+            ("* * * * *", "2024-01-01 10:00", "2024-01-01 10:01"),
+            ("*/15 * * * *", "2024-01-01 10:07", "2024-01-01 10:15"),
+            ("0 9 * * *", "2024-01-01 10:00", "2024-01-02 09:00"),
+            ("30 9-17 * * 1-5", "2024-01-05 17:30", "2024-01-08 09:30"),
+            ("0 0 1 * *", "2024-01-15 00:00", "2024-02-01 00:00"),
+            ("0 0 29 2 *", "2024-03-01 00:00", "2028-02-29 00:00"),
+            // sunday as 7
+            ("0 12 * * 7", "2024-01-01 00:00", "2024-01-07 12:00"),
+            // either the day of month or the weekday matching is enough
+            ("0 0 15 * 3", "2024-01-01 00:00", "2024-01-03 00:00"),
+        ];
+        for (schedule, time, expected) in cases {
+            let parsed: CronSchedule = schedule.parse().unwrap();
+            assert_eq!(
+                parsed.next_after(at(time)),
+                Some(at(expected)),
+                "{}",
+                schedule
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_cron_schedules() {
+        let cases = [
+            "",
+            "* * * *",
+            "* * * * * *",
+            "60 * * * *",
+            "* 24 * * *",
+            "0 0 31 2 *",
+        ];
+        for schedule in cases {
+            assert!(schedule.parse::<CronSchedule>().is_err(), "{}", schedule);
+        }
+    }
+}
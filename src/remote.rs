@@ -0,0 +1,40 @@
+/// The local command that runs `command` on `host` over ssh, in `cwd` on the host when given.
+///
+/// A terminal is forced on the host so that when the local ssh process is stopped, the
+/// connection closing hangs up the remote command rather than leaving it running.
+pub fn ssh_command(host: &str, command: &str, cwd: Option<&str>) -> String {
+    let remote = match cwd {
+        Some(cwd) => format!("cd {} || exit; {}", remote_path(cwd), command),
+        None => command.to_string(),
+    };
+    format!(
+        "ssh -tt -o BatchMode=yes {} -- {}",
+        local_quote(host),
+        local_quote(&remote)
+    )
+}
+
+/// Quotes a path for the host's shell, leaving a leading `~` to be expanded there
+pub(crate) fn remote_path(path: &str) -> String {
+    match path.strip_prefix("~/") {
+        Some(path) => format!("~/{}", posix_quote(path)),
+        None if path == "~" => path.to_string(),
+        None => posix_quote(path),
+    }
+}
+
+/// Quotes a value for a POSIX shell on another machine or in a container
+pub(crate) fn posix_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', r"'\''"))
+}
+
+/// Quotes a value for the local shell that together runs commands through
+#[cfg(not(windows))]
+pub(crate) fn local_quote(value: &str) -> String {
+    posix_quote(value)
+}
+
+#[cfg(windows)]
+pub(crate) fn local_quote(value: &str) -> String {
+    format!("\"{}\"", value.replace('"', "\\\""))
+}
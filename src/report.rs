@@ -0,0 +1,312 @@
+use std::{
+    collections::HashMap,
+    fmt::Write,
+    path::PathBuf,
+    time::{Duration, SystemTime},
+};
+
+use crate::{config::commands::CommandConfig, log, log_err, process::ProcessId, terminal};
+
+/// Format of a run report
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReportFormat {
+    /// JUnit XML, with a test case for each command, as read by most CI systems
+    Junit,
+    Markdown,
+}
+
+impl std::str::FromStr for ReportFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "junit" => Ok(Self::Junit),
+            "markdown" => Ok(Self::Markdown),
+            _ => Err(format!("unknown report format '{}'", s)),
+        }
+    }
+}
+
+/// File a run report is written to when the session ends, as JUnit XML for `.xml` files or
+/// Markdown for `.md` files
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct ReportTarget {
+    path: PathBuf,
+    format: ReportFormat,
+}
+
+impl std::str::FromStr for ReportTarget {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let path = PathBuf::from(s);
+        let format = match path.extension().and_then(|e| e.to_str()) {
+            Some("xml") => ReportFormat::Junit,
+            Some("md" | "markdown") => ReportFormat::Markdown,
+            _ => {
+                return Err(format!(
+                    "report '{}' should be a .xml file for JUnit XML or a .md file for Markdown",
+                    s
+                ))
+            }
+        };
+        Ok(Self { path, format })
+    }
+}
+
+impl TryFrom<String> for ReportTarget {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl From<ReportTarget> for String {
+    fn from(target: ReportTarget) -> Self {
+        target.path.display().to_string()
+    }
+}
+
+/// How a command's last process ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Outcome {
+    Running,
+    Passed,
+    Failed,
+    /// Killed by together, e.g. when quitting, which isn't a failure
+    Stopped,
+}
+
+impl std::fmt::Display for Outcome {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Outcome::Running => write!(f, "running"),
+            Outcome::Passed => write!(f, "passed"),
+            Outcome::Failed => write!(f, "failed"),
+            Outcome::Stopped => write!(f, "stopped"),
+        }
+    }
+}
+
+/// A command from when it was first started until its last process exited
+struct CommandRun {
+    command: String,
+    started_at: SystemTime,
+    finished_at: Option<SystemTime>,
+    restarts: u32,
+    exit_code: Option<i32>,
+    outcome: Outcome,
+}
+
+impl CommandRun {
+    fn duration(&self) -> Duration {
+        let end = self.finished_at.unwrap_or_else(SystemTime::now);
+        end.duration_since(self.started_at).unwrap_or_default()
+    }
+}
+
+/// Records each command the session runs, with how long it ran, how often it was restarted and
+/// how it exited, to export as a JUnit XML or Markdown report
+pub struct RunReport {
+    started_at: SystemTime,
+    runs: Vec<CommandRun>,
+    aliases: HashMap<String, String>,
+}
+
+impl RunReport {
+    pub fn new(commands: &[CommandConfig]) -> Self {
+        let aliases = commands
+            .iter()
+            .filter_map(|c| Some((c.as_str().to_string(), c.alias()?.to_string())))
+            .collect();
+        Self {
+            started_at: SystemTime::now(),
+            runs: vec![],
+            aliases,
+        }
+    }
+
+    pub fn spawned(&mut self, id: &ProcessId) {
+        match self.runs.iter_mut().find(|r| r.command == id.command()) {
+            Some(run) => {
+                run.finished_at = None;
+                run.exit_code = None;
+                run.outcome = Outcome::Running;
+            }
+            None => self.runs.push(CommandRun {
+                command: id.command().to_string(),
+                started_at: SystemTime::now(),
+                finished_at: None,
+                restarts: 0,
+                exit_code: None,
+                outcome: Outcome::Running,
+            }),
+        }
+    }
+
+    pub fn restarted(&mut self, command: &str) {
+        if let Some(run) = self.runs.iter_mut().find(|r| r.command == command) {
+            run.restarts += 1;
+        }
+    }
+
+    /// Records the exit of a process, `stopped` when together killed it
+    pub fn exited(&mut self, id: &ProcessId, status: i32, failed: bool, stopped: bool) {
+        let Some(run) = self.runs.iter_mut().find(|r| r.command == id.command()) else {
+            return;
+        };
+        run.finished_at = Some(SystemTime::now());
+        run.exit_code = Some(status);
+        run.outcome = match (failed, stopped) {
+            (false, _) => Outcome::Passed,
+            (true, true) => Outcome::Stopped,
+            (true, false) => Outcome::Failed,
+        };
+    }
+
+    pub fn render(&self, format: ReportFormat) -> String {
+        match format {
+            ReportFormat::Junit => self.junit(),
+            ReportFormat::Markdown => self.markdown(),
+        }
+    }
+
+    pub fn write(&self, target: &ReportTarget) {
+        match std::fs::write(&target.path, self.render(target.format)) {
+            Ok(()) => {
+                log!("Wrote run report to {}", target.path.display());
+            }
+            Err(e) => {
+                log_err!(
+                    "Failed to write run report to {}: {}",
+                    target.path.display(),
+                    e
+                );
+            }
+        }
+    }
+
+    fn name<'a>(&'a self, run: &'a CommandRun) -> &'a str {
+        self.aliases.get(&run.command).unwrap_or(&run.command)
+    }
+
+    fn count(&self, outcome: Outcome) -> usize {
+        self.runs.iter().filter(|r| r.outcome == outcome).count()
+    }
+
+    fn elapsed(&self) -> Duration {
+        self.started_at.elapsed().unwrap_or_default()
+    }
+
+    fn junit(&self) -> String {
+        let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+        let (tests, failures, skipped) = (
+            self.runs.len(),
+            self.count(Outcome::Failed),
+            self.count(Outcome::Running),
+        );
+        let time = self.elapsed().as_secs_f64();
+        _ = writeln!(
+            xml,
+            "<testsuites name=\"together\" tests=\"{}\" failures=\"{}\" time=\"{:.3}\">",
+            tests, failures, time
+        );
+        _ = writeln!(
+            xml,
+            "  <testsuite name=\"together\" tests=\"{}\" failures=\"{}\" errors=\"0\" skipped=\"{}\" time=\"{:.3}\" timestamp=\"{}\">",
+            tests,
+            failures,
+            skipped,
+            time,
+            rfc3339(self.started_at)
+        );
+        for run in &self.runs {
+            _ = writeln!(
+                xml,
+                "    <testcase name=\"{}\" classname=\"together\" time=\"{:.3}\">",
+                escape_xml(self.name(run)),
+                run.duration().as_secs_f64()
+            );
+            xml.push_str("      <properties>\n");
+            let exit_code = run.exit_code.map(|c| c.to_string());
+            let properties = [
+                ("command", Some(run.command.clone())),
+                ("started_at", Some(rfc3339(run.started_at))),
+                ("restarts", Some(run.restarts.to_string())),
+                ("exit_code", exit_code),
+                ("result", Some(run.outcome.to_string())),
+            ];
+            for (name, value) in properties {
+                if let Some(value) = value {
+                    _ = writeln!(
+                        xml,
+                        "        <property name=\"{}\" value=\"{}\"/>",
+                        name,
+                        escape_xml(&value)
+                    );
+                }
+            }
+            xml.push_str("      </properties>\n");
+            match (run.outcome, run.exit_code) {
+                (Outcome::Failed, Some(code)) => {
+                    _ = writeln!(
+                        xml,
+                        "      <failure message=\"exited with status {}\" type=\"exit\"/>",
+                        code
+                    );
+                }
+                (Outcome::Running, _) => {
+                    xml.push_str("      <skipped message=\"still running\"/>\n");
+                }
+                _ => {}
+            }
+            xml.push_str("    </testcase>\n");
+        }
+        xml.push_str("  </testsuite>\n</testsuites>\n");
+        xml
+    }
+
+    fn markdown(&self) -> String {
+        let mut md = String::from("# together run report\n\n");
+        _ = writeln!(
+            md,
+            "Started {}, ran for {}: {} passed, {} failed, {} stopped, {} running\n",
+            rfc3339(self.started_at),
+            terminal::format_duration(self.elapsed()),
+            self.count(Outcome::Passed),
+            self.count(Outcome::Failed),
+            self.count(Outcome::Stopped),
+            self.count(Outcome::Running),
+        );
+        md.push_str("| Command | Started | Duration | Restarts | Exit code | Result |\n");
+        md.push_str("| --- | --- | --- | --- | --- | --- |\n");
+        for run in &self.runs {
+            _ = writeln!(
+                md,
+                "| `{}` | {} | {} | {} | {} | {} |",
+                self.name(run).replace('|', "\\|").replace('`', "'"),
+                rfc3339(run.started_at),
+                terminal::format_duration(run.duration()),
+                run.restarts,
+                run.exit_code.map_or("-".to_string(), |c| c.to_string()),
+                run.outcome
+            );
+        }
+        md
+    }
+}
+
+fn rfc3339(time: SystemTime) -> String {
+    chrono::DateTime::<chrono::Local>::from(time)
+        .to_rfc3339_opts(chrono::SecondsFormat::Secs, false)
+}
+
+fn escape_xml(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
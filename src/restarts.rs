@@ -0,0 +1,118 @@
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    time::{Duration, Instant},
+};
+
+use serde::{Deserialize, Serialize};
+
+/// Restarts of a command allowed within `window` before it counts as crash-looping
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CrashLoopLimit {
+    pub max_restarts: usize,
+    /// e.g. `1m` or `30s`
+    #[serde(with = "window")]
+    pub window: Duration,
+}
+
+impl CrashLoopLimit {
+    pub fn validate(&self) -> Result<(), String> {
+        match self.max_restarts {
+            0 => Err("max_restarts has to be at least 1".to_string()),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl Default for CrashLoopLimit {
+    fn default() -> Self {
+        Self {
+            max_restarts: 5,
+            window: Duration::from_secs(60),
+        }
+    }
+}
+
+/// The window is written like the other durations of the configuration file, e.g. `90s`
+mod window {
+    use std::time::Duration;
+
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(window: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&format!("{}s", window.as_secs()))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+        let window = String::deserialize(deserializer)?;
+        crate::recurring::parse_duration(&window).map_err(D::Error::custom)
+    }
+}
+
+/// When each command was restarted, by hand or automatically, to notice commands that keep
+/// being restarted and stop restarting them automatically
+pub struct RestartHistory {
+    limit: CrashLoopLimit,
+    restarts: HashMap<String, VecDeque<Instant>>,
+    crash_looping: HashSet<String>,
+}
+
+impl RestartHistory {
+    pub fn new(limit: Option<&CrashLoopLimit>) -> Self {
+        Self {
+            limit: limit.cloned().unwrap_or_default(),
+            restarts: HashMap::new(),
+            crash_looping: HashSet::new(),
+        }
+    }
+
+    /// Records a restart of the command, returning how many restarts there were within the
+    /// window if this one made it crash-looping
+    pub fn record(&mut self, command: &str) -> Option<usize> {
+        let now = Instant::now();
+        let restarts = self.restarts.entry(command.to_string()).or_default();
+        while restarts
+            .front()
+            .is_some_and(|at| now.duration_since(*at) > self.limit.window)
+        {
+            restarts.pop_front();
+        }
+        restarts.push_back(now);
+        let crash_looping = restarts.len() > self.limit.max_restarts;
+        match crash_looping && self.crash_looping.insert(command.to_string()) {
+            true => Some(restarts.len()),
+            false => None,
+        }
+    }
+
+    /// How many times the command was restarted within the window
+    pub fn recent(&self, command: &str) -> usize {
+        let now = Instant::now();
+        self.restarts.get(command).map_or(0, |restarts| {
+            restarts
+                .iter()
+                .filter(|at| now.duration_since(**at) <= self.limit.window)
+                .count()
+        })
+    }
+
+    pub fn is_crash_looping(&self, command: &str) -> bool {
+        self.crash_looping.contains(command)
+    }
+
+    /// The crash-looping commands, sorted
+    pub fn crash_looping(&self) -> Vec<&str> {
+        let mut crash_looping: Vec<_> = self.crash_looping.iter().map(|c| c.as_str()).collect();
+        crash_looping.sort();
+        crash_looping
+    }
+
+    /// Forgets the restarts of a command, e.g. once it is started again by hand
+    pub fn reset(&mut self, command: &str) {
+        self.restarts.remove(command);
+        self.crash_looping.remove(command);
+    }
+
+    pub fn window(&self) -> Duration {
+        self.limit.window
+    }
+}
@@ -0,0 +1,85 @@
+use crate::errors::{ConfigError, TogetherResult};
+
+/// Prefix of values read from the OS keychain, followed by the name of the secret
+const KEYCHAIN_PREFIX: &str = "secret://keychain/";
+/// Prefix of 1Password secret references, read with the `op` CLI
+const ONE_PASSWORD_PREFIX: &str = "op://";
+
+/// Whether an `env` value refers to a secret, rather than being the value itself
+pub fn is_reference(value: &str) -> bool {
+    value.starts_with(KEYCHAIN_PREFIX) || value.starts_with(ONE_PASSWORD_PREFIX)
+}
+
+/// Replaces secret references among the values of environment variables with the secrets,
+/// read each time a command is started so they are never stored in the configuration file
+pub fn resolve_env(env: Vec<(String, String)>) -> TogetherResult<Vec<(String, String)>> {
+    env.into_iter()
+        .map(|(name, value)| match is_reference(&value) {
+            false => Ok((name, value)),
+            true => match resolve(&value) {
+                Ok(secret) => Ok((name, secret)),
+                Err(e) => Err(ConfigError::new(format!(
+                    "Failed to read {} from '{}': {}",
+                    name, value, e
+                ))
+                .into()),
+            },
+        })
+        .collect()
+}
+
+fn resolve(reference: &str) -> Result<String, String> {
+    let argv = match reference.strip_prefix(KEYCHAIN_PREFIX) {
+        Some("") => return Err("missing the name of the secret".to_string()),
+        Some(name) => keychain_argv(name),
+        None => vec!["op".to_string(), "read".to_string(), reference.to_string()],
+    };
+    let output = std::process::Command::new(&argv[0])
+        .args(&argv[1..])
+        .stdin(std::process::Stdio::null())
+        .output()
+        .map_err(|e| format!("failed to run {}: {}", argv[0], e))?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(match stderr.trim() {
+            "" => format!("{} exited with {}", argv[0], output.status),
+            stderr => stderr.to_string(),
+        });
+    }
+    let secret = String::from_utf8(output.stdout)
+        .map_err(|_| "the secret is not valid UTF-8".to_string())?;
+    Ok(secret.trim_end_matches(['\r', '\n']).to_string())
+}
+
+/// Reads a generic password by its service name from the login keychain
+#[cfg(target_os = "macos")]
+fn keychain_argv(name: &str) -> Vec<String> {
+    ["security", "find-generic-password", "-w", "-s", name]
+        .map(str::to_string)
+        .to_vec()
+}
+
+/// Looks up a secret stored with `secret-tool store --label=<label> service <name>`
+#[cfg(all(unix, not(target_os = "macos")))]
+fn keychain_argv(name: &str) -> Vec<String> {
+    ["secret-tool", "lookup", "service", name]
+        .map(str::to_string)
+        .to_vec()
+}
+
+/// Reads a credential by its resource name from the Windows credential locker
+#[cfg(windows)]
+fn keychain_argv(name: &str) -> Vec<String> {
+    let script = format!(
+        "[void][Windows.Security.Credentials.PasswordVault,Windows.Security.Credentials,ContentType=WindowsRuntime]; \
+         $c = (New-Object Windows.Security.Credentials.PasswordVault).FindAllByResource('{}')[0]; \
+         $c.RetrievePassword(); $c.Password",
+        name.replace('\'', "''")
+    );
+    vec![
+        "powershell".to_string(),
+        "-NoProfile".to_string(),
+        "-Command".to_string(),
+        script,
+    ]
+}
@@ -0,0 +1,165 @@
+use crate::{
+    config::{commands::CommandConfig, TogetherConfigFile},
+    errors::{ConfigError, TogetherResult},
+    log_sink::{ForwardTarget, LogSinkKind},
+    manager::{ProcessManager, ProcessManagerHandle},
+    output::{OutputFilter, OutputMode},
+    ports::PortsMode,
+    supervisor::SupervisorOptions,
+    telemetry::OtlpEndpoint,
+    timestamp::{self, TimestampFormat},
+};
+
+/// Runs commands together from another program. Unlike the `together` binary, a session never
+/// prompts, installs no signal handlers and never exits the process: processes are spawned,
+/// killed and waited on through the `ProcessManagerHandle` returned by `start`, which kills
+/// any remaining processes when dropped.
+pub struct Session {
+    manager: ProcessManager,
+    timestamp_format: Option<TimestampFormat>,
+}
+
+impl Default for Session {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Session {
+    pub fn new() -> Self {
+        Self {
+            manager: ProcessManager::new()
+                .with_quit_on_completion(false)
+                .with_process_exit(false),
+            timestamp_format: None,
+        }
+    }
+
+    /// A session using the settings of a configuration file, e.g. from `config::load_from`.
+    /// Its commands aren't started until they are spawned.
+    pub fn from_config(config: &TogetherConfigFile) -> TogetherResult<Self> {
+        let options = &config.start_options;
+        let timestamp_format =
+            TimestampFormat::new(options.timestamp_format.as_deref(), options.timestamp_utc)
+                .map_err(|e| ConfigError::new(format!("Invalid timestamp format: {}", e)))?;
+        Ok(Self::new()
+            .with_raw_mode(options.raw)
+            .with_exit_on_error(options.exit_on_error)
+            .with_commands(options.commands.clone())
+            .with_pty(options.pty)
+            .with_spawn_retries(options.spawn_retries.unwrap_or_default())
+            .with_output_mode(options.output_mode)
+            .with_log_sink(options.log_sink)
+            .with_log_forward(options.log_forward.clone())
+            .with_otlp_endpoint(options.otlp_endpoint.clone())
+            .with_ports(options.ports)
+            .with_hyperlink_format(options.hyperlink_format.clone())
+            .with_timestamps(options.timestamps)
+            .with_timestamp_format(timestamp_format))
+    }
+
+    pub fn with_raw_mode(mut self, raw_mode: bool) -> Self {
+        self.manager = self.manager.with_raw_mode(raw_mode);
+        self
+    }
+
+    pub fn with_exit_on_error(mut self, exit_on_error: bool) -> Self {
+        self.manager = self.manager.with_exit_on_error(exit_on_error);
+        self
+    }
+
+    /// Stop the session once all of its processes have exited, after which the handle
+    /// returns errors
+    pub fn with_quit_on_completion(mut self, quit_on_completion: bool) -> Self {
+        self.manager = self.manager.with_quit_on_completion(quit_on_completion);
+        self
+    }
+
+    pub fn with_working_directory(mut self, working_directory: Option<String>) -> Self {
+        self.manager = self.manager.with_working_directory(working_directory);
+        self
+    }
+
+    /// Configured commands, used to look up per-command settings when they are spawned
+    pub fn with_commands(mut self, commands: Vec<CommandConfig>) -> Self {
+        self.manager = self.manager.with_commands(commands);
+        self
+    }
+
+    pub fn with_pty(mut self, pty: bool) -> Self {
+        self.manager = self.manager.with_pty(pty);
+        self
+    }
+
+    /// Retry starting a process this many times if it fails to start
+    pub fn with_spawn_retries(mut self, spawn_retries: u32) -> Self {
+        self.manager = self.manager.with_spawn_retries(spawn_retries);
+        self
+    }
+
+    pub fn with_output_mode(mut self, output_mode: OutputMode) -> Self {
+        self.manager = self.manager.with_output_mode(output_mode);
+        self
+    }
+
+    /// Forward process output and lifecycle events to syslog or journald
+    pub fn with_log_sink(mut self, log_sink: Option<LogSinkKind>) -> Self {
+        self.manager = self.manager.with_log_sink(log_sink);
+        self
+    }
+
+    /// Stream process output as ndjson to a remote collector
+    pub fn with_log_forward(mut self, log_forward: Option<ForwardTarget>) -> Self {
+        self.manager = self.manager.with_log_forward(log_forward);
+        self
+    }
+
+    /// Export a span for each process, from when it is spawned until it exits, to an OTLP
+    /// collector
+    pub fn with_otlp_endpoint(mut self, otlp_endpoint: Option<OtlpEndpoint>) -> Self {
+        self.manager = self.manager.with_otlp_endpoint(otlp_endpoint);
+        self
+    }
+
+    /// Pick a port for each configured command when starting, passed to all of them through
+    /// the environment
+    pub fn with_ports(mut self, ports_mode: Option<PortsMode>) -> Self {
+        self.manager = self.manager.with_ports(ports_mode);
+        self
+    }
+
+    /// Link file references in output with this format instead of `file://` URLs, filling
+    /// in `{path}`, `{line}` and `{column}`
+    pub fn with_hyperlink_format(mut self, hyperlink_format: Option<String>) -> Self {
+        self.manager = self.manager.with_hyperlink_format(hyperlink_format);
+        self
+    }
+
+    pub fn with_timestamps(mut self, timestamps: bool) -> Self {
+        self.manager = self.manager.with_timestamps(timestamps);
+        self
+    }
+
+    /// The timestamp format is shared by the whole program, only the first one set is used
+    pub fn with_timestamp_format(mut self, timestamp_format: TimestampFormat) -> Self {
+        self.timestamp_format = Some(timestamp_format);
+        self
+    }
+
+    pub fn with_output_filter(mut self, output_filter: Option<OutputFilter>) -> Self {
+        self.manager = self.manager.with_output_filter(output_filter);
+        self
+    }
+
+    pub fn with_supervisor(mut self, options: Option<SupervisorOptions>) -> Self {
+        self.manager = self.manager.with_supervisor(options);
+        self
+    }
+
+    pub fn start(self) -> ProcessManagerHandle {
+        if let Some(timestamp_format) = self.timestamp_format {
+            timestamp::init(timestamp_format);
+        }
+        self.manager.start()
+    }
+}
@@ -0,0 +1,179 @@
+use std::{
+    io::Write,
+    path::PathBuf,
+    time::{Duration, Instant, SystemTime},
+};
+
+use crate::{
+    errors::TogetherResult,
+    log, log_err,
+    manager::{CreateOptions, ProcessState, ProcessStatus},
+    process::ProcessId,
+    restarts::RestartHistory,
+    terminal, timestamp,
+};
+
+#[derive(Debug, Clone)]
+pub struct SupervisorOptions {
+    pub max_backoff: Duration,
+    pub heartbeat_interval: Duration,
+    pub heartbeat_log: PathBuf,
+}
+
+impl SupervisorOptions {
+    pub fn new(heartbeat_log: Option<PathBuf>) -> Self {
+        Self {
+            max_backoff: Duration::from_secs(60),
+            heartbeat_interval: Duration::from_secs(60),
+            heartbeat_log: heartbeat_log.unwrap_or_else(default_heartbeat_log),
+        }
+    }
+}
+
+fn default_heartbeat_log() -> PathBuf {
+    dirs::state_dir()
+        .or_else(dirs::data_local_dir)
+        .unwrap_or_else(std::env::temp_dir)
+        .join("together")
+        .join("heartbeat.log")
+}
+
+struct PendingRestart {
+    at: Instant,
+    command: String,
+    options: CreateOptions,
+}
+
+/// Restarts crashed processes with exponential backoff, stops restarting crash-looping
+/// commands, and periodically appends the session state to a heartbeat log. Restarts are
+/// tracked in the `RestartHistory` of the manager, like those of other restart policies.
+pub struct Supervisor {
+    options: SupervisorOptions,
+    pending: Vec<PendingRestart>,
+    restarts: usize,
+    last_heartbeat: Option<Instant>,
+}
+
+impl Supervisor {
+    pub fn new(options: SupervisorOptions) -> Self {
+        log!(
+            "Supervising processes, writing heartbeat to {:?}",
+            options.heartbeat_log
+        );
+        Self {
+            options,
+            pending: vec![],
+            restarts: 0,
+            last_heartbeat: None,
+        }
+    }
+
+    pub fn on_crash(
+        &mut self,
+        id: &ProcessId,
+        status: i32,
+        options: CreateOptions,
+        history: &RestartHistory,
+    ) {
+        let command = id.command().to_string();
+        if history.is_crash_looping(&command) {
+            let message = format!(
+                "{} is crash-looping ({} restarts in {}), not restarting",
+                id,
+                history.recent(&command),
+                terminal::format_duration(history.window())
+            );
+            log_err!("{}", message);
+            self.write_heartbeat_line(&message);
+            return;
+        }
+
+        let exponent = history.recent(&command).min(16) as u32;
+        let backoff = Duration::from_secs(1)
+            .saturating_mul(2u32.pow(exponent))
+            .min(self.options.max_backoff);
+        log_err!(
+            "{} crashed with status {}, restarting in {}",
+            id,
+            status,
+            terminal::format_duration(backoff)
+        );
+        self.write_heartbeat_line(&format!("{} crashed with status {}", id, status));
+        self.pending.push(PendingRestart {
+            at: Instant::now() + backoff,
+            command,
+            options,
+        });
+    }
+
+    pub fn has_pending(&self) -> bool {
+        !self.pending.is_empty()
+    }
+
+    /// When there is next a restart or heartbeat due
+    pub fn next_due(&self) -> Instant {
+        let heartbeat = match self.last_heartbeat {
+            Some(last_heartbeat) => last_heartbeat + self.options.heartbeat_interval,
+            None => Instant::now(),
+        };
+        self.pending
+            .iter()
+            .map(|r| r.at)
+            .fold(heartbeat, Instant::min)
+    }
+
+    pub fn take_due_restarts(&mut self) -> Vec<(String, CreateOptions)> {
+        let now = Instant::now();
+        let (due, pending): (Vec<_>, Vec<_>) = std::mem::take(&mut self.pending)
+            .into_iter()
+            .partition(|r| r.at <= now);
+        self.pending = pending;
+        self.restarts += due.len();
+        due.into_iter().map(|r| (r.command, r.options)).collect()
+    }
+
+    pub fn heartbeat(&mut self, statuses: &[ProcessStatus], history: &RestartHistory) {
+        if let Some(last_heartbeat) = self.last_heartbeat {
+            if last_heartbeat.elapsed() < self.options.heartbeat_interval {
+                return;
+            }
+        }
+        self.last_heartbeat = Some(Instant::now());
+
+        let count = |f: fn(&ProcessState) -> bool| statuses.iter().filter(|s| f(&s.state)).count();
+        let running = count(|s| *s == ProcessState::Running);
+        let exited = count(|s| matches!(s, ProcessState::Exited(_)));
+        let mut line = format!(
+            "heartbeat: running={} exited={} restarts={} pending_restarts={}",
+            running,
+            exited,
+            self.restarts,
+            self.pending.len()
+        );
+        let crash_looping = history.crash_looping();
+        if !crash_looping.is_empty() {
+            line.push_str(&format!(" crash_looping={:?}", crash_looping));
+        }
+        self.write_heartbeat_line(&line);
+    }
+
+    fn write_heartbeat_line(&self, line: &str) {
+        if let Err(e) = self.try_write_heartbeat_line(line) {
+            log_err!("Failed to write heartbeat log: {}", e);
+        }
+    }
+
+    fn try_write_heartbeat_line(&self, line: &str) -> TogetherResult<()> {
+        let path = &self.options.heartbeat_log;
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let mut file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)?;
+        let timestamp = timestamp::format(SystemTime::now());
+        writeln!(file, "{} {}", timestamp, line)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,430 @@
+use std::{
+    collections::HashMap,
+    hash::{BuildHasher, Hasher},
+    sync::mpsc,
+    thread::JoinHandle,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+#[cfg(feature = "otlp")]
+use std::{
+    io::{Read, Write},
+    net::{TcpStream, ToSocketAddrs},
+    time::Duration,
+};
+
+use crate::{config::commands::CommandConfig, log_err, process::ProcessId};
+
+/// Most spans exported in one request
+#[cfg(feature = "otlp")]
+const BATCH_SIZE: usize = 100;
+
+/// How long finished spans are held back to be exported together
+#[cfg(feature = "otlp")]
+const BATCH_DELAY: Duration = Duration::from_secs(2);
+
+/// Longest a request to the collector can take before the batch is dropped
+#[cfg(feature = "otlp")]
+const EXPORT_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// An OTLP collector accepting traces over HTTP, as `http://host:port`, e.g. the default
+/// `http://localhost:4318`. Spans are posted to `/v1/traces` unless the endpoint has a path.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct OtlpEndpoint {
+    host: String,
+    port: u16,
+    path: String,
+}
+
+impl std::str::FromStr for OtlpEndpoint {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || {
+            format!(
+                "OTLP endpoint '{}' should be 'http://host:port', e.g. 'http://localhost:4318'",
+                s
+            )
+        };
+        let address = s.strip_prefix("http://").ok_or_else(invalid)?;
+        let (authority, path) = match address.find('/') {
+            Some(slash) if address[slash..].len() > 1 => address.split_at(slash),
+            Some(slash) => (&address[..slash], "/v1/traces"),
+            None => (address, "/v1/traces"),
+        };
+        let (host, port) = match authority.rsplit_once(':') {
+            Some((host, port)) => (host, port.parse().map_err(|_| invalid())?),
+            None => (authority, 4318),
+        };
+        if host.is_empty() {
+            return Err(invalid());
+        }
+        Ok(Self {
+            host: host.to_string(),
+            port,
+            path: path.to_string(),
+        })
+    }
+}
+
+impl TryFrom<String> for OtlpEndpoint {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl From<OtlpEndpoint> for String {
+    fn from(endpoint: OtlpEndpoint) -> Self {
+        endpoint.to_string()
+    }
+}
+
+impl std::fmt::Display for OtlpEndpoint {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "http://{}:{}{}", self.host, self.port, self.path)
+    }
+}
+
+/// A process that has started and not yet exited
+struct OpenSpan {
+    span_id: u64,
+    started_at: SystemTime,
+    restarts: u32,
+}
+
+/// Records a span for each process from when it is spawned until it exits, exported to an
+/// OTLP collector from a separate thread. All spans of a session share one trace.
+pub struct Tracer {
+    trace_id: u128,
+    spans: HashMap<ProcessId, OpenSpan>,
+    restarts: HashMap<String, u32>,
+    aliases: HashMap<String, String>,
+    sender: Option<mpsc::Sender<serde_json::Value>>,
+    exporter: Option<JoinHandle<()>>,
+}
+
+impl Tracer {
+    pub fn start(endpoint: OtlpEndpoint, commands: &[CommandConfig]) -> Self {
+        let (sender, receiver) = mpsc::channel();
+        let exporter = start_exporter(endpoint, receiver);
+        let aliases = commands
+            .iter()
+            .filter_map(|c| Some((c.as_str().to_string(), c.alias()?.to_string())))
+            .collect();
+        Self {
+            trace_id: (random_id() as u128) << 64 | random_id() as u128,
+            spans: HashMap::new(),
+            restarts: HashMap::new(),
+            aliases,
+            sender: Some(sender),
+            exporter,
+        }
+    }
+
+    pub fn spawned(&mut self, id: &ProcessId) {
+        let span = OpenSpan {
+            span_id: random_id(),
+            started_at: SystemTime::now(),
+            restarts: self.restarts.get(id.command()).copied().unwrap_or_default(),
+        };
+        self.spans.insert(id.clone(), span);
+    }
+
+    /// Counts a restart of the command, recorded on the spans of its later processes
+    pub fn restarted(&mut self, command: &str) {
+        *self.restarts.entry(command.to_string()).or_default() += 1;
+    }
+
+    pub fn exited(&mut self, id: &ProcessId, status: i32, failed: bool) {
+        let Some(span) = self.spans.remove(id) else {
+            return;
+        };
+        let span = self.encode(id, &span, SystemTime::now(), status, failed);
+        if let Some(sender) = &self.sender {
+            _ = sender.send(span);
+        }
+    }
+
+    /// The span of a process in OTLP's JSON encoding
+    fn encode(
+        &self,
+        id: &ProcessId,
+        span: &OpenSpan,
+        ended_at: SystemTime,
+        status: i32,
+        failed: bool,
+    ) -> serde_json::Value {
+        let alias = self.aliases.get(id.command());
+        let mut attributes = vec![
+            attribute("together.command", string(id.command())),
+            attribute("together.process", int(id.index() as i64)),
+            attribute("together.restarts", int(span.restarts as i64)),
+            attribute("process.exit.code", int(status as i64)),
+        ];
+        if let Some(alias) = alias {
+            attributes.push(attribute("together.alias", string(alias)));
+        }
+        serde_json::json!({
+            "traceId": format!("{:032x}", self.trace_id),
+            "spanId": format!("{:016x}", span.span_id),
+            "name": alias.map_or(id.command(), |a| a.as_str()),
+            // SPAN_KIND_INTERNAL
+            "kind": 1,
+            "startTimeUnixNano": unix_nanos(span.started_at),
+            "endTimeUnixNano": unix_nanos(ended_at),
+            "attributes": attributes,
+            // STATUS_CODE_OK or STATUS_CODE_ERROR
+            "status": { "code": if failed { 2 } else { 1 } },
+        })
+    }
+
+    /// Exports the remaining finished spans, waiting for the exporter to finish
+    pub fn flush(&mut self) {
+        self.sender.take();
+        if let Some(exporter) = self.exporter.take() {
+            _ = exporter.join();
+        }
+    }
+}
+
+#[cfg(feature = "otlp")]
+fn start_exporter(
+    endpoint: OtlpEndpoint,
+    receiver: mpsc::Receiver<serde_json::Value>,
+) -> Option<JoinHandle<()>> {
+    Some(std::thread::spawn(move || export_loop(endpoint, receiver)))
+}
+
+#[cfg(not(feature = "otlp"))]
+fn start_exporter(
+    endpoint: OtlpEndpoint,
+    _receiver: mpsc::Receiver<serde_json::Value>,
+) -> Option<JoinHandle<()>> {
+    log_err!(
+        "Spans won't be exported to {}, together was built without the 'otlp' feature",
+        endpoint
+    );
+    None
+}
+
+#[cfg(feature = "otlp")]
+fn export_loop(endpoint: OtlpEndpoint, receiver: mpsc::Receiver<serde_json::Value>) {
+    let mut failing = false;
+    let mut export = |spans: Vec<serde_json::Value>| match post(&endpoint, &spans) {
+        Ok(()) => failing = false,
+        // only report the first failure until the collector is back
+        Err(e) if !failing => {
+            log_err!("Failed to export spans to {}: {}", endpoint, e);
+            failing = true;
+        }
+        Err(_) => {}
+    };
+    while let Ok(span) = receiver.recv() {
+        let mut spans = vec![span];
+        let deadline = std::time::Instant::now() + BATCH_DELAY;
+        while spans.len() < BATCH_SIZE {
+            let timeout = deadline.saturating_duration_since(std::time::Instant::now());
+            match receiver.recv_timeout(timeout) {
+                Ok(span) => spans.push(span),
+                Err(mpsc::RecvTimeoutError::Timeout) => break,
+                Err(mpsc::RecvTimeoutError::Disconnected) => {
+                    export(spans);
+                    return;
+                }
+            }
+        }
+        export(spans);
+    }
+}
+
+#[cfg(feature = "otlp")]
+fn post(endpoint: &OtlpEndpoint, spans: &[serde_json::Value]) -> std::io::Result<()> {
+    let body = serde_json::json!({
+        "resourceSpans": [{
+            "resource": {
+                "attributes": [attribute("service.name", string("together"))],
+            },
+            "scopeSpans": [{
+                "scope": { "name": "together", "version": env!("CARGO_PKG_VERSION") },
+                "spans": spans,
+            }],
+        }],
+    })
+    .to_string();
+
+    let address = (endpoint.host.as_str(), endpoint.port)
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::NotFound, "no address"))?;
+    let mut stream = TcpStream::connect_timeout(&address, EXPORT_TIMEOUT)?;
+    stream.set_read_timeout(Some(EXPORT_TIMEOUT))?;
+    stream.set_write_timeout(Some(EXPORT_TIMEOUT))?;
+    write!(
+        stream,
+        "POST {} HTTP/1.1\r\nHost: {}:{}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        endpoint.path,
+        endpoint.host,
+        endpoint.port,
+        body.len(),
+        body
+    )?;
+
+    let mut response = String::new();
+    _ = stream.read_to_string(&mut response);
+    let status = response.split_whitespace().nth(1).unwrap_or_default();
+    match status.starts_with('2') {
+        true => Ok(()),
+        false => Err(std::io::Error::other(format!(
+            "unexpected response '{}'",
+            response.lines().next().unwrap_or_default()
+        ))),
+    }
+}
+
+fn attribute(key: &str, value: serde_json::Value) -> serde_json::Value {
+    serde_json::json!({ "key": key, "value": value })
+}
+
+fn string(value: &str) -> serde_json::Value {
+    serde_json::json!({ "stringValue": value })
+}
+
+/// 64 bit integers are strings in OTLP's JSON encoding
+fn int(value: i64) -> serde_json::Value {
+    serde_json::json!({ "intValue": value.to_string() })
+}
+
+fn unix_nanos(time: SystemTime) -> String {
+    let nanos = time
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos();
+    nanos.to_string()
+}
+
+/// A random id for traces and spans, from the random keys std seeds hash maps with
+fn random_id() -> u64 {
+    let mut hasher = std::collections::hash_map::RandomState::new().build_hasher();
+    hasher.write_u128(
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos(),
+    );
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_otlp_endpoints() {
+        let cases = [
+            ("http://localhost:4318", "localhost", 4318, "/v1/traces"),
+            ("http://localhost", "localhost", 4318, "/v1/traces"),
+            ("http://collector:9000/", "collector", 9000, "/v1/traces"),
+            (
+                "http://10.0.0.1:4318/otlp/traces",
+                "10.0.0.1",
+                4318,
+                "/otlp/traces",
+            ),
+        ];
+        for (input, host, port, path) in cases {
+            let endpoint: OtlpEndpoint = input.parse().unwrap();
+            assert_eq!(endpoint.host, host, "{}", input);
+            assert_eq!(endpoint.port, port, "{}", input);
+            assert_eq!(endpoint.path, path, "{}", input);
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_otlp_endpoints() {
+        let cases = [
+            "localhost:4318",
+            "https://localhost:4318",
+            "http://",
+            "http://:4318",
+            "http://localhost:port",
+            "http://localhost:70000",
+        ];
+        for input in cases {
+            assert!(input.parse::<OtlpEndpoint>().is_err(), "{}", input);
+        }
+    }
+
+    #[test]
+    fn displays_otlp_endpoints_with_their_path() {
+        let endpoint: OtlpEndpoint = "http://localhost".parse().unwrap();
+        assert_eq!(endpoint.to_string(), "http://localhost:4318/v1/traces");
+    }
+
+    fn tracer(commands: &[CommandConfig]) -> Tracer {
+        Tracer {
+            trace_id: 0xabc,
+            spans: HashMap::new(),
+            restarts: HashMap::new(),
+            aliases: commands
+                .iter()
+                .filter_map(|c| Some((c.as_str().to_string(), c.alias()?.to_string())))
+                .collect(),
+            sender: None,
+            exporter: None,
+        }
+    }
+
+    #[test]
+    fn encodes_spans_as_otlp_json() {
+        let tracer = tracer(&[]);
+        let id = ProcessId::new(3, "cargo run".to_string());
+        let span = OpenSpan {
+            span_id: 0x12,
+            started_at: UNIX_EPOCH + std::time::Duration::from_secs(1),
+            restarts: 2,
+        };
+        let ended_at = UNIX_EPOCH + std::time::Duration::from_secs(5);
+        let encoded = tracer.encode(&id, &span, ended_at, 101, true);
+        assert_eq!(
+            encoded,
+            serde_json::json!({
+                "traceId": "00000000000000000000000000000abc",
+                "spanId": "0000000000000012",
+                "name": "cargo run",
+                "kind": 1,
+                "startTimeUnixNano": "1000000000",
+                "endTimeUnixNano": "5000000000",
+                "attributes": [
+                    { "key": "together.command", "value": { "stringValue": "cargo run" } },
+                    { "key": "together.process", "value": { "intValue": "3" } },
+                    { "key": "together.restarts", "value": { "intValue": "2" } },
+                    { "key": "process.exit.code", "value": { "intValue": "101" } },
+                ],
+                "status": { "code": 2 },
+            })
+        );
+    }
+
+    #[test]
+    fn names_spans_after_aliases() {
+        let command: CommandConfig =
+            serde_json::from_value(serde_json::json!({ "command": "cargo run", "alias": "api" }))
+                .unwrap();
+        let tracer = tracer(&[command]);
+        let id = ProcessId::new(0, "cargo run".to_string());
+        let span = OpenSpan {
+            span_id: 1,
+            started_at: UNIX_EPOCH,
+            restarts: 0,
+        };
+        let encoded = tracer.encode(&id, &span, UNIX_EPOCH, 0, false);
+        assert_eq!(encoded["name"], "api");
+        assert_eq!(encoded["status"]["code"], 1);
+        let alias =
+            serde_json::json!({ "key": "together.alias", "value": { "stringValue": "api" } });
+        assert!(encoded["attributes"].as_array().unwrap().contains(&alias));
+    }
+}
@@ -1,5 +1,7 @@
 use dialoguer::{theme::ColorfulTheme, MultiSelect};
 
+use crate::errors::{TogetherError, TogetherResult};
+
 #[derive(Debug, clap::Parser)]
 #[clap(
     name = "together",
@@ -16,7 +18,11 @@ pub struct TogetherArgs {
     #[clap(short, long = "cwd", help = "Directory to run commands in.")]
     pub working_directory: Option<String>,
 
-    #[clap(short, long, help = "Only run the startup commands.")]
+    #[clap(
+        short,
+        long,
+        help = "Only run the startup commands, then exit (non-zero if any failed)."
+    )]
     pub init_only: bool,
 
     #[clap(
@@ -36,6 +42,115 @@ pub struct TogetherArgs {
         value_delimiter = ','
     )]
     pub recipes: Option<Vec<String>>,
+
+    #[clap(
+        long,
+        help = "Leave out commands tagged under provided recipe(s), running all other commands if no recipes are given. Use comma to separate multiple recipes.",
+        value_delimiter = ','
+    )]
+    pub exclude_recipes: Option<Vec<String>>,
+
+    #[clap(
+        long,
+        conflicts_with = "recipes",
+        help = "Run only the provided command(s) by alias, command or index, without prompting. Use comma to separate multiple commands.",
+        value_delimiter = ','
+    )]
+    pub only: Option<Vec<String>>,
+
+    #[clap(
+        long,
+        help = "Only show output lines matching the provided regular expression."
+    )]
+    pub grep: Option<String>,
+
+    #[clap(
+        long,
+        help = "Restart crashed commands with backoff, stop restarting crash-looping commands, and write a heartbeat log."
+    )]
+    pub supervise: bool,
+
+    #[clap(
+        long,
+        requires = "supervise",
+        help = "Path of the heartbeat log written in supervise mode."
+    )]
+    pub heartbeat_log: Option<std::path::PathBuf>,
+
+    #[clap(
+        long,
+        conflicts_with = "grep",
+        help = "Highlight matches of the provided regular expression in the output."
+    )]
+    pub highlight: Option<String>,
+
+    #[clap(long, help = "Prefix each output line with a timestamp.")]
+    pub timestamps: bool,
+
+    #[clap(
+        long,
+        help = "strftime format of timestamps in output and logs, e.g. '%H:%M:%S%.3f'. Defaults to RFC 3339."
+    )]
+    pub timestamp_format: Option<String>,
+
+    #[clap(long, help = "Write timestamps in UTC instead of local time.")]
+    pub utc: bool,
+
+    #[clap(
+        long,
+        default_value = "auto",
+        help = "When to color output: 'always', 'never' or 'auto', which colors output written to a terminal."
+    )]
+    pub color: ColorChoice,
+
+    #[clap(
+        long,
+        help = "Write a report of how each command ran when together exits, as JUnit XML to a .xml file or Markdown to a .md file."
+    )]
+    pub report: Option<crate::report::ReportTarget>,
+
+    #[clap(
+        long,
+        value_delimiter = ',',
+        help = "If together is already running in this directory, restart these commands there by alias instead of starting another session."
+    )]
+    pub restart_on_start: Option<Vec<String>>,
+
+    #[clap(
+        short,
+        long,
+        action = clap::ArgAction::Count,
+        help = "Write what together does internally to a debug log, -vv for every event handled."
+    )]
+    pub verbose: u8,
+
+    #[clap(
+        long,
+        help = "Path of the debug log, instead of together.log in the user's state directory, e.g. ~/.local/state/together."
+    )]
+    pub debug_log: Option<std::path::PathBuf>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ColorChoice {
+    Always,
+    /// Color output written to a terminal, but not when it is piped or redirected
+    #[default]
+    Auto,
+    Never,
+}
+
+impl std::str::FromStr for ColorChoice {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "always" => Ok(Self::Always),
+            "auto" => Ok(Self::Auto),
+            "never" => Ok(Self::Never),
+            _ => Err(format!("unknown color choice '{}'", s)),
+        }
+    }
 }
 
 #[derive(Debug, clap::Parser)]
@@ -51,6 +166,45 @@ pub enum ArgsCommands {
 
     #[clap(name = "load", about = "Run commands from a configuration file.")]
     Load(LoadCommand),
+
+    #[clap(name = "add", about = "Add a command to the configuration file.")]
+    Add(AddCommand),
+
+    #[clap(
+        name = "remove",
+        about = "Remove a command from the configuration file."
+    )]
+    Remove(RemoveCommand),
+
+    #[clap(
+        name = "order",
+        about = "Set the order commands start in, and the wait between them."
+    )]
+    Order(OrderCommand),
+
+    #[clap(
+        name = "status",
+        about = "Print the processes of a running together instance, e.g. for scripts."
+    )]
+    Status(StatusCommand),
+
+    #[clap(
+        name = "report",
+        about = "Print a report of how the commands of a running together instance have run so far."
+    )]
+    Report(ReportCommand),
+
+    #[clap(
+        name = "ctl",
+        about = "Kill or restart processes of a running together instance by alias, command or id."
+    )]
+    Ctl(CtlCommand),
+
+    #[clap(
+        name = "cleanup",
+        about = "Kill processes left behind by together instances that crashed or were killed."
+    )]
+    Cleanup(CleanupCommand),
 }
 
 #[derive(Debug, clap::Parser)]
@@ -58,7 +212,11 @@ pub struct LoadCommand {
     #[clap(required = true, help = "Configuration file path.")]
     pub path: String,
 
-    #[clap(short, long, help = "Only run the startup commands.")]
+    #[clap(
+        short,
+        long,
+        help = "Only run the startup commands, then exit (non-zero if any failed)."
+    )]
     pub init_only: bool,
 
     #[clap(
@@ -75,11 +233,183 @@ pub struct LoadCommand {
         value_delimiter = ','
     )]
     pub recipes: Option<Vec<String>>,
+
+    #[clap(
+        long,
+        help = "Leave out commands tagged under provided recipe(s), running all other commands if no recipes are given. Use comma to separate multiple recipes.",
+        value_delimiter = ','
+    )]
+    pub exclude_recipes: Option<Vec<String>>,
+
+    #[clap(
+        long,
+        conflicts_with = "recipes",
+        help = "Run only the provided command(s) by alias, command or index, without prompting. Use comma to separate multiple commands.",
+        value_delimiter = ','
+    )]
+    pub only: Option<Vec<String>>,
 }
 
 #[derive(Debug, clap::Parser)]
 pub struct RerunCommand {}
 
+#[derive(Debug, clap::Parser)]
+pub struct StatusCommand {
+    #[clap(
+        short,
+        long,
+        default_value = "json",
+        help = "Format to print the processes in: 'json' or 'yaml'."
+    )]
+    pub format: StatusFormat,
+
+    #[clap(
+        long,
+        help = "Pid of the instance, when several are running outside the current directory."
+    )]
+    pub pid: Option<u32>,
+}
+
+#[derive(Debug, clap::Parser)]
+pub struct ReportCommand {
+    #[clap(
+        short,
+        long,
+        default_value = "markdown",
+        help = "Format of the report: 'markdown' or 'junit' for JUnit XML."
+    )]
+    pub format: crate::report::ReportFormat,
+
+    #[clap(
+        short,
+        long,
+        help = "File to write the report to, instead of printing it."
+    )]
+    pub output: Option<std::path::PathBuf>,
+
+    #[clap(
+        long,
+        help = "Pid of the instance, when several are running outside the current directory."
+    )]
+    pub pid: Option<u32>,
+}
+
+#[derive(Debug, clap::Parser)]
+pub struct CtlCommand {
+    #[clap(required = true, help = "Action to take: 'kill' or 'restart'.")]
+    pub action: crate::control::CtlAction,
+
+    #[clap(
+        required = true,
+        help = "Aliases, commands or ids of the processes to act on."
+    )]
+    pub targets: Vec<String>,
+
+    #[clap(
+        long,
+        help = "Pid of the instance, when several are running outside the current directory."
+    )]
+    pub pid: Option<u32>,
+}
+
+#[derive(Debug, clap::Parser)]
+pub struct CleanupCommand {
+    #[clap(
+        long,
+        help = "Only list the processes left behind, without killing them."
+    )]
+    pub dry_run: bool,
+
+    #[clap(short, long, help = "Kill the processes without asking first.")]
+    pub yes: bool,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum StatusFormat {
+    #[default]
+    Json,
+    Yaml,
+}
+
+impl std::str::FromStr for StatusFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "json" => Ok(Self::Json),
+            "yaml" => Ok(Self::Yaml),
+            _ => Err(format!("unknown status format '{}'", s)),
+        }
+    }
+}
+
+#[derive(Debug, clap::Parser)]
+pub struct AddCommand {
+    #[clap(
+        required = true,
+        help = "Command to add. e.g. 'yarn workspace server dev'"
+    )]
+    pub command: String,
+
+    #[clap(short, long, help = "Alias of the command.")]
+    pub alias: Option<String>,
+
+    #[clap(
+        short,
+        long,
+        help = "Recipe(s) to tag the command under. Use comma to separate multiple recipes.",
+        value_delimiter = ','
+    )]
+    pub recipes: Option<Vec<String>>,
+
+    #[clap(
+        short,
+        long,
+        help = "Rewrite the configuration file without asking first, even if its comments are lost."
+    )]
+    pub yes: bool,
+
+    #[clap(
+        short,
+        long,
+        help = "Configuration file path. Defaults to the configuration file in the current directory."
+    )]
+    pub path: Option<String>,
+}
+
+#[derive(Debug, clap::Parser)]
+pub struct RemoveCommand {
+    #[clap(
+        required = true,
+        help = "Alias, command or index of the command to remove."
+    )]
+    pub command: String,
+
+    #[clap(
+        short,
+        long,
+        help = "Rewrite the configuration file without asking first, even if its comments are lost."
+    )]
+    pub yes: bool,
+
+    #[clap(
+        short,
+        long,
+        help = "Configuration file path. Defaults to the configuration file in the current directory."
+    )]
+    pub path: Option<String>,
+}
+
+#[derive(Debug, clap::Parser)]
+pub struct OrderCommand {
+    #[clap(
+        short,
+        long,
+        help = "Configuration file path. Defaults to the configuration file in the current directory."
+    )]
+    pub path: Option<String>,
+}
+
 #[derive(Debug, Clone, clap::Parser)]
 pub struct RunCommand {
     #[clap(
@@ -109,7 +439,36 @@ pub struct RunCommand {
     #[clap(short, long, help = "Enable raw stdout/stderr output.")]
     pub raw: bool,
 
-    #[clap(short, long, help = "Only run the startup commands.")]
+    #[clap(
+        long,
+        default_value = "interleaved",
+        help = "How process output is scheduled: 'interleaved' prints lines as they arrive, 'sliced' batches each process's output into short time slices, 'blocks' prints each process's output in blocks ended by a blank line or a short pause."
+    )]
+    pub output_mode: crate::output::OutputMode,
+
+    #[clap(
+        long,
+        help = "Run commands in a pseudo-terminal, so they keep colors and interactive output."
+    )]
+    pub pty: bool,
+
+    #[clap(
+        long,
+        help = "Retry starting a command this many times, with backoff, if it fails to start."
+    )]
+    pub spawn_retries: Option<u32>,
+
+    #[clap(
+        long,
+        help = "Milliseconds to wait between starting each command, so they don't all start at once."
+    )]
+    pub stagger_ms: Option<u64>,
+
+    #[clap(
+        short,
+        long,
+        help = "Only run the startup commands, then exit (non-zero if any failed)."
+    )]
     pub init_only: bool,
 
     #[clap(
@@ -126,9 +485,9 @@ impl Terminal {
     pub fn select_multiple<'a, T: std::fmt::Display>(
         prompt: &'a str,
         items: &'a [T],
-    ) -> Vec<&'a T> {
+    ) -> TogetherResult<Vec<&'a T>> {
         if items.is_empty() {
-            return vec![];
+            return Ok(vec![]);
         }
 
         let mut opts_commands = vec![];
@@ -138,107 +497,249 @@ impl Terminal {
             .items(items)
             .defaults(&defaults[..])
             .interact();
-        let selections = multi_select.map_err(map_dialoguer_err).unwrap();
+        let selections = multi_select.map_err(map_dialoguer_err)?;
         for index in selections {
             opts_commands.push(&items[index]);
         }
-        opts_commands
+        Ok(opts_commands)
     }
     pub fn select_single<'a, T: std::fmt::Display>(
         prompt: &'a str,
         items: &'a [T],
-    ) -> Option<&'a T> {
-        if items.is_empty() {
-            return None;
-        }
-
+    ) -> TogetherResult<Option<&'a T>> {
         let index = Self::select_single_index(prompt, items)?;
-        Some(&items[index])
+        Ok(index.map(|index| &items[index]))
     }
     pub fn select_single_index<'a, T: std::fmt::Display>(
         prompt: &'a str,
         items: &'a [T],
-    ) -> Option<usize> {
+    ) -> TogetherResult<Option<usize>> {
         if items.is_empty() {
-            return None;
+            return Ok(None);
         }
 
         let index = dialoguer::Select::with_theme(&ColorfulTheme::default())
             .with_prompt(prompt)
             .items(items)
             .interact_opt()
-            .map_err(map_dialoguer_err)
-            .unwrap()?;
-        Some(index)
+            .map_err(map_dialoguer_err)?;
+        Ok(index)
     }
     pub fn select_ordered<'a, T: std::fmt::Display>(
         prompt: &'a str,
         items: &'a [T],
-    ) -> Option<Vec<&'a T>> {
+    ) -> TogetherResult<Option<Vec<&'a T>>> {
         if items.is_empty() {
-            return None;
+            return Ok(None);
         }
 
-        let mut opts_commands = vec![];
         let sort = dialoguer::Sort::with_theme(&ColorfulTheme::default())
             .with_prompt(prompt)
             .items(items)
             .interact_opt()
+            .map_err(map_dialoguer_err)?;
+        Ok(sort.map(|sort| sort.into_iter().map(|index| &items[index]).collect()))
+    }
+    pub fn confirm(prompt: &str) -> TogetherResult<bool> {
+        dialoguer::Confirm::with_theme(&ColorfulTheme::default())
+            .with_prompt(prompt)
+            .default(false)
+            .interact()
             .map_err(map_dialoguer_err)
-            .unwrap()?;
-        for index in sort {
-            opts_commands.push(&items[index]);
-        }
-        Some(opts_commands)
+    }
+    pub fn input(prompt: &str) -> TogetherResult<Option<String>> {
+        let input = dialoguer::Input::<String>::with_theme(&ColorfulTheme::default())
+            .with_prompt(prompt)
+            .allow_empty(true)
+            .interact_text()
+            .map_err(map_dialoguer_err)?;
+        let input = input.trim();
+        Ok((!input.is_empty()).then(|| input.to_string()))
     }
     pub fn log(message: &str) {
-        // print message with green colorized prefix
-        crate::t_println!("{}[+] {}{}", "\x1b[32m", "\x1b[0m", message);
+        // print message with the theme's prefix, green by default
+        crate::t_println!("{} {}", crate::theme::log_marker(), message);
+        crate::debug_log::console(" INFO", message);
     }
     pub fn log_error(message: &str) {
-        // print message with red colorized prefix
-        crate::t_eprintln!("{}[!] {}{}", "\x1b[31m", "\x1b[0m", message);
+        // print message with the theme's error prefix, red by default
+        crate::t_eprintln!("{} {}", crate::theme::error_marker(), message);
+        crate::debug_log::console("ERROR", message);
     }
 }
 
-fn map_dialoguer_err(err: dialoguer::Error) -> ! {
+/// Formats a duration as a compact human readable string, e.g. "1h 02m", "3m 04s" or "12s"
+pub fn format_duration(duration: std::time::Duration) -> String {
+    let secs = duration.as_secs();
+    match (secs / 3600, secs / 60 % 60, secs % 60) {
+        (0, 0, s) => format!("{}s", s),
+        (0, m, s) => format!("{}m {:02}s", m, s),
+        (h, m, _) => format!("{}h {:02}m", h, m),
+    }
+}
+
+pub fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KB", "MB", "GB", "TB"];
+    let mut value = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", value, UNITS[unit])
+}
+
+/// Parses a size such as `2GB`, `512M` or `1.5 GB`, in the same 1024-based units as
+/// `format_bytes`, or a plain number of bytes
+pub fn parse_bytes(size: &str) -> Result<u64, String> {
+    let invalid = || format!("invalid size '{}', e.g. '512MB' or '2GB'", size);
+    let size = size.trim();
+    let digits = size
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(size.len());
+    let value: f64 = size[..digits].parse().map_err(|_| invalid())?;
+    let unit = size[digits..].trim().to_ascii_uppercase();
+    let power = match unit.trim_end_matches(['B', 'I']) {
+        "" => 0,
+        "K" => 1,
+        "M" => 2,
+        "G" => 3,
+        "T" => 4,
+        _ => return Err(invalid()),
+    };
+    match value * 1024f64.powi(power) {
+        bytes if bytes >= 1.0 => Ok(bytes as u64),
+        _ => Err(invalid()),
+    }
+}
+
+fn map_dialoguer_err(err: dialoguer::Error) -> TogetherError {
     let dialoguer::Error::IO(io) = err;
     match io.kind() {
         std::io::ErrorKind::Interrupted | std::io::ErrorKind::BrokenPipe => {
-            std::process::exit(0);
-        }
-        _ => {
-            panic!("Unexpected error: {}", io);
+            TogetherError::Interrupted
         }
+        _ => io.into(),
     }
 }
 
 pub mod stdout {
-    /// macro for logging like println! but with a carriage return
+    use std::{borrow::Cow, io::IsTerminal, sync::OnceLock};
+
+    use super::ColorChoice;
+
+    static STYLE: OnceLock<Style> = OnceLock::new();
+
+    /// How lines are written to stdout and stderr, depending on whether they are terminals
+    #[derive(Debug, Clone, Copy)]
+    struct Style {
+        stdout: StreamStyle,
+        stderr: StreamStyle,
+    }
+
+    #[derive(Debug, Clone, Copy)]
+    struct StreamStyle {
+        color: bool,
+        /// End lines with a carriage return, for terminals in raw mode
+        crlf: bool,
+    }
+
+    impl StreamStyle {
+        fn new(color: ColorChoice, terminal: bool) -> Self {
+            let color = match color {
+                ColorChoice::Always => true,
+                ColorChoice::Auto => terminal,
+                ColorChoice::Never => false,
+            };
+            Self {
+                color,
+                crlf: terminal,
+            }
+        }
+
+        fn render(&self, args: std::fmt::Arguments) -> String {
+            let mut line = args.to_string();
+            if !self.color {
+                line = strip_ansi(&line).into_owned();
+            }
+            line.push_str(if self.crlf { "\r\n" } else { "\n" });
+            line
+        }
+    }
+
+    /// Sets when output is colored, ignored if output has already been written
+    pub fn init(color: ColorChoice) {
+        _ = STYLE.set(Style {
+            stdout: StreamStyle::new(color, std::io::stdout().is_terminal()),
+            stderr: StreamStyle::new(color, std::io::stderr().is_terminal()),
+        });
+    }
+
+    fn style() -> &'static Style {
+        STYLE.get_or_init(|| {
+            init(ColorChoice::default());
+            *STYLE.get().unwrap()
+        })
+    }
+
+    /// Whether output written to stdout should be colored
+    pub fn color() -> bool {
+        style().stdout.color
+    }
+
+    pub fn print_line(args: std::fmt::Arguments) {
+        print!("{}", style().stdout.render(args));
+    }
+
+    pub fn eprint_line(args: std::fmt::Arguments) {
+        eprint!("{}", style().stderr.render(args));
+    }
+
+    /// Removes ANSI escape sequences such as colors from a line
+    pub fn strip_ansi(line: &str) -> Cow<'_, str> {
+        if !line.contains('\x1b') {
+            return line.into();
+        }
+        let mut stripped = String::with_capacity(line.len());
+        let mut chars = line.chars();
+        while let Some(c) = chars.next() {
+            if c != '\x1b' {
+                stripped.push(c);
+                continue;
+            }
+            // skip a CSI sequence up to and including its final byte, e.g. `\x1b[1;33m`
+            if chars.next() == Some('[') {
+                for c in chars.by_ref() {
+                    if ('@'..='~').contains(&c) {
+                        break;
+                    }
+                }
+            }
+        }
+        stripped.into()
+    }
+
+    /// macro for logging like println! but with a carriage return on terminals, and without
+    /// colors when they are turned off
     #[macro_export]
     macro_rules! t_println {
         () => {
-            ::std::print!("\r\n");
-        };
-        ($fmt:tt) => {
-            ::std::print!(concat!($fmt, "\r\n"));
+            $crate::terminal::stdout::print_line(format_args!(""))
         };
-        ($fmt:tt, $($arg:tt)*) => {
-            ::std::print!(concat!($fmt, "\r\n"), $($arg)*);
+        ($($arg:tt)*) => {
+            $crate::terminal::stdout::print_line(format_args!($($arg)*))
         };
     }
 
-    /// macro for logging like eprintln! but with a carriage return
+    /// macro for logging like eprintln! but with a carriage return on terminals, and without
+    /// colors when they are turned off
     #[macro_export]
     macro_rules! t_eprintln {
         () => {
-            ::std::eprint!("\r\n");
+            $crate::terminal::stdout::eprint_line(format_args!(""))
         };
-        ($fmt:tt) => {
-            ::std::eprint!(concat!($fmt, "\r\n"));
-        };
-        ($fmt:tt, $($arg:tt)*) => {
-            ::std::eprint!(concat!($fmt, "\r\n"), $($arg)*);
+        ($($arg:tt)*) => {
+            $crate::terminal::stdout::eprint_line(format_args!($($arg)*))
         };
     }
 }
@@ -258,3 +759,34 @@ macro_rules! log_err {
         $crate::terminal::Terminal::log_error(&format!($($arg)*));
     };
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_bytes() {
+        let cases = [
+            ("1024", 1024),
+            ("512B", 512),
+            ("1K", 1024),
+            ("2kb", 2048),
+            ("512M", 512 * 1024 * 1024),
+            ("512MiB", 512 * 1024 * 1024),
+            ("2GB", 2 * 1024 * 1024 * 1024),
+            ("1.5 GB", 3 * 512 * 1024 * 1024),
+            ("1T", 1024u64.pow(4)),
+        ];
+        for (input, expected) in cases {
+            assert_eq!(parse_bytes(input), Ok(expected), "{}", input);
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_bytes() {
+        let cases = ["", "GB", "0", "0.5", "-1GB", "2XB", "1.2.3M", "2 G B"];
+        for input in cases {
+            assert!(parse_bytes(input).is_err(), "{}", input);
+        }
+    }
+}
@@ -39,6 +39,12 @@ pub trait TerminalExt {
         sender: &'a manager::ProcessManagerHandle,
         list: &'a [String],
     ) -> TogetherResult<Vec<&'a String>>;
+
+    fn select_multiple_processes<'a>(
+        prompt: &'a str,
+        sender: &'a manager::ProcessManagerHandle,
+        list: &'a [process::ProcessId],
+    ) -> TogetherResult<Vec<&'a process::ProcessId>>;
 }
 
 impl TerminalExt for terminal::Terminal {
@@ -47,7 +53,7 @@ impl TerminalExt for terminal::Terminal {
         _sender: &'a manager::ProcessManagerHandle,
         list: &'a [process::ProcessId],
     ) -> TogetherResult<Option<&'a process::ProcessId>> {
-        let command = terminal::Terminal::select_single(prompt, list);
+        let command = terminal::Terminal::select_single(prompt, list)?;
         Ok(command)
     }
 
@@ -60,11 +66,12 @@ impl TerminalExt for terminal::Terminal {
             log!("No commands available...");
             return Ok(None);
         }
+        let list = deprecated_last(list);
         let commands = list
             .iter()
-            .map(|c| c.alias().unwrap_or(c.as_str()))
+            .map(|c| with_deprecation(c, Cow::from(c.alias().unwrap_or(c.as_str()))))
             .collect::<Vec<_>>();
-        let command = terminal::Terminal::select_single_index(prompt, &commands).map(|index| {
+        let command = terminal::Terminal::select_single_index(prompt, &commands)?.map(|index| {
             let command = list.get(index).unwrap();
             command.as_str()
         });
@@ -81,6 +88,7 @@ impl TerminalExt for terminal::Terminal {
             log!("No commands available...");
             return Ok(None);
         }
+        let list = deprecated_last(list);
         let commands = list
             .iter()
             .map(
@@ -95,8 +103,10 @@ impl TerminalExt for terminal::Terminal {
                     .into(),
                 },
             )
+            .zip(list.iter())
+            .map(|(label, c)| with_deprecation(c, label))
             .collect::<Vec<_>>();
-        let command = terminal::Terminal::select_single_index(prompt, &commands).map(|index| {
+        let command = terminal::Terminal::select_single_index(prompt, &commands)?.map(|index| {
             let command = list.get(index).unwrap();
             command.as_str()
         });
@@ -112,7 +122,7 @@ impl TerminalExt for terminal::Terminal {
             log!("No recipes available...");
             return Ok(None);
         }
-        let command = terminal::Terminal::select_single(prompt, list);
+        let command = terminal::Terminal::select_single(prompt, list)?;
         Ok(command)
     }
 
@@ -121,7 +131,7 @@ impl TerminalExt for terminal::Terminal {
         _sender: &'a manager::ProcessManagerHandle,
         list: &'a [String],
     ) -> TogetherResult<Vec<&'a String>> {
-        let commands = terminal::Terminal::select_multiple(prompt, list);
+        let commands = terminal::Terminal::select_multiple(prompt, list)?;
         if commands.is_empty() {
             log!("No commands selected...");
         }
@@ -137,10 +147,46 @@ impl TerminalExt for terminal::Terminal {
             log!("No recipes available...");
             return Ok(vec![]);
         }
-        let recipes = terminal::Terminal::select_multiple(prompt, list);
+        let recipes = terminal::Terminal::select_multiple(prompt, list)?;
         if recipes.is_empty() {
             log!("No recipes selected...");
         }
         Ok(recipes)
     }
+
+    fn select_multiple_processes<'a>(
+        prompt: &'a str,
+        _sender: &'a manager::ProcessManagerHandle,
+        list: &'a [process::ProcessId],
+    ) -> TogetherResult<Vec<&'a process::ProcessId>> {
+        if list.is_empty() {
+            log!("No running commands...");
+            return Ok(vec![]);
+        }
+        let processes = terminal::Terminal::select_multiple(prompt, list)?;
+        if processes.is_empty() {
+            log!("No commands selected...");
+        }
+        Ok(processes)
+    }
+}
+
+/// Orders commands so that deprecated commands are listed last
+fn deprecated_last(
+    list: &[config::commands::CommandConfig],
+) -> Vec<&config::commands::CommandConfig> {
+    let mut list: Vec<_> = list.iter().collect();
+    list.sort_by_key(|c| c.deprecated().is_some());
+    list
+}
+
+fn with_deprecation<'a>(
+    command: &config::commands::CommandConfig,
+    label: Cow<'a, str>,
+) -> Cow<'a, str> {
+    match command.deprecated() {
+        // format: "command (deprecated)" with gray color for parentheses
+        Some(_) => format!("{} \x1b[90m(deprecated)\x1b[0m", label).into(),
+        None => label,
+    }
 }
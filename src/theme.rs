@@ -0,0 +1,360 @@
+use std::{collections::HashMap, sync::OnceLock};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{config::commands::CommandConfig, process::ProcessId, terminal};
+
+static THEME: OnceLock<ActiveTheme> = OnceLock::new();
+
+/// Names of the 8 standard terminal colors, in the order of their escape codes
+const COLOR_NAMES: [&str; 8] = [
+    "black", "red", "green", "yellow", "blue", "magenta", "cyan", "white",
+];
+
+/// Colors and symbols of together's own messages, and the prefix of each output line, from the
+/// `theme` section of the configuration file
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Theme {
+    /// Marks together's messages, `[+]` by default
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub log_symbol: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub log_color: Option<Color>,
+    /// Marks together's error messages, `[!]` by default
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error_symbol: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error_color: Option<Color>,
+    /// Written before each line of output, `{id}:` by default
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prefix: Option<PrefixFormat>,
+    /// Colors of the prefix, taken in turn by each process
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prefix_colors: Option<Vec<Color>>,
+}
+
+/// A color and style, by name such as `bright_blue`, as a 256 color number, or as `#rrggbb`,
+/// optionally with `bold` or `underline`, e.g. `bold yellow`. `none` leaves text uncolored.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Color {
+    name: String,
+    /// The SGR parameters of the escape sequence, empty for `none`
+    sgr: String,
+}
+
+impl Color {
+    fn named(name: &str, sgr: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            sgr: sgr.to_string(),
+        }
+    }
+
+    fn paint(&self, text: &str) -> String {
+        match self.sgr.is_empty() {
+            true => text.to_string(),
+            false => format!("\x1b[{}m{}\x1b[0m", self.sgr, text),
+        }
+    }
+}
+
+impl std::str::FromStr for Color {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut codes = vec![];
+        for word in s.split_whitespace() {
+            let word = word.to_ascii_lowercase();
+            let base = |name: &str| COLOR_NAMES.iter().position(|n| *n == name);
+            let bright = word.strip_prefix("bright_").and_then(base);
+            let code = match word.as_str() {
+                "none" => continue,
+                "bold" => "1".to_string(),
+                "dim" => "2".to_string(),
+                "italic" => "3".to_string(),
+                "underline" => "4".to_string(),
+                "gray" | "grey" => "90".to_string(),
+                name if base(name).is_some() => (30 + base(name).unwrap()).to_string(),
+                _ if bright.is_some() => (90 + bright.unwrap()).to_string(),
+                number if number.parse::<u8>().is_ok() => format!("38;5;{}", number),
+                hex if hex.starts_with('#') && hex.len() == 7 => {
+                    let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16);
+                    match (channel(1), channel(3), channel(5)) {
+                        (Ok(r), Ok(g), Ok(b)) => format!("38;2;{};{};{}", r, g, b),
+                        _ => return Err(format!("invalid color '{}'", s)),
+                    }
+                }
+                _ => {
+                    return Err(format!(
+                        "invalid color '{}', e.g. 'cyan', 'bold bright_red', '208' or '#ff8800'",
+                        s
+                    ))
+                }
+            };
+            codes.push(code);
+        }
+        Ok(Self {
+            name: s.to_string(),
+            sgr: codes.join(";"),
+        })
+    }
+}
+
+impl TryFrom<String> for Color {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl From<Color> for String {
+    fn from(color: Color) -> Self {
+        color.name
+    }
+}
+
+/// A format string for the prefix of output lines, with `{id}`, `{alias}` (the command when it
+/// has no alias) and `{command}` replaced, padded to a width with e.g. `{alias:>8}`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct PrefixFormat {
+    format: String,
+    segments: Vec<Segment>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Segment {
+    Text(String),
+    Field {
+        field: Field,
+        align: Align,
+        width: usize,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Field {
+    Id,
+    Alias,
+    Command,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Align {
+    Left,
+    Right,
+    Center,
+}
+
+impl PrefixFormat {
+    fn render(&self, id: &ProcessId, alias: Option<&str>) -> String {
+        let mut prefix = String::new();
+        for segment in &self.segments {
+            let (field, align, width) = match segment {
+                Segment::Text(text) => {
+                    prefix.push_str(text);
+                    continue;
+                }
+                Segment::Field {
+                    field,
+                    align,
+                    width,
+                } => (field, *align, *width),
+            };
+            let value = match field {
+                Field::Id => id.index().to_string(),
+                Field::Alias => alias.unwrap_or(id.command()).to_string(),
+                Field::Command => id.command().to_string(),
+            };
+            prefix.push_str(&match align {
+                Align::Left => format!("{:<width$}", value),
+                Align::Right => format!("{:>width$}", value),
+                Align::Center => format!("{:^width$}", value),
+            });
+        }
+        prefix
+    }
+}
+
+impl Default for PrefixFormat {
+    fn default() -> Self {
+        "{id}:".parse().unwrap()
+    }
+}
+
+impl std::str::FromStr for PrefixFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut segments = vec![];
+        let mut text = String::new();
+        let mut chars = s.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    text.push('{');
+                }
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    text.push('}');
+                }
+                '{' => {
+                    let placeholder: String = chars.by_ref().take_while(|c| *c != '}').collect();
+                    if !text.is_empty() {
+                        segments.push(Segment::Text(std::mem::take(&mut text)));
+                    }
+                    segments.push(
+                        parse_placeholder(&placeholder)
+                            .map_err(|e| format!("invalid prefix '{}': {}", s, e))?,
+                    );
+                }
+                '}' => return Err(format!("invalid prefix '{}': unmatched '}}'", s)),
+                c => text.push(c),
+            }
+        }
+        if !text.is_empty() {
+            segments.push(Segment::Text(text));
+        }
+        Ok(Self {
+            format: s.to_string(),
+            segments,
+        })
+    }
+}
+
+fn parse_placeholder(placeholder: &str) -> Result<Segment, String> {
+    let (name, spec) = placeholder.split_once(':').unwrap_or((placeholder, ""));
+    let field = match name.trim() {
+        "id" => Field::Id,
+        "alias" => Field::Alias,
+        "command" => Field::Command,
+        name => {
+            return Err(format!(
+                "unknown field '{{{}}}', expected {{id}}, {{alias}} or {{command}}",
+                name
+            ))
+        }
+    };
+    let (align, width) = match spec.chars().next() {
+        Some('<') => (Align::Left, &spec[1..]),
+        Some('>') => (Align::Right, &spec[1..]),
+        Some('^') => (Align::Center, &spec[1..]),
+        _ => (Align::Left, spec),
+    };
+    let width = match width {
+        "" => 0,
+        width => width
+            .parse()
+            .map_err(|_| format!("invalid width '{}' in '{{{}}}'", width, placeholder))?,
+    };
+    Ok(Segment::Field {
+        field,
+        align,
+        width,
+    })
+}
+
+impl TryFrom<String> for PrefixFormat {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl From<PrefixFormat> for String {
+    fn from(format: PrefixFormat) -> Self {
+        format.format
+    }
+}
+
+/// The theme in use, with the aliases of commands to fill in prefixes
+struct ActiveTheme {
+    log_symbol: String,
+    log_color: Color,
+    error_symbol: String,
+    error_color: Color,
+    prefix: PrefixFormat,
+    prefix_colors: Vec<Color>,
+    aliases: HashMap<String, String>,
+}
+
+impl ActiveTheme {
+    fn new(theme: Theme, commands: &[CommandConfig]) -> Self {
+        Self {
+            log_symbol: theme.log_symbol.unwrap_or_else(|| "[+]".to_string()),
+            log_color: theme
+                .log_color
+                .unwrap_or_else(|| Color::named("green", "32")),
+            error_symbol: theme.error_symbol.unwrap_or_else(|| "[!]".to_string()),
+            error_color: theme
+                .error_color
+                .unwrap_or_else(|| Color::named("red", "31")),
+            prefix: theme.prefix.unwrap_or_default(),
+            prefix_colors: theme.prefix_colors.unwrap_or_default(),
+            aliases: commands
+                .iter()
+                .filter_map(|c| Some((c.as_str().to_string(), c.alias()?.to_string())))
+                .collect(),
+        }
+    }
+}
+
+/// Sets the theme used for all output, only the first call has an effect
+pub fn init(theme: Option<Theme>, commands: &[CommandConfig]) {
+    _ = THEME.set(ActiveTheme::new(theme.unwrap_or_default(), commands));
+}
+
+fn active() -> &'static ActiveTheme {
+    THEME.get_or_init(|| ActiveTheme::new(Theme::default(), &[]))
+}
+
+/// Marks together's own messages, e.g. a green `[+]`
+pub fn log_marker() -> String {
+    let theme = active();
+    theme.log_color.paint(&theme.log_symbol)
+}
+
+/// Marks together's own error messages, e.g. a red `[!]`
+pub fn error_marker() -> String {
+    let theme = active();
+    theme.error_color.paint(&theme.error_symbol)
+}
+
+/// Details that are less important than the text around them, e.g. the state of a process
+pub fn muted(text: &str) -> String {
+    Color::named("gray", "90").paint(text)
+}
+
+/// Something that needs attention, e.g. a command that keeps crashing
+pub fn alert(text: &str) -> String {
+    Color::named("bold red", "1;31").paint(text)
+}
+
+/// A message that should stand out from the output around it, e.g. a crash loop warning
+pub fn emphasis(text: &str) -> String {
+    Color::named("bold", "1").paint(text)
+}
+
+/// A group name, colored by the position of the group so each group stands out
+pub fn group(name: &str, position: usize) -> String {
+    const COLORS: [&str; 6] = ["36", "35", "33", "34", "32", "31"];
+    let sgr = format!("1;{}", COLORS[position % COLORS.len()]);
+    Color::named(name, &sgr).paint(name)
+}
+
+/// The prefix of a line of output from a process
+pub fn prefix(id: &ProcessId) -> String {
+    let theme = active();
+    let alias = theme.aliases.get(id.command()).map(String::as_str);
+    let prefix = theme.prefix.render(id, alias);
+    // command output is printed as is, so colors aren't stripped from it later
+    match theme.prefix_colors.as_slice() {
+        _ if !terminal::stdout::color() => prefix,
+        [] => prefix,
+        colors => colors[id.index() as usize % colors.len()].paint(&prefix),
+    }
+}
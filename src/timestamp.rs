@@ -0,0 +1,58 @@
+use std::{sync::OnceLock, time::SystemTime};
+
+use chrono::format::{Item, StrftimeItems};
+
+/// RFC 3339, e.g. "2024-02-01T14:03:09+00:00"
+pub const DEFAULT_FORMAT: &str = "%Y-%m-%dT%H:%M:%S%:z";
+
+static FORMAT: OnceLock<TimestampFormat> = OnceLock::new();
+
+/// A strftime format and time zone shared by every place timestamps are written,
+/// so console output and log files can be correlated line by line.
+#[derive(Debug, Clone)]
+pub struct TimestampFormat {
+    format: String,
+    utc: bool,
+}
+
+impl TimestampFormat {
+    pub fn new(format: Option<&str>, utc: bool) -> Result<Self, String> {
+        let format = format.unwrap_or(DEFAULT_FORMAT);
+        if StrftimeItems::new(format).any(|item| matches!(item, Item::Error)) {
+            return Err(format!("invalid strftime format '{}'", format));
+        }
+        Ok(Self {
+            format: format.to_string(),
+            utc,
+        })
+    }
+
+    pub fn format(&self, time: SystemTime) -> String {
+        let time = chrono::DateTime::<chrono::Utc>::from(time);
+        match self.utc {
+            true => time.format(&self.format).to_string(),
+            false => time
+                .with_timezone(&chrono::Local)
+                .format(&self.format)
+                .to_string(),
+        }
+    }
+}
+
+impl Default for TimestampFormat {
+    fn default() -> Self {
+        Self {
+            format: DEFAULT_FORMAT.to_string(),
+            utc: false,
+        }
+    }
+}
+
+/// Sets the format used for all timestamps, only the first call has an effect
+pub fn init(format: TimestampFormat) {
+    _ = FORMAT.set(format);
+}
+
+pub fn format(time: SystemTime) -> String {
+    FORMAT.get_or_init(TimestampFormat::default).format(time)
+}
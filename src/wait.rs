@@ -0,0 +1,88 @@
+use std::{
+    io::{BufRead, BufReader, Write},
+    net::{TcpStream, ToSocketAddrs},
+    path::Path,
+    sync::atomic::{AtomicBool, Ordering},
+    time::Duration,
+};
+
+use crate::config::commands::WaitFor;
+
+/// How often a condition is checked until it is met
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+const CONNECT_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Blocks until the condition is met, returning false if it was cancelled first
+pub fn wait_until(condition: &WaitFor, cwd: Option<&str>, cancelled: &AtomicBool) -> bool {
+    while !cancelled.load(Ordering::Relaxed) {
+        if is_met(condition, cwd) {
+            return true;
+        }
+        std::thread::sleep(POLL_INTERVAL);
+    }
+    false
+}
+
+pub fn is_met(condition: &WaitFor, cwd: Option<&str>) -> bool {
+    match condition {
+        WaitFor::Port(port) => connect(&format!("localhost:{}", port)).is_some(),
+        WaitFor::Url(url) => url_ok(url),
+        WaitFor::File(file) => match cwd {
+            Some(cwd) => Path::new(cwd).join(file).exists(),
+            None => Path::new(file).exists(),
+        },
+    }
+}
+
+fn connect(address: &str) -> Option<TcpStream> {
+    address
+        .to_socket_addrs()
+        .ok()?
+        .find_map(|addr| TcpStream::connect_timeout(&addr, CONNECT_TIMEOUT).ok())
+}
+
+/// Sends a GET request to an http URL, checking for a 2xx status. https URLs are only
+/// checked for accepting connections.
+fn url_ok(url: &str) -> bool {
+    let (https, rest) = match url.split_once("://") {
+        Some((scheme, rest)) => (scheme.eq_ignore_ascii_case("https"), rest),
+        None => (false, url),
+    };
+    let (authority, path) = match rest.find('/') {
+        Some(i) => rest.split_at(i),
+        None => (rest, "/"),
+    };
+    let has_port = authority
+        .rsplit_once(':')
+        .is_some_and(|(_, port)| port.parse::<u16>().is_ok());
+    let address = match (has_port, https) {
+        (true, _) => authority.to_string(),
+        (false, true) => format!("{}:443", authority),
+        (false, false) => format!("{}:80", authority),
+    };
+
+    let Some(mut stream) = connect(&address) else {
+        return false;
+    };
+    if https {
+        return true;
+    }
+    let request = format!(
+        "GET {} HTTP/1.0\r\nHost: {}\r\nConnection: close\r\n\r\n",
+        path, authority
+    );
+    if stream.set_read_timeout(Some(CONNECT_TIMEOUT)).is_err()
+        || stream.write_all(request.as_bytes()).is_err()
+    {
+        return false;
+    }
+    let mut status_line = String::new();
+    if BufReader::new(stream).read_line(&mut status_line).is_err() {
+        return false;
+    }
+    status_line
+        .split_whitespace()
+        .nth(1)
+        .and_then(|status| status.parse::<u16>().ok())
+        .is_some_and(|status| (200..300).contains(&status))
+}
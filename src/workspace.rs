@@ -0,0 +1,132 @@
+use crate::{
+    config::{
+        self,
+        commands::{CommandConfig, CommandDetails, ConfigFileStartOptions},
+        StartTogetherOptions, TogetherConfigFile,
+    },
+    errors::{ConfigError, TogetherError, TogetherResult},
+    log, terminal,
+};
+
+#[derive(Debug, serde::Deserialize)]
+struct CargoMetadata {
+    packages: Vec<CargoPackage>,
+    workspace_members: Vec<String>,
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct CargoPackage {
+    id: String,
+    name: String,
+    targets: Vec<CargoTarget>,
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct CargoTarget {
+    name: String,
+    kind: Vec<String>,
+}
+
+/// Builds start options for `cargo together`: an existing together configuration is used as-is,
+/// otherwise every binary target in the current cargo workspace is offered as a command.
+pub fn to_start_options(
+    command_args: terminal::TogetherArgs,
+) -> TogetherResult<StartTogetherOptions> {
+    if command_args.command.is_some() || (!command_args.no_config && config::exists()) {
+        return config::to_start_options(command_args);
+    }
+
+    let output_filter = config::output_filter(&command_args)
+        .map_err(|e| ConfigError::new(format!("Invalid output filter: {}", e)))?;
+    let supervise = config::supervise_options(&command_args);
+    let commands = binary_commands()
+        .map_err(|e| ConfigError::new(format!("Failed to read cargo workspace metadata: {}", e)))?;
+    if commands.is_empty() {
+        return Err(ConfigError::new("No binary targets found in the cargo workspace").into());
+    }
+    log!(
+        "Found {} binary targets in the cargo workspace",
+        commands.len()
+    );
+
+    let mut start_options = ConfigFileStartOptions {
+        commands,
+        all: false,
+        exit_on_error: false,
+        quit_on_completion: false,
+        quiet_startup: command_args.quiet_startup,
+        raw: false,
+        init_only: command_args.init_only,
+        no_init: command_args.no_init,
+        report: command_args.report,
+        ..Default::default()
+    };
+
+    config::validate_working_directories(
+        command_args.working_directory.as_deref(),
+        &start_options,
+    )?;
+    let only = config::only_commands(&start_options, command_args.only)?;
+    let timestamp_format = config::timestamp_format(
+        &mut start_options,
+        command_args.timestamps,
+        command_args.timestamp_format,
+        command_args.utc,
+    )?;
+
+    let config = TogetherConfigFile::new(start_options);
+    Ok(StartTogetherOptions {
+        command_sources: (0..config.start_options.commands.len()).collect(),
+        source_config: config.clone(),
+        config,
+        working_directory: command_args.working_directory,
+        active_recipes: command_args.recipes,
+        exclude_recipes: command_args.exclude_recipes,
+        only,
+        config_path: None,
+        output_filter,
+        supervise,
+        timestamp_format,
+    })
+}
+
+/// Lists a `cargo run` command for each binary target of the workspace members,
+/// tagged with the package name as a recipe.
+pub fn binary_commands() -> TogetherResult<Vec<CommandConfig>> {
+    let output = std::process::Command::new("cargo")
+        .args(["metadata", "--format-version", "1", "--no-deps"])
+        .stderr(std::process::Stdio::inherit())
+        .output()?;
+    if !output.status.success() {
+        return Err(TogetherError::DynError(
+            format!("cargo metadata exited with {}", output.status).into(),
+        ));
+    }
+    let metadata: CargoMetadata = serde_json::from_slice(&output.stdout)?;
+
+    let mut commands = vec![];
+    let members = metadata
+        .packages
+        .iter()
+        .filter(|p| metadata.workspace_members.contains(&p.id));
+    for package in members {
+        let bins: Vec<_> = package
+            .targets
+            .iter()
+            .filter(|t| t.kind.iter().any(|k| k == "bin"))
+            .collect();
+        for bin in &bins {
+            let command = match bins.len() {
+                1 => format!("cargo run -p {}", package.name),
+                _ => format!("cargo run -p {} --bin {}", package.name, bin.name),
+            };
+            commands.push(CommandConfig::Detailed(Box::new(CommandDetails {
+                command,
+                alias: Some(bin.name.clone()),
+                recipes: Some(vec![package.name.clone()]),
+                ..Default::default()
+            })));
+        }
+    }
+    Ok(commands)
+}
@@ -0,0 +1,140 @@
+use std::{borrow::Cow, process::Command, thread::JoinHandle};
+
+use crate::{
+    log_err,
+    process::{ProcessId, ProcessSignal},
+    remote::{local_quote, posix_quote, remote_path},
+};
+
+/// Runs a command in WSL, `true` for the default distribution or the name of one
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(untagged)]
+pub enum Wsl {
+    Enabled(bool),
+    Distro(String),
+}
+
+impl Wsl {
+    pub fn is_enabled(&self) -> bool {
+        !matches!(self, Wsl::Enabled(false))
+    }
+
+    pub fn distro(&self) -> Option<&str> {
+        match self {
+            Wsl::Enabled(_) => None,
+            Wsl::Distro(distro) => Some(distro),
+        }
+    }
+}
+
+/// A command running in WSL, which is signalled through `wsl --exec kill` as console events
+/// sent to `wsl.exe` don't reach it
+#[derive(Debug, Clone)]
+pub struct WslProcess {
+    distro: Option<String>,
+    user: Option<String>,
+    /// Name of the pid file the command's pid is recorded in
+    name: String,
+}
+
+impl WslProcess {
+    pub fn new(wsl: &Wsl, user: Option<&str>, id: &ProcessId) -> Self {
+        Self {
+            distro: wsl.distro().map(str::to_string),
+            user: user.map(str::to_string),
+            name: format!("together-{}-{}", std::process::id(), id.index()),
+        }
+    }
+
+    /// The `wsl.exe` command that runs `command`, in `cwd` when given as a windows or linux
+    /// path
+    pub fn command(&self, command: &str, cwd: Option<&str>) -> String {
+        let mut wsl = self.wsl();
+        // the pid is recorded so the command can be signalled from another `wsl --exec`
+        let mut script = format!(
+            "echo $$ > {} && exec sh -c {}",
+            self.pid_file(),
+            posix_quote(command)
+        );
+        if let Some(cwd) = cwd {
+            script = format!("cd {} || exit; {}", remote_path(&linux_path(cwd)), script);
+        }
+        wsl.extend(["--exec", "sh", "-c"].map(str::to_string));
+        wsl.push(local_quote(&script));
+        wsl.join(" ")
+    }
+
+    /// Sends the signal to the command in WSL, from another thread
+    pub fn signal(&self, signal: Option<&ProcessSignal>) -> JoinHandle<()> {
+        let signal = match signal {
+            Some(ProcessSignal::SIGINT | ProcessSignal::SIGBREAK) => "INT",
+            Some(ProcessSignal::SIGKILL) => "KILL",
+            Some(ProcessSignal::SIGTERM) | None => "TERM",
+        };
+        let kill = format!("kill -s {} \"$(cat {})\"", signal, self.pid_file());
+        let mut wsl = Command::new("wsl.exe");
+        if let Some(distro) = &self.distro {
+            wsl.args(["-d", distro]);
+        }
+        if let Some(user) = &self.user {
+            wsl.args(["-u", user]);
+        }
+        wsl.args(["--exec", "sh", "-c", &kill])
+            .stdout(std::process::Stdio::null())
+            .stderr(std::process::Stdio::null());
+        let name = self.name.clone();
+        std::thread::spawn(move || match wsl.status() {
+            Ok(_) => {}
+            Err(e) => {
+                log_err!("Failed to signal {} in WSL: {}", name, e);
+            }
+        })
+    }
+
+    fn wsl(&self) -> Vec<String> {
+        let mut wsl = vec!["wsl.exe".to_string()];
+        if let Some(distro) = &self.distro {
+            wsl.extend(["-d".to_string(), local_quote(distro)]);
+        }
+        if let Some(user) = &self.user {
+            wsl.extend(["-u".to_string(), local_quote(user)]);
+        }
+        wsl
+    }
+
+    fn pid_file(&self) -> String {
+        format!("/tmp/{}.pid", self.name)
+    }
+}
+
+/// `WSLENV` listing the variables to share with WSL, on top of any already shared
+pub fn shared_env(env: &[(String, String)]) -> Option<(String, String)> {
+    if env.is_empty() {
+        return None;
+    }
+    let current = std::env::var("WSLENV").ok().filter(|v| !v.is_empty());
+    let names = env.iter().map(|(name, _)| format!("{}/u", name));
+    let shared: Vec<_> = current.into_iter().chain(names).collect();
+    Some(("WSLENV".to_string(), shared.join(":")))
+}
+
+/// Translates a windows path to where WSL sees it, e.g. `C:\src\app` to `/mnt/c/src/app`
+/// and `\\wsl$\Ubuntu\home\me` to `/home/me`. Linux paths are left as they are.
+pub fn linux_path(path: &str) -> Cow<'_, str> {
+    let unc = ["\\\\wsl$\\", "\\\\wsl.localhost\\"]
+        .iter()
+        .find_map(|prefix| path.strip_prefix(prefix));
+    if let Some(path) = unc {
+        // the first component is the distribution
+        let path = path.split_once('\\').map_or("", |(_, path)| path);
+        return format!("/{}", path.replace('\\', "/")).into();
+    }
+    let mut chars = path.chars();
+    match (chars.next(), chars.next(), chars.next()) {
+        (Some(drive), Some(':'), None | Some('\\' | '/')) if drive.is_ascii_alphabetic() => {
+            let rest = path[2..].replace('\\', "/");
+            format!("/mnt/{}{}", drive.to_ascii_lowercase(), rest).into()
+        }
+        _ => path.into(),
+    }
+}